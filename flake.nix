@@ -36,6 +36,12 @@
             gettext
             parted
             e2fsprogs
+            btrfs-progs
+            xfsprogs
+            exfatprogs
+            ntfs3g
+            dosfstools
+            cryptsetup
             util-linux
           ];
 
@@ -0,0 +1,134 @@
+use crate::utils::nix_parser::{self, NixParseError};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Failures a front-end can explain to the user
+///
+/// Functions of this crate return `anyhow::Result`; when the cause is one of
+/// these it can be recovered with [`NixDiskError::find`].
+#[derive(Debug, Error)]
+pub enum NixDiskError {
+    #[error("{} not found", path.display())]
+    ConfigNotFound { path: PathBuf },
+
+    #[error("{}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: NixParseError,
+    },
+
+    #[error("Permission denied: {}", path.display())]
+    PermissionDenied { path: PathBuf },
+
+    #[error("{tool} is not installed")]
+    ToolMissing { tool: String },
+
+    #[error("{} was modified by another program", path.display())]
+    ModifiedExternally { path: PathBuf },
+
+    /// The configuration generated for `path` failed the checks run before
+    /// writing it
+    #[error("{} would be invalid: {reason}", path.display())]
+    CheckFailed { path: PathBuf, reason: String },
+
+    #[error("{} is in use: {reason}", device.display())]
+    DeviceBusy { device: PathBuf, reason: String },
+
+    #[error("Not authorized to {action}")]
+    NotAuthorized { action: String },
+
+    #[error("nixos-rebuild failed{}", code.map(|c| format!(" with exit code {}", c)).unwrap_or_default())]
+    RebuildFailed { code: Option<i32> },
+
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl NixDiskError {
+    /// Classify an I/O error on `path`
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied {
+                path: path.to_path_buf(),
+            },
+            _ => Self::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    /// Classify an error spawning the external program `tool`
+    pub fn tool(tool: &str, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => Self::ToolMissing {
+                tool: tool.to_string(),
+            },
+            _ => Self::Io {
+                path: PathBuf::from(tool),
+                source,
+            },
+        }
+    }
+
+    /// The typed cause of an error, if any
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.chain().find_map(|cause| cause.downcast_ref::<Self>())
+    }
+}
+
+/// Read a configuration file, reporting a missing file or missing rights as
+/// such
+pub fn read_file(path: &Path) -> Result<String, NixDiskError> {
+    std::fs::read_to_string(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => NixDiskError::ConfigNotFound {
+            path: path.to_path_buf(),
+        },
+        _ => NixDiskError::io(path, e),
+    })
+}
+
+/// Read a Nix file and check that it parses
+pub fn read_nix_file(path: &Path) -> Result<String, NixDiskError> {
+    let content = read_file(path)?;
+    nix_parser::parse(&content).map_err(|source| NixDiskError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_nix_file_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let missing = dir.path().join("missing.nix");
+        let error = read_nix_file(&missing).unwrap_err();
+        assert!(matches!(error, NixDiskError::ConfigNotFound { ref path } if *path == missing));
+        // Only configuration files are reported as missing configuration
+        let error = NixDiskError::io(&missing, io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(error, NixDiskError::Io { .. }));
+
+        let broken = dir.path().join("broken.nix");
+        std::fs::write(&broken, "{\n  fileSystems.\"/data\".device = ;\n}\n").unwrap();
+        let error = anyhow::Error::from(read_nix_file(&broken).unwrap_err())
+            .context("Failed to load the configuration");
+        match NixDiskError::find(&error) {
+            Some(NixDiskError::Parse { path, source }) => {
+                assert_eq!(path, &broken);
+                assert_eq!(source.line, 2);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
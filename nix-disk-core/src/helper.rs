@@ -0,0 +1,186 @@
+//! D-Bus protocol between nix-disk and its privileged helper
+//!
+//! `nix-disk-helper` runs as root on the system bus and performs the few
+//! operations that need it, each guarded by its own polkit action. Errors
+//! travel as D-Bus error names so the window can still tell them apart.
+
+use crate::error::NixDiskError;
+use crate::utils::nix_parser::NixParseError;
+use std::path::PathBuf;
+
+pub const BUS_NAME: &str = "org.glfos.nixdiskmanager.Helper";
+pub const OBJECT_PATH: &str = "/org/glfos/nixdiskmanager/Helper";
+pub const INTERFACE: &str = "org.glfos.nixdiskmanager.Helper";
+pub const ERROR_PREFIX: &str = "org.glfos.nixdiskmanager.Error";
+
+/// The `layout` argument of `Format` is a
+/// [`DiskLayout`](crate::utils::layout::DiskLayout) serialized as JSON, and
+/// `passphrase` opens its encrypted partitions (empty when there are none).
+///
+/// Signal carrying one line of output of a running operation:
+/// `(s operation, s line)`
+pub const OUTPUT_SIGNAL: &str = "Output";
+
+/// polkit action of each method
+pub const ACTION_WRITE_CONFIG: &str = "org.glfos.nixdiskmanager.write-config";
+pub const ACTION_REBUILD: &str = "org.glfos.nixdiskmanager.rebuild";
+pub const ACTION_FORMAT: &str = "org.glfos.nixdiskmanager.format";
+
+pub const INTROSPECTION_XML: &str = r#"<node>
+  <interface name="org.glfos.nixdiskmanager.Helper">
+    <method name="WriteConfig">
+      <arg name="path" type="s" direction="in"/>
+      <arg name="content" type="s" direction="in"/>
+      <arg name="action" type="s" direction="in"/>
+      <arg name="expected_hash" type="s" direction="in"/>
+    </method>
+    <method name="Rebuild">
+      <arg name="flake_ref" type="s" direction="in"/>
+      <arg name="exit_code" type="i" direction="out"/>
+    </method>
+    <method name="CancelRebuild"/>
+    <method name="Format">
+      <arg name="disk" type="s" direction="in"/>
+      <arg name="layout" type="s" direction="in"/>
+      <arg name="passphrase" type="s" direction="in"/>
+      <arg name="partitions" type="as" direction="out"/>
+    </method>
+    <signal name="Output">
+      <arg name="operation" type="s"/>
+      <arg name="line" type="s"/>
+    </signal>
+  </interface>
+</node>"#;
+
+/// D-Bus error name and message for an error of the helper
+pub fn encode_error(error: &anyhow::Error) -> (String, String) {
+    let (kind, message) = match NixDiskError::find(error) {
+        Some(NixDiskError::ConfigNotFound { path }) => {
+            ("ConfigNotFound", path.display().to_string())
+        }
+        Some(NixDiskError::Parse { path, source }) => (
+            "Parse",
+            format!(
+                "{}\n{}\n{}\n{}",
+                path.display(),
+                source.line,
+                source.column,
+                source.message
+            ),
+        ),
+        Some(NixDiskError::PermissionDenied { path }) => {
+            ("PermissionDenied", path.display().to_string())
+        }
+        Some(NixDiskError::ToolMissing { tool }) => ("ToolMissing", tool.clone()),
+        Some(NixDiskError::ModifiedExternally { path }) => {
+            ("ModifiedExternally", path.display().to_string())
+        }
+        Some(NixDiskError::CheckFailed { path, reason }) => {
+            ("CheckFailed", format!("{}\n{}", path.display(), reason))
+        }
+        Some(NixDiskError::DeviceBusy { device, reason }) => {
+            ("DeviceBusy", format!("{}\n{}", device.display(), reason))
+        }
+        Some(NixDiskError::NotAuthorized { action }) => ("NotAuthorized", action.clone()),
+        Some(NixDiskError::RebuildFailed { code }) => (
+            "RebuildFailed",
+            code.map(|c| c.to_string()).unwrap_or_default(),
+        ),
+        Some(NixDiskError::Io { .. }) | None => ("Failed", format!("{:#}", error)),
+    };
+    (format!("{}.{}", ERROR_PREFIX, kind), message)
+}
+
+/// Rebuild the error behind a failed call, from the message of the
+/// `GDBus.Error:<name>: <message>` error gio reports
+pub fn decode_error(error_message: &str) -> anyhow::Error {
+    let Some((name, message)) = error_message
+        .strip_prefix("GDBus.Error:")
+        .and_then(|rest| rest.split_once(": "))
+    else {
+        return anyhow::anyhow!("{}", error_message);
+    };
+
+    if name == "org.freedesktop.DBus.Error.ServiceUnknown" {
+        return NixDiskError::ToolMissing {
+            tool: "nix-disk-helper".to_string(),
+        }
+        .into();
+    }
+
+    let mut fields = message.splitn(4, '\n');
+    let mut field = || fields.next().unwrap_or_default().to_string();
+    let typed = match name
+        .strip_prefix(ERROR_PREFIX)
+        .and_then(|n| n.strip_prefix('.'))
+    {
+        Some("ConfigNotFound") => NixDiskError::ConfigNotFound {
+            path: PathBuf::from(field()),
+        },
+        Some("Parse") => {
+            let path = PathBuf::from(field());
+            let line = field().parse().unwrap_or(0);
+            let column = field().parse().unwrap_or(0);
+            NixDiskError::Parse {
+                path,
+                source: NixParseError {
+                    message: field(),
+                    offset: 0,
+                    line,
+                    column,
+                },
+            }
+        }
+        Some("PermissionDenied") => NixDiskError::PermissionDenied {
+            path: PathBuf::from(field()),
+        },
+        Some("ToolMissing") => NixDiskError::ToolMissing { tool: field() },
+        Some("ModifiedExternally") => NixDiskError::ModifiedExternally {
+            path: PathBuf::from(field()),
+        },
+        Some("CheckFailed") => NixDiskError::CheckFailed {
+            path: PathBuf::from(field()),
+            reason: field(),
+        },
+        Some("DeviceBusy") => NixDiskError::DeviceBusy {
+            device: PathBuf::from(field()),
+            reason: field(),
+        },
+        Some("NotAuthorized") => NixDiskError::NotAuthorized { action: field() },
+        Some("RebuildFailed") => NixDiskError::RebuildFailed {
+            code: field().parse().ok(),
+        },
+        _ => return anyhow::anyhow!("{}", message),
+    };
+    typed.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_round_trip() {
+        let error = anyhow::Error::from(NixDiskError::DeviceBusy {
+            device: PathBuf::from("/dev/sdb"),
+            reason: "/dev/sdb1 is mounted on /mnt/data".to_string(),
+        });
+        let (name, message) = encode_error(&error);
+        assert_eq!(name, "org.glfos.nixdiskmanager.Error.DeviceBusy");
+
+        let decoded = decode_error(&format!("GDBus.Error:{}: {}", name, message));
+        assert_eq!(decoded.to_string(), error.to_string());
+        assert!(matches!(
+            NixDiskError::find(&decoded),
+            Some(NixDiskError::DeviceBusy { .. })
+        ));
+
+        let missing = decode_error(
+            "GDBus.Error:org.freedesktop.DBus.Error.ServiceUnknown: The name is not activatable",
+        );
+        assert!(matches!(
+            NixDiskError::find(&missing),
+            Some(NixDiskError::ToolMissing { tool }) if tool == "nix-disk-helper"
+        ));
+    }
+}
@@ -0,0 +1,28 @@
+//! Core of nix-disk: everything that does not need a display
+//!
+//! - [`models`]: disks, partitions and the configuration being edited
+//! - [`utils::get_disks`]: probe the block devices of the machine
+//! - [`utils::parse_nix_filesystems`] / [`utils::get_nix_disks_config`]: read
+//!   and rewrite the `fileSystems` entries of a NixOS module with minimal edits
+//! - [`utils::load_config`] / [`utils::enable_module`]: locate the files to
+//!   edit, including flake-based systems, and set up `nix-disk.nix`
+//! - [`utils::validate_mount_point`]: refuse mount points that would break
+//!   the system before they reach the configuration
+//! - [`utils::check_syntax`] / [`utils::StagedConfig`]: check a generated
+//!   file, and optionally dry-build the system with it, before writing it
+//! - [`utils::layout`] / [`utils::format`]: plan a GPT or MBR partition layout
+//!   and format a disk with it, each partition in ext4, btrfs, XFS, exFAT,
+//!   NTFS or FAT
+//! - [`utils::rebuild_command`]: apply the configuration
+//! - [`NixDiskError`]: failures worth a specific message
+//! - [`helper`]: D-Bus protocol of the privileged `nix-disk-helper`
+//!
+//! Functions touching the system (probing, formatting, writing files) expect
+//! to run as root, like the nix-disk binaries.
+
+pub mod error;
+pub mod helper;
+pub mod models;
+pub mod utils;
+
+pub use error::NixDiskError;
@@ -0,0 +1,83 @@
+use super::stack::LayerKind;
+use std::path::PathBuf;
+
+/// A whole block device
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BlockDevice {
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    pub removable: bool,
+    pub read_only: bool,
+    pub rotational: bool,
+    /// Sorted by partition number
+    pub partitions: Vec<BlockPartition>,
+    /// Devices built on the whole disk, when it has no partition table
+    pub holders: Vec<BlockHolder>,
+}
+
+impl BlockDevice {
+    pub fn dev_path(&self) -> PathBuf {
+        PathBuf::from("/dev").join(&self.name)
+    }
+}
+
+/// A partition of a [`BlockDevice`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BlockPartition {
+    pub name: String,
+    pub number: u32,
+    /// Size in bytes
+    pub size: u64,
+    pub read_only: bool,
+    /// Filesystem details, when the probe reads them
+    pub fs_type: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// Devices built on the partition
+    pub holders: Vec<BlockHolder>,
+}
+
+impl BlockPartition {
+    pub fn dev_path(&self) -> PathBuf {
+        PathBuf::from("/dev").join(&self.name)
+    }
+}
+
+/// A device built on partitions, from their `holders` in sysfs: an opened
+/// LUKS volume, an LVM logical volume, a RAID array...
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHolder {
+    /// Kernel name, `dm-0` or `md0`
+    pub name: String,
+    pub kind: LayerKind,
+    /// Device-mapper name, for `/dev/mapper/<name>`
+    pub mapper_name: Option<String>,
+    /// Size in bytes
+    pub size: u64,
+    pub read_only: bool,
+    pub fs_type: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// Devices built on this one, and the partitions of a RAID array
+    pub holders: Vec<BlockHolder>,
+}
+
+impl BlockHolder {
+    pub fn kernel_path(&self) -> PathBuf {
+        PathBuf::from("/dev").join(&self.name)
+    }
+
+    /// `/dev/mapper/<name>` for device-mapper devices, the kernel path otherwise
+    pub fn dev_path(&self) -> PathBuf {
+        match &self.mapper_name {
+            Some(name) => PathBuf::from("/dev/mapper").join(name),
+            None => self.kernel_path(),
+        }
+    }
+
+    /// Name shown in a storage stack
+    pub fn display_name(&self) -> &str {
+        self.mapper_name.as_deref().unwrap_or(&self.name)
+    }
+}
@@ -0,0 +1,432 @@
+use super::disk::Disk;
+use super::encryption::Encryption;
+use super::partition::Partition;
+use std::path::{Path, PathBuf};
+
+/// An edit of the mount points of a partition, or of the LUKS volume they
+/// are opened from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountChange {
+    Add {
+        partition: PathBuf,
+        mount_point: String,
+    },
+    Remove {
+        partition: PathBuf,
+        mount_point: String,
+    },
+    /// `None` writes the probed filesystem type
+    SetFsType {
+        partition: PathBuf,
+        mount_point: String,
+        fs_type: Option<String>,
+        previous: Option<String>,
+    },
+    /// `None` writes the default options of the filesystem type
+    SetOptions {
+        partition: PathBuf,
+        mount_point: String,
+        options: Option<Vec<String>>,
+        previous: Option<Vec<String>>,
+    },
+    /// Open the LUKS volume of a partition at boot; `None` mounts the
+    /// partition itself
+    SetEncryption {
+        partition: PathBuf,
+        encryption: Option<Encryption>,
+        previous: Option<Encryption>,
+    },
+}
+
+impl MountChange {
+    pub fn partition(&self) -> &Path {
+        match self {
+            Self::Add { partition, .. }
+            | Self::Remove { partition, .. }
+            | Self::SetFsType { partition, .. }
+            | Self::SetOptions { partition, .. }
+            | Self::SetEncryption { partition, .. } => partition,
+        }
+    }
+
+    /// `None` for changes of the whole partition
+    pub fn mount_point(&self) -> Option<&str> {
+        match self {
+            Self::Add { mount_point, .. }
+            | Self::Remove { mount_point, .. }
+            | Self::SetFsType { mount_point, .. }
+            | Self::SetOptions { mount_point, .. } => Some(mount_point),
+            Self::SetEncryption { .. } => None,
+        }
+    }
+
+    /// The change undoing this one
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Self::Add {
+                partition,
+                mount_point,
+            } => Self::Remove {
+                partition,
+                mount_point,
+            },
+            Self::Remove {
+                partition,
+                mount_point,
+            } => Self::Add {
+                partition,
+                mount_point,
+            },
+            Self::SetFsType {
+                partition,
+                mount_point,
+                fs_type,
+                previous,
+            } => Self::SetFsType {
+                partition,
+                mount_point,
+                fs_type: previous,
+                previous: fs_type,
+            },
+            Self::SetOptions {
+                partition,
+                mount_point,
+                options,
+                previous,
+            } => Self::SetOptions {
+                partition,
+                mount_point,
+                options: previous,
+                previous: options,
+            },
+            Self::SetEncryption {
+                partition,
+                encryption,
+                previous,
+            } => Self::SetEncryption {
+                partition,
+                encryption: previous,
+                previous: encryption,
+            },
+        }
+    }
+
+    /// Apply the change to `disks`, returns false when the partition is not
+    /// one of them
+    pub fn apply(&self, disks: &mut [Disk]) -> bool {
+        let Some(partition) = disks
+            .iter_mut()
+            .flat_map(|disk| disk.partitions.iter_mut())
+            .find(|p| p.path == self.partition())
+        else {
+            return false;
+        };
+        match self {
+            Self::Add { mount_point, .. } => partition.add_mount_point(mount_point.clone()),
+            Self::Remove { mount_point, .. } => partition.remove_mount_point(mount_point),
+            Self::SetFsType {
+                mount_point,
+                fs_type,
+                ..
+            } => {
+                let settings = partition
+                    .mount_settings
+                    .entry(mount_point.clone())
+                    .or_default();
+                settings.fs_type = fs_type.clone();
+            }
+            Self::SetOptions {
+                mount_point,
+                options,
+                ..
+            } => {
+                let settings = partition
+                    .mount_settings
+                    .entry(mount_point.clone())
+                    .or_default();
+                settings.options = options.clone();
+            }
+            Self::SetEncryption { encryption, .. } => partition.encryption = encryption.clone(),
+        }
+        true
+    }
+
+    /// Both changes edit the same thing: the presence of a mount point, its
+    /// type, its options or the encryption of the partition
+    fn same_target(&self, other: &Self) -> bool {
+        let kind = |change: &Self| match change {
+            Self::Add { .. } | Self::Remove { .. } => 0,
+            Self::SetFsType { .. } => 1,
+            Self::SetOptions { .. } => 2,
+            Self::SetEncryption { .. } => 3,
+        };
+        kind(self) == kind(other)
+            && self.partition() == other.partition()
+            && self.mount_point() == other.mount_point()
+    }
+
+    /// This change followed by `next`, which has the same target, as a
+    /// single change; `None` when they cancel out
+    fn then(&self, next: &Self) -> Option<Self> {
+        match (self, next) {
+            (
+                Self::SetFsType {
+                    mount_point,
+                    previous,
+                    ..
+                },
+                Self::SetFsType { fs_type, .. },
+            ) => (previous != fs_type).then(|| Self::SetFsType {
+                partition: self.partition().to_path_buf(),
+                mount_point: mount_point.clone(),
+                fs_type: fs_type.clone(),
+                previous: previous.clone(),
+            }),
+            (
+                Self::SetOptions {
+                    mount_point,
+                    previous,
+                    ..
+                },
+                Self::SetOptions { options, .. },
+            ) => (previous != options).then(|| Self::SetOptions {
+                partition: self.partition().to_path_buf(),
+                mount_point: mount_point.clone(),
+                options: options.clone(),
+                previous: previous.clone(),
+            }),
+            (Self::SetEncryption { previous, .. }, Self::SetEncryption { encryption, .. }) => {
+                (previous != encryption).then(|| Self::SetEncryption {
+                    partition: self.partition().to_path_buf(),
+                    encryption: encryption.clone(),
+                    previous: previous.clone(),
+                })
+            }
+            _ if *next == self.inverse() => None,
+            _ => Some(self.clone()),
+        }
+    }
+}
+
+/// Mount point changes made in the interface but not written yet, on top of
+/// the disks as the configuration declares them
+///
+/// Every change is kept in order so that it can be undone, then redone until
+/// a new change is made.
+#[derive(Debug, Clone, Default)]
+pub struct PendingChanges {
+    saved: Vec<Disk>,
+    history: Vec<MountChange>,
+    undone: Vec<MountChange>,
+    /// Configured partitions that are gone, whose entries and LUKS volumes
+    /// the next write deletes
+    forgotten: Vec<Partition>,
+}
+
+impl PendingChanges {
+    pub fn new(saved: Vec<Disk>) -> Self {
+        Self {
+            saved,
+            history: Vec::new(),
+            undone: Vec::new(),
+            forgotten: Vec::new(),
+        }
+    }
+
+    /// Delete the configuration of `partitions` with the next write
+    pub fn forget(&mut self, partitions: impl IntoIterator<Item = Partition>) {
+        for partition in partitions {
+            if !self
+                .forgotten
+                .iter()
+                .any(|p| p.uuid_path == partition.uuid_path)
+            {
+                self.forgotten.push(partition);
+            }
+        }
+    }
+
+    pub fn forgotten(&self) -> &[Partition] {
+        &self.forgotten
+    }
+
+    pub fn push(&mut self, change: MountChange) {
+        self.undone.clear();
+        self.history.push(change);
+    }
+
+    /// Take back the last change, returns it
+    pub fn undo(&mut self) -> Option<MountChange> {
+        let change = self.history.pop()?;
+        self.undone.push(change.clone());
+        Some(change)
+    }
+
+    /// Make the last undone change again, returns it
+    pub fn redo(&mut self) -> Option<MountChange> {
+        let change = self.undone.pop()?;
+        self.history.push(change.clone());
+        Some(change)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// The net effect of the history: a change undoing an earlier one
+    /// cancels it, successive edits of the same setting are merged
+    pub fn changes(&self) -> Vec<MountChange> {
+        let mut changes: Vec<MountChange> = Vec::new();
+        for change in &self.history {
+            match changes.iter().position(|c| c.same_target(change)) {
+                Some(index) => match changes[index].then(change) {
+                    Some(merged) => changes[index] = merged,
+                    None => {
+                        changes.remove(index);
+                    }
+                },
+                None => changes.push(change.clone()),
+            }
+        }
+        changes
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes().len() + self.forgotten.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes().is_empty() && self.forgotten.is_empty()
+    }
+
+    /// Disks as the configuration declares them
+    pub fn saved(&self) -> &[Disk] {
+        &self.saved
+    }
+
+    /// Keep the pending changes on top of disks probed again; changes of
+    /// partitions that disappeared are dropped, and so are forgotten
+    /// partitions that are back
+    pub fn rebase(&mut self, saved: Vec<Disk>) {
+        self.forgotten.retain(|forgotten| {
+            !saved
+                .iter()
+                .flat_map(|disk| disk.partitions.iter())
+                .any(|p| p.uuid_path == forgotten.uuid_path)
+        });
+        self.saved = saved;
+        let mut disks = self.saved.clone();
+        self.history.retain(|change| change.apply(&mut disks));
+        self.undone
+            .retain(|change| change.apply(&mut disks.clone()));
+    }
+
+    /// Disks with every pending change applied
+    pub fn disks(&self) -> Vec<Disk> {
+        let mut disks = self.saved.clone();
+        for change in &self.history {
+            change.apply(&mut disks);
+        }
+        disks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_changes() {
+        let partition = Partition::new(
+            PathBuf::from("/dev/sdb1"),
+            PathBuf::from("/dev/disk/by-uuid/1234"),
+            vec!["/media/data".to_string()],
+            Some("ext4".to_string()),
+            0,
+            None,
+        );
+        let disk = Disk::new(PathBuf::from("/dev/sdb"), vec![partition], 0);
+        let mut pending = PendingChanges::new(vec![disk]);
+
+        let add = MountChange::Add {
+            partition: PathBuf::from("/dev/sdb1"),
+            mount_point: "/media/backup".to_string(),
+        };
+        pending.push(add.clone());
+        pending.push(MountChange::Remove {
+            partition: PathBuf::from("/dev/sdb1"),
+            mount_point: "/media/data".to_string(),
+        });
+        assert_eq!(pending.len(), 2);
+        assert_eq!(
+            pending.disks()[0].partitions[0].mount_points,
+            vec!["/media/backup"]
+        );
+
+        // Removing what was just added leaves only the removal
+        pending.push(add.inverse());
+        assert_eq!(pending.len(), 1);
+        assert!(pending.disks()[0].partitions[0].mount_points.is_empty());
+        assert_eq!(
+            pending.saved()[0].partitions[0].mount_points,
+            vec!["/media/data"]
+        );
+
+        // Successive option edits show as one change, undone one at a time
+        let set_options = |options: &[&str], previous: Option<&[&str]>| MountChange::SetOptions {
+            partition: PathBuf::from("/dev/sdb1"),
+            mount_point: "/media/data".to_string(),
+            options: Some(options.iter().map(|o| o.to_string()).collect()),
+            previous: previous.map(|p| p.iter().map(|o| o.to_string()).collect()),
+        };
+        pending.push(set_options(&["ro"], None));
+        pending.push(set_options(&["noatime"], Some(&["ro"])));
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.changes()[1], set_options(&["noatime"], None));
+
+        assert_eq!(
+            pending.undo(),
+            Some(set_options(&["noatime"], Some(&["ro"])))
+        );
+        let settings = pending.disks()[0].partitions[0].settings("/media/data");
+        assert_eq!(settings.options, Some(vec!["ro".to_string()]));
+        assert!(pending.redo().is_some());
+        assert!(!pending.can_redo());
+
+        while pending.undo().is_some() {}
+        assert!(pending.is_empty());
+        assert_eq!(
+            pending.disks()[0].partitions[0].mount_points,
+            vec!["/media/data"]
+        );
+
+        // A new change drops what could be redone
+        pending.push(add);
+        assert!(!pending.can_redo());
+
+        // A gone partition is forgotten once, until it comes back
+        let gone = Partition::new(
+            PathBuf::from("/dev/sdc1"),
+            PathBuf::from("/dev/disk/by-uuid/5678"),
+            vec!["/media/old".to_string()],
+            None,
+            0,
+            None,
+        );
+        pending.forget([gone.clone(), gone.clone()]);
+        assert_eq!(pending.forgotten().len(), 1);
+        assert_eq!(pending.len(), 2);
+
+        let saved = pending.saved().to_vec();
+        pending.rebase(saved.clone());
+        assert_eq!(pending.forgotten().len(), 1);
+        let mut back = saved;
+        back.push(Disk::new(PathBuf::from("/dev/sdc"), vec![gone], 0));
+        pending.rebase(back);
+        assert!(pending.forgotten().is_empty());
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+/// The NixOS configuration nix-disk works on
+#[derive(Debug, Clone, Default)]
+pub struct NixConfig {
+    /// File nix-disk writes its mount points to
+    pub path: PathBuf,
+    /// Current content of `path`
+    pub content: String,
+    /// Other modules declaring filesystems (hardware-configuration.nix when
+    /// nix-disk has its own module), only read to know which partitions are
+    /// already used by the system
+    pub readonly: Vec<String>,
+}
+
+impl NixConfig {
+    pub fn new(path: PathBuf, content: String) -> Self {
+        Self {
+            path,
+            content,
+            readonly: Vec::new(),
+        }
+    }
+
+    /// Every configuration source, starting with the managed file
+    pub fn sources(&self) -> Vec<&str> {
+        std::iter::once(self.content.as_str())
+            .chain(self.readonly.iter().map(String::as_str))
+            .collect()
+    }
+}
@@ -0,0 +1,42 @@
+use super::partition::Partition;
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Disk {
+    pub path: PathBuf,
+    pub partitions: Vec<Partition>,
+    /// Size in bytes
+    pub size: u64,
+    /// USB sticks, SD cards...
+    pub removable: bool,
+    pub read_only: bool,
+    /// Spinning disk rather than SSD
+    pub rotational: bool,
+    /// Devices built on the whole disk, without a partition table: a RAID
+    /// array, LVM logical volumes...
+    pub holders: Vec<String>,
+}
+
+impl Disk {
+    pub fn new(path: PathBuf, partitions: Vec<Partition>, size: u64) -> Self {
+        Self {
+            path,
+            partitions,
+            size,
+            removable: false,
+            read_only: false,
+            rotational: false,
+            holders: Vec::new(),
+        }
+    }
+
+    /// Neither partitioned nor used whole by another device
+    pub fn is_blank(&self) -> bool {
+        self.partitions.is_empty() && self.holders.is_empty()
+    }
+
+    pub fn add_partition(&mut self, partition: Partition) {
+        self.partitions.push(partition);
+    }
+}
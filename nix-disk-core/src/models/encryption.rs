@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How a LUKS volume is opened at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unlock {
+    /// `boot.initrd.luks.devices`: the passphrase is asked before the root
+    /// filesystem is mounted
+    Initrd,
+    /// `/etc/crypttab`: systemd opens the volume once the system is up,
+    /// with a key file or by asking the passphrase
+    Crypttab,
+}
+
+impl Unlock {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unlock::Initrd => "initrd",
+            Unlock::Crypttab => "crypttab",
+        }
+    }
+}
+
+impl fmt::Display for Unlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Unlock {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "initrd" => Ok(Unlock::Initrd),
+            "crypttab" => Ok(Unlock::Crypttab),
+            _ => Err(format!("Unknown unlock method: {}", s)),
+        }
+    }
+}
+
+/// LUKS volume held by a partition, opened as `/dev/mapper/<name>`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Encryption {
+    pub name: String,
+    pub unlock: Unlock,
+    /// Key file opening the volume without a passphrase
+    pub key_file: Option<String>,
+    /// Filesystem inside the volume, when known
+    pub fs_type: Option<String>,
+}
+
+impl Encryption {
+    /// Device of the opened volume, the one `fileSystems` entries mount
+    pub fn mapper_path(&self) -> PathBuf {
+        PathBuf::from("/dev/mapper").join(&self.name)
+    }
+}
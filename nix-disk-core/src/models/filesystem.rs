@@ -0,0 +1,80 @@
+use super::encryption::{Encryption, Unlock};
+use std::ops::Range;
+
+/// A `fileSystems."<mount point>"` entry of a NixOS configuration
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileSystemEntry {
+    pub mount_point: String,
+    pub device: Option<String>,
+    pub fs_type: Option<String>,
+    pub options: Vec<String>,
+    pub needed_for_boot: bool,
+    pub depends: Vec<String>,
+    /// Byte ranges of every binding defining this entry, in source order.
+    /// Usually a single `fileSystems."/x" = { ... };` binding, but the
+    /// entry may also be split as `fileSystems."/x".device = ...;`
+    pub spans: Vec<Range<usize>>,
+    /// Byte range of the `device` string literal, quotes included
+    pub device_span: Option<Range<usize>>,
+    /// Byte range of the `fsType` string literal, quotes included
+    pub fs_type_span: Option<Range<usize>>,
+    /// Byte range of the `options` list, brackets included, when it only
+    /// holds string literals
+    pub options_span: Option<Range<usize>>,
+    /// Whether the entry lives inside a `fileSystems = { ... };` set
+    pub nested: bool,
+}
+
+/// A LUKS volume opened at boot, from `boot.initrd.luks.devices."<name>"`
+/// or a line of `environment.etc."crypttab".text`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuksDevice {
+    pub name: String,
+    /// Partition holding the volume, `UUID=` crypttab devices as by-uuid paths
+    pub device: Option<String>,
+    pub key_file: Option<String>,
+    pub unlock: Unlock,
+    /// Byte ranges of the `boot.initrd.luks.devices` bindings defining it,
+    /// empty for crypttab lines
+    pub spans: Vec<Range<usize>>,
+    /// Whether the device lives inside a `boot.initrd.luks.devices = { ... };`
+    /// set, or a set above it
+    pub nested: bool,
+}
+
+impl LuksDevice {
+    /// The volume as a partition holds it; `fs_type` comes from its
+    /// `fileSystems` entry
+    pub fn encryption(&self, fs_type: Option<String>) -> Encryption {
+        Encryption {
+            name: self.name.clone(),
+            unlock: self.unlock,
+            key_file: self.key_file.clone(),
+            fs_type,
+        }
+    }
+}
+
+/// Every filesystem declared in a NixOS module
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NixFileSystems {
+    pub entries: Vec<FileSystemEntry>,
+    /// LUKS volumes opened at boot
+    pub luks_devices: Vec<LuksDevice>,
+    /// Byte range of the `environment.etc."crypttab".text` value
+    pub crypttab_span: Option<Range<usize>>,
+    /// Byte range of the module's top-level attribute set, braces included
+    pub module_span: Option<Range<usize>>,
+    /// Byte range of a `fileSystems = { ... }` attribute set, braces included
+    pub attr_set_span: Option<Range<usize>>,
+}
+
+impl NixFileSystems {
+    pub fn get(&self, mount_point: &str) -> Option<&FileSystemEntry> {
+        self.entries.iter().find(|e| e.mount_point == mount_point)
+    }
+
+    pub fn luks_device(&self, name: &str) -> Option<&LuksDevice> {
+        self.luks_devices.iter().find(|d| d.name == name)
+    }
+}
@@ -0,0 +1,34 @@
+use super::disk::Disk;
+use super::partition::Partition;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Version of the JSON document printed by `nix-disk list --json`.
+/// Bumped whenever a field is renamed or removed; new fields may be added
+/// without changing it.
+pub const INVENTORY_SCHEMA_VERSION: u32 = 1;
+
+/// A `fileSystems` entry as declared in the configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfiguredMount {
+    pub mount_point: String,
+    pub device: Option<String>,
+    pub fs_type: Option<String>,
+    pub options: Vec<String>,
+    /// Declared outside the file nix-disk manages, nix-disk leaves it alone
+    pub readonly: bool,
+}
+
+/// Everything nix-disk knows about the machine
+#[derive(Debug, Clone, Serialize)]
+pub struct Inventory {
+    pub schema_version: u32,
+    /// File nix-disk writes its mount points to
+    pub config_file: PathBuf,
+    /// `<dir>#<host>` when the system is built from a flake
+    pub flake: Option<String>,
+    pub disks: Vec<Disk>,
+    pub mounts: Vec<ConfiguredMount>,
+    /// Configured partitions whose device no longer exists
+    pub missing_partitions: Vec<Partition>,
+}
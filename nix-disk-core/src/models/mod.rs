@@ -0,0 +1,19 @@
+pub mod block_device;
+pub mod changes;
+pub mod config;
+pub mod disk;
+pub mod encryption;
+pub mod filesystem;
+pub mod inventory;
+pub mod partition;
+pub mod stack;
+
+pub use block_device::{BlockDevice, BlockHolder, BlockPartition};
+pub use changes::{MountChange, PendingChanges};
+pub use config::NixConfig;
+pub use disk::Disk;
+pub use encryption::{Encryption, Unlock};
+pub use filesystem::{FileSystemEntry, LuksDevice, NixFileSystems};
+pub use inventory::{ConfiguredMount, INVENTORY_SCHEMA_VERSION, Inventory};
+pub use partition::{MountSettings, Partition};
+pub use stack::{LayerKind, StackLayer};
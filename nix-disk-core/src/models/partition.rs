@@ -0,0 +1,122 @@
+use super::encryption::Encryption;
+use super::stack::StackLayer;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// How a mount point is declared, as read from the configuration or
+/// changed in the interface
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountSettings {
+    /// `fsType`, the probed filesystem type when unset
+    pub fs_type: Option<String>,
+    /// `options`, the defaults of the filesystem type when unset
+    pub options: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Partition {
+    pub path: PathBuf,
+    pub uuid_path: PathBuf,
+    /// Filesystem UUID, taken from `uuid_path`
+    pub uuid: Option<String>,
+    pub mount_points: Vec<String>,
+    pub fs_type: Option<String>,
+    /// Size in bytes
+    pub size: u64,
+    pub label: Option<String>,
+    /// Settings of each mount point; kept when the mount point is removed
+    /// so that adding it back restores them. The inventory lists the
+    /// configured mounts with their options already.
+    #[serde(skip)]
+    pub mount_settings: BTreeMap<String, MountSettings>,
+    /// LUKS volume the partition holds; its mount points are the ones of
+    /// the filesystem inside
+    pub encryption: Option<Encryption>,
+    /// Layers below a filesystem built on LUKS, LVM or RAID, from the disk
+    /// up to the device itself: `[sda2, LUKS cryptroot, LVM vg-root]`.
+    /// Empty for a plain partition.
+    pub stack: Vec<StackLayer>,
+    /// Options of its new mount points, from the choices made when nix-disk
+    /// formatted it
+    #[serde(skip)]
+    pub default_options: Option<Vec<String>>,
+}
+
+impl Partition {
+    pub fn new(
+        path: PathBuf,
+        uuid_path: PathBuf,
+        mount_points: Vec<String>,
+        fs_type: Option<String>,
+        size: u64,
+        label: Option<String>,
+    ) -> Self {
+        let uuid = uuid_path
+            .strip_prefix("/dev/disk/by-uuid")
+            .ok()
+            .map(|uuid| uuid.to_string_lossy().into_owned());
+        Self {
+            path,
+            uuid_path,
+            uuid,
+            mount_points,
+            fs_type,
+            size,
+            label,
+            mount_settings: BTreeMap::new(),
+            encryption: None,
+            stack: Vec::new(),
+            default_options: None,
+        }
+    }
+
+    pub fn add_mount_point(&mut self, mount_point: String) {
+        if !self.mount_points.contains(&mount_point) {
+            self.mount_points.push(mount_point);
+        }
+    }
+
+    pub fn remove_mount_point(&mut self, mount_point: &str) {
+        self.mount_points.retain(|mp| mp != mount_point);
+    }
+
+    pub fn settings(&self, mount_point: &str) -> MountSettings {
+        self.mount_settings
+            .get(mount_point)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// `fsType` written for `mount_point`
+    pub fn mount_fs_type(&self, mount_point: &str) -> Option<&str> {
+        let configured = self
+            .mount_settings
+            .get(mount_point)
+            .and_then(|s| s.fs_type.as_deref());
+        match &self.encryption {
+            // The probed type is the one of the LUKS header
+            Some(encryption) => configured.or(encryption.fs_type.as_deref()),
+            None => configured.or(self.fs_type.as_deref()),
+        }
+    }
+
+    /// `sda2 → LUKS cryptroot → LVM vg-root → ext4` for a stacked filesystem
+    pub fn stack_description(&self) -> Option<String> {
+        if self.stack.is_empty() {
+            return None;
+        }
+        let mut layers: Vec<String> = self.stack.iter().map(ToString::to_string).collect();
+        layers.extend(self.fs_type.clone());
+        Some(layers.join(" → "))
+    }
+
+    /// `device` written for the mount points: the opened volume of an
+    /// encrypted partition, the partition by UUID otherwise
+    pub fn mount_device(&self) -> PathBuf {
+        match &self.encryption {
+            Some(encryption) => encryption.mapper_path(),
+            None => self.uuid_path.clone(),
+        }
+    }
+}
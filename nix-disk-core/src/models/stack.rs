@@ -0,0 +1,67 @@
+use serde::Serialize;
+use std::fmt;
+
+/// What a level of a storage stack is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerKind {
+    /// A disk used whole, without a partition table
+    Disk,
+    Partition,
+    /// An opened LUKS volume
+    Luks,
+    /// An LVM logical volume
+    Lvm,
+    /// A software RAID array (mdraid)
+    Raid,
+    /// Any other device-mapper target: multipath, dm-integrity...
+    Mapper,
+}
+
+impl LayerKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LayerKind::Disk => "disk",
+            LayerKind::Partition => "partition",
+            LayerKind::Luks => "LUKS",
+            LayerKind::Lvm => "LVM",
+            LayerKind::Raid => "RAID",
+            LayerKind::Mapper => "device-mapper",
+        }
+    }
+}
+
+impl fmt::Display for LayerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Devices of one level of a storage stack
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackLayer {
+    pub kind: LayerKind,
+    /// Kernel or mapper names; several for the members of a RAID array or a
+    /// logical volume spread over several partitions
+    pub devices: Vec<String>,
+}
+
+impl StackLayer {
+    pub fn new(kind: LayerKind, device: &str) -> Self {
+        Self {
+            kind,
+            devices: vec![device.to_string()],
+        }
+    }
+}
+
+impl fmt::Display for StackLayer {
+    /// `sda1 + sdb1` for disks and partitions, `LUKS cryptroot` otherwise
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let devices = self.devices.join(" + ");
+        match self.kind {
+            LayerKind::Disk | LayerKind::Partition => f.write_str(&devices),
+            kind => write!(f, "{} {}", kind, devices),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Backups of the configuration files nix-disk overwrites
+//!
+//! Every write first stores the previous content in its own directory under
+//! [`BACKUP_DIR`], together with the diff of the change and the action that
+//! caused it, so that any earlier version can be restored.
+
+use crate::error::{NixDiskError, read_file};
+use crate::utils::diff::unified_diff;
+use crate::utils::file::{check_unchanged, write_file};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const BACKUP_DIR: &str = "/var/lib/nix-disk/backups";
+
+/// Older backups are deleted beyond this count
+const MAX_BACKUPS: usize = 50;
+
+const METADATA_FILE: &str = "backup.json";
+const CONTENT_FILE: &str = "content.nix";
+const DIFF_FILE: &str = "changes.diff";
+
+/// A previous version of a configuration file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// File the content was saved from
+    pub path: PathBuf,
+    /// What replaced it, as shown in the history
+    pub action: String,
+    /// Seconds since the epoch
+    pub timestamp: u64,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl Backup {
+    /// Content of the file before the change
+    pub fn content(&self) -> Result<String> {
+        Ok(read_file(&self.dir.join(CONTENT_FILE))?)
+    }
+
+    /// Unified diff from this version to the one that replaced it
+    pub fn diff(&self) -> Result<String> {
+        Ok(read_file(&self.dir.join(DIFF_FILE))?)
+    }
+}
+
+/// Store the current content of `path` before it becomes `new_content`
+///
+/// Returns `None` when there is nothing to keep: the file does not exist yet
+/// or already has this content.
+pub fn create_backup(
+    backup_root: &Path,
+    path: &Path,
+    new_content: &str,
+    action: &str,
+) -> Result<Option<Backup>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let old_content = read_file(path)?;
+    if old_content == new_content {
+        return Ok(None);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut dir = backup_root.join(format!("{}-{:09}", now.as_secs(), now.subsec_nanos()));
+    let mut attempt = 1;
+    while dir.exists() {
+        dir = backup_root.join(format!(
+            "{}-{:09}-{}",
+            now.as_secs(),
+            now.subsec_nanos(),
+            attempt
+        ));
+        attempt += 1;
+    }
+    fs::create_dir_all(&dir).map_err(|e| NixDiskError::io(&dir, e))?;
+
+    let backup = Backup {
+        path: path.to_path_buf(),
+        action: action.to_string(),
+        timestamp: now.as_secs(),
+        dir: dir.clone(),
+    };
+    let name = path.display().to_string();
+    let diff = unified_diff(&old_content, new_content, &name, &name, 3);
+    write_file(&dir.join(CONTENT_FILE), &old_content)?;
+    write_file(&dir.join(DIFF_FILE), &diff)?;
+    write_file(
+        &dir.join(METADATA_FILE),
+        &serde_json::to_string_pretty(&backup)?,
+    )?;
+
+    prune_backups(backup_root)?;
+    Ok(Some(backup))
+}
+
+/// Back up `path`, then write `content` to it
+///
+/// With `expected_hash`, the write is refused if the file no longer has the
+/// content it was loaded with (see [`content_hash`](crate::utils::content_hash)).
+pub fn write_with_backup(
+    backup_root: &Path,
+    path: &Path,
+    content: &str,
+    action: &str,
+    expected_hash: Option<&str>,
+) -> Result<Option<Backup>> {
+    if let Some(expected_hash) = expected_hash {
+        check_unchanged(path, expected_hash)?;
+    }
+    let backup = create_backup(backup_root, path, content, action)
+        .with_context(|| format!("Failed to back up {}", path.display()))?;
+    write_file(path, content)?;
+    Ok(backup)
+}
+
+/// Backups found under `backup_root`, newest first
+pub fn list_backups(backup_root: &Path) -> Result<Vec<Backup>> {
+    let entries = match fs::read_dir(backup_root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(NixDiskError::io(backup_root, e).into()),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries {
+        let dir = entry.map_err(|e| NixDiskError::io(backup_root, e))?.path();
+        let Ok(metadata) = read_file(&dir.join(METADATA_FILE)) else {
+            continue;
+        };
+        match serde_json::from_str::<Backup>(&metadata) {
+            Ok(backup) => backups.push(Backup { dir, ..backup }),
+            Err(e) => eprintln!("⚠️  Sauvegarde illisible {}: {}", dir.display(), e),
+        }
+    }
+    backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.dir.cmp(&a.dir)));
+    Ok(backups)
+}
+
+fn prune_backups(backup_root: &Path) -> Result<()> {
+    for old in list_backups(backup_root)?.iter().skip(MAX_BACKUPS) {
+        fs::remove_dir_all(&old.dir).map_err(|e| NixDiskError::io(&old.dir, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = dir.path().join("backups");
+        let config = dir.path().join("nix-disk.nix");
+
+        // Nothing to keep for a new file
+        let first = write_with_backup(&backups, &config, "{ }\n", "Set up", None).unwrap();
+        assert!(first.is_none());
+
+        let new = "{\n  fileSystems.\"/data\".device = \"/dev/sdb1\";\n}\n";
+        let backup = write_with_backup(&backups, &config, new, "Save mount points", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), new);
+        assert_eq!(backup.content().unwrap(), "{ }\n");
+        assert!(backup.diff().unwrap().contains("+  fileSystems"));
+
+        // Unchanged content is not backed up again
+        assert!(
+            write_with_backup(&backups, &config, new, "Save", None)
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(list_backups(&backups).unwrap(), vec![backup]);
+    }
+}
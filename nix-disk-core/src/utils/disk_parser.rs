@@ -0,0 +1,861 @@
+use crate::error::NixDiskError;
+use crate::models::{
+    BlockDevice, BlockHolder, BlockPartition, Disk, Encryption, FileSystemEntry, LayerKind,
+    LuksDevice, MountSettings, NixFileSystems, Partition, StackLayer, Unlock,
+};
+use crate::utils::lsblk::probe_lsblk;
+use crate::utils::nix_parser::{self, Binding, Expr};
+use crate::utils::sysfs::{DEFAULT_SYSFS_ROOT, probe_block_devices};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Parse NixOS filesystem configurations from hardware-configuration.nix
+pub fn parse_nix_filesystems(nix_config: &str) -> Result<NixFileSystems> {
+    let expr = nix_parser::parse(nix_config).context("Failed to parse NixOS configuration")?;
+
+    let mut filesystems = NixFileSystems::default();
+    collect_module(expr.strip_wrappers(), &mut filesystems);
+
+    Ok(filesystems)
+}
+
+/// Walk a module body, following `config = { ... }` and `lib.mkMerge [ ... ]`
+fn collect_module(expr: &Expr, filesystems: &mut NixFileSystems) {
+    match expr {
+        Expr::AttrSet { bindings, span, .. } => {
+            filesystems.module_span.get_or_insert_with(|| span.clone());
+            for binding in bindings {
+                let Some(path) = binding.path_names() else {
+                    continue;
+                };
+                match path.as_slice() {
+                    ["config"] => collect_module(binding.value.strip_wrappers(), filesystems),
+                    ["config", rest @ ..] => collect_filesystems(rest, binding, filesystems),
+                    path => collect_filesystems(path, binding, filesystems),
+                }
+            }
+        }
+        Expr::Apply { arg, .. } if expr.function_name() == Some("mkMerge") => {
+            if let Expr::List { items, .. } = arg.strip_wrappers() {
+                for item in items {
+                    collect_module(item.strip_wrappers(), filesystems);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+const LUKS_DEVICES: &[&str] = &["boot", "initrd", "luks", "devices"];
+const CRYPTTAB: &[&str] = &["environment", "etc", "crypttab"];
+
+/// Handle both `fileSystems."/x" = { ... };` and `fileSystems = { "/x" = { ... }; };`,
+/// and the LUKS volumes opened at boot
+fn collect_filesystems(path: &[&str], binding: &Binding, filesystems: &mut NixFileSystems) {
+    match path {
+        ["fileSystems"] => {
+            if let Expr::AttrSet { bindings, span, .. } = binding.value.strip_wrappers() {
+                filesystems.attr_set_span = Some(span.clone());
+                for inner in bindings {
+                    if let Some([mount_point, fields @ ..]) = inner.path_names().as_deref() {
+                        add_definition(filesystems, mount_point, fields, inner, true);
+                    }
+                }
+            }
+        }
+        ["fileSystems", mount_point, fields @ ..] => {
+            add_definition(filesystems, mount_point, fields, binding, false);
+        }
+        _ => {
+            collect_below(path, LUKS_DEVICES, binding, &mut |rest, binding| {
+                if let [name, fields @ ..] = rest {
+                    let nested = path.len() <= LUKS_DEVICES.len();
+                    add_luks_device(filesystems, name, fields, binding, nested);
+                }
+            });
+            collect_below(path, CRYPTTAB, binding, &mut |rest, binding| {
+                if rest == ["text"] {
+                    add_crypttab(filesystems, &binding.value);
+                }
+            });
+        }
+    }
+}
+
+/// Call `found` with each binding below `target` and its path under it,
+/// whether written `a.b.c = ...;` or `a = { b.c = ...; };`
+fn collect_below<'a>(
+    path: &[&'a str],
+    target: &[&str],
+    binding: &'a Binding,
+    found: &mut dyn FnMut(&[&'a str], &'a Binding),
+) {
+    if path.len() > target.len() && path.starts_with(target) {
+        found(&path[target.len()..], binding);
+    } else if target.starts_with(path)
+        && let Expr::AttrSet { bindings, .. } = binding.value.strip_wrappers()
+    {
+        for inner in bindings {
+            if let Some(inner_path) = inner.path_names() {
+                let full: Vec<&str> = path.iter().copied().chain(inner_path).collect();
+                collect_below(&full, target, inner, found);
+            }
+        }
+    }
+}
+
+fn add_luks_device(
+    filesystems: &mut NixFileSystems,
+    name: &str,
+    fields: &[&str],
+    binding: &Binding,
+    nested: bool,
+) {
+    let index = match filesystems
+        .luks_devices
+        .iter()
+        .position(|d| d.name == name && d.unlock == Unlock::Initrd)
+    {
+        Some(index) => index,
+        None => {
+            filesystems.luks_devices.push(LuksDevice {
+                name: name.to_string(),
+                device: None,
+                key_file: None,
+                unlock: Unlock::Initrd,
+                spans: Vec::new(),
+                nested,
+            });
+            filesystems.luks_devices.len() - 1
+        }
+    };
+    let device = &mut filesystems.luks_devices[index];
+    device.spans.push(binding.span.clone());
+
+    let mut apply = |field: &[&str], value: &Expr| match field {
+        ["device"] => device.device = value.as_str().map(str::to_string),
+        ["keyFile"] => device.key_file = value.as_str().map(str::to_string),
+        _ => {}
+    };
+    if !fields.is_empty() {
+        apply(fields, &binding.value);
+    } else if let Expr::AttrSet { bindings, .. } = binding.value.strip_wrappers() {
+        for field in bindings {
+            if let Some(path) = field.path_names() {
+                apply(&path, &field.value);
+            }
+        }
+    }
+}
+
+/// Read the volumes of a crypttab: `name device [key file] [options]`
+fn add_crypttab(filesystems: &mut NixFileSystems, value: &Expr) {
+    filesystems.crypttab_span = Some(value.span());
+    let Some(text) = value.as_str() else {
+        return;
+    };
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(device)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let device = match device.split_once('=') {
+            Some(("UUID", uuid)) => format!("/dev/disk/by-uuid/{}", uuid),
+            Some(("PARTUUID", uuid)) => format!("/dev/disk/by-partuuid/{}", uuid),
+            Some(("LABEL", label)) => format!("/dev/disk/by-label/{}", label),
+            _ => device.to_string(),
+        };
+        filesystems.luks_devices.push(LuksDevice {
+            name: name.to_string(),
+            device: Some(device),
+            // "-" and "none" ask for the passphrase
+            key_file: fields
+                .next()
+                .filter(|key| !matches!(*key, "-" | "none"))
+                .map(str::to_string),
+            unlock: Unlock::Crypttab,
+            spans: Vec::new(),
+            nested: false,
+        });
+    }
+}
+
+fn add_definition(
+    filesystems: &mut NixFileSystems,
+    mount_point: &str,
+    fields: &[&str],
+    binding: &Binding,
+    nested: bool,
+) {
+    let index = match filesystems
+        .entries
+        .iter()
+        .position(|e| e.mount_point == mount_point)
+    {
+        Some(index) => index,
+        None => {
+            filesystems.entries.push(FileSystemEntry {
+                mount_point: mount_point.to_string(),
+                nested,
+                ..Default::default()
+            });
+            filesystems.entries.len() - 1
+        }
+    };
+    let entry = &mut filesystems.entries[index];
+    entry.spans.push(binding.span.clone());
+
+    if !fields.is_empty() {
+        apply_field(entry, fields, &binding.value);
+    } else if let Expr::AttrSet { bindings, .. } = binding.value.strip_wrappers() {
+        for field in bindings {
+            if let Some(path) = field.path_names() {
+                apply_field(entry, &path, &field.value);
+            }
+        }
+    }
+}
+
+fn apply_field(entry: &mut FileSystemEntry, field: &[&str], value: &Expr) {
+    let string_span = match value.strip_wrappers() {
+        Expr::Str {
+            value: Some(_),
+            span,
+        } => Some(span.clone()),
+        _ => None,
+    };
+
+    match field {
+        ["device"] => {
+            entry.device = value.as_str().map(str::to_string);
+            entry.device_span = string_span;
+        }
+        ["label"] if entry.device.is_none() => {
+            entry.device = value
+                .as_str()
+                .map(|label| format!("/dev/disk/by-label/{}", label));
+        }
+        ["fsType"] => {
+            entry.fs_type = value.as_str().map(str::to_string);
+            entry.fs_type_span = string_span;
+        }
+        ["options"] => {
+            entry.options = value.as_string_list().unwrap_or_default();
+            entry.options_span = match value.strip_wrappers() {
+                Expr::List { items, span } if items.len() == entry.options.len() => {
+                    Some(span.clone())
+                }
+                _ => None,
+            };
+        }
+        ["neededForBoot"] => entry.needed_for_boot = value.as_bool().unwrap_or(false),
+        ["depends"] => entry.depends = value.as_string_list().unwrap_or_default(),
+        _ => {}
+    }
+}
+
+/// Group configured filesystems by the partition backing them
+///
+/// Filesystems mounted from `/dev/mapper/<name>` belong to the partition
+/// holding the LUKS volume `name`, which also gets a partition when nothing
+/// is mounted from it yet.
+pub fn configured_partitions(filesystems: &NixFileSystems) -> HashMap<PathBuf, Partition> {
+    let mut partitions = HashMap::new();
+
+    for entry in &filesystems.entries {
+        // tmpfs, network shares and bind mounts are not partitions
+        let Some(device) = entry.device.as_deref().filter(|d| d.starts_with("/dev/")) else {
+            continue;
+        };
+        let luks = device
+            .strip_prefix("/dev/mapper/")
+            .and_then(|name| filesystems.luks_device(name))
+            .filter(|luks| luks.device.is_some());
+        let device = luks.and_then(|l| l.device.as_deref()).unwrap_or(device);
+        let mount_point = entry.mount_point.clone();
+
+        let partition = configured_partition(&mut partitions, device);
+        partition.add_mount_point(mount_point.clone());
+        if let Some(luks) = luks {
+            partition
+                .encryption
+                .get_or_insert_with(|| luks.encryption(entry.fs_type.clone()));
+        } else if partition.fs_type.is_none() {
+            partition.fs_type = entry.fs_type.clone();
+        }
+        partition.mount_settings.insert(
+            mount_point,
+            MountSettings {
+                fs_type: entry.fs_type.clone(),
+                options: entry.options_span.is_some().then(|| entry.options.clone()),
+            },
+        );
+    }
+
+    for luks in &filesystems.luks_devices {
+        let Some(device) = luks.device.as_deref().filter(|d| d.starts_with("/dev/")) else {
+            continue;
+        };
+        configured_partition(&mut partitions, device)
+            .encryption
+            .get_or_insert_with(|| luks.encryption(None));
+    }
+
+    partitions
+}
+
+/// The partition of `partitions` for a configured device, added if needed
+fn configured_partition<'a>(
+    partitions: &'a mut HashMap<PathBuf, Partition>,
+    device: &str,
+) -> &'a mut Partition {
+    let partition_uuid_path = PathBuf::from(device);
+
+    // Resolve symlink to get actual partition path
+    let partition_path = if partition_uuid_path.exists() {
+        fs::read_link(&partition_uuid_path)
+            .map(|link| {
+                let parent = partition_uuid_path
+                    .parent()
+                    .unwrap_or(Path::new("/dev/disk/by-uuid"));
+                parent.join(&link).canonicalize().unwrap_or(link)
+            })
+            .unwrap_or_else(|_| partition_uuid_path.clone())
+    } else {
+        partition_uuid_path.clone()
+    };
+
+    partitions.entry(partition_path.clone()).or_insert_with(|| {
+        Partition::new(
+            partition_path,
+            partition_uuid_path,
+            Vec::new(),
+            None,
+            0,
+            None,
+        )
+    })
+}
+
+/// Get all disks from the system, with the mount points declared in `nix_configs`
+pub fn get_disks(nix_configs: &[&str]) -> Result<Vec<Disk>> {
+    let devices = match probe_lsblk() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("⚠️  lsblk indisponible, lecture de sysfs et blkid: {:#}", e);
+            probe_with_blkid(Path::new(DEFAULT_SYSFS_ROOT))?
+        }
+    };
+    build_disks(devices, nix_configs)
+}
+
+/// [`get_disks`] reading sysfs from `sysfs_root` instead of running `lsblk`
+pub fn get_disks_with_sysfs_root(sysfs_root: &Path, nix_configs: &[&str]) -> Result<Vec<Disk>> {
+    build_disks(probe_with_blkid(sysfs_root)?, nix_configs)
+}
+
+/// Enumerate devices from sysfs and read filesystem details with `blkid`
+fn probe_with_blkid(sysfs_root: &Path) -> Result<Vec<BlockDevice>> {
+    let mut devices = probe_block_devices(sysfs_root)?;
+    for device in &mut devices {
+        for partition in &mut device.partitions {
+            let mut tags = read_blkid(&partition.dev_path())?;
+            partition.fs_type = tags.remove("TYPE");
+            partition.label = tags.remove("LABEL");
+            partition.uuid = tags.remove("UUID");
+            read_holders_blkid(&mut partition.holders)?;
+        }
+        read_holders_blkid(&mut device.holders)?;
+    }
+    Ok(devices)
+}
+
+/// [`read_blkid`] for each stacked device, down to the filesystems
+fn read_holders_blkid(holders: &mut [BlockHolder]) -> Result<()> {
+    for holder in holders {
+        let mut tags = read_blkid(&holder.kernel_path())?;
+        holder.fs_type = tags.remove("TYPE");
+        holder.label = tags.remove("LABEL");
+        holder.uuid = tags.remove("UUID");
+        read_holders_blkid(&mut holder.holders)?;
+    }
+    Ok(())
+}
+
+/// Tags of a device from `blkid -o export`: `TYPE`, `LABEL`, `UUID`...
+fn read_blkid(device: &Path) -> Result<HashMap<String, String>> {
+    let blkid_output = Command::new("blkid")
+        .args(["-o", "export"])
+        .arg(device)
+        .output()
+        .map_err(|e| NixDiskError::tool("blkid", e))?;
+
+    Ok(String::from_utf8_lossy(&blkid_output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
+
+/// Match probed devices with the partitions declared in `nix_configs`
+fn build_disks(devices: Vec<BlockDevice>, nix_configs: &[&str]) -> Result<Vec<Disk>> {
+    let mut partitions: HashMap<PathBuf, Partition> = HashMap::new();
+    for config in nix_configs {
+        for (path, partition) in configured_partitions(&parse_nix_filesystems(config)?) {
+            match partitions.get_mut(&path) {
+                Some(existing) => {
+                    for mount_point in partition.mount_points {
+                        existing.add_mount_point(mount_point);
+                    }
+                    for (mount_point, settings) in partition.mount_settings {
+                        existing
+                            .mount_settings
+                            .entry(mount_point)
+                            .or_insert(settings);
+                    }
+                    if existing.encryption.is_none() {
+                        existing.encryption = partition.encryption;
+                    }
+                }
+                None => {
+                    partitions.insert(path, partition);
+                }
+            }
+        }
+    }
+
+    let mut disks: Vec<Disk> = Vec::new();
+    // Filesystems on LUKS, LVM or RAID, with the index of their disk
+    let mut stacked: Vec<(usize, Partition)> = Vec::new();
+
+    for device in devices {
+        let mut disk = Disk::new(device.dev_path(), Vec::new(), device.size);
+        disk.removable = device.removable;
+        disk.read_only = device.read_only;
+        disk.rotational = device.rotational;
+
+        for mut block_partition in device.partitions {
+            let holders = std::mem::take(&mut block_partition.holders);
+            let stack = [StackLayer::new(LayerKind::Partition, &block_partition.name)];
+            let mut partition = to_partition(block_partition, &mut partitions);
+            let encryption = partition.as_mut().and_then(|p| p.encryption.as_mut());
+            for part in stacked_partitions(holders, &stack, encryption, &mut partitions) {
+                add_stacked(&mut stacked, disks.len(), part);
+            }
+            if let Some(part) = partition {
+                disk.add_partition(part);
+            }
+        }
+
+        disk.holders = device
+            .holders
+            .iter()
+            .map(|holder| holder.display_name().to_string())
+            .collect();
+        let stack = [StackLayer::new(LayerKind::Disk, &device.name)];
+        for part in stacked_partitions(device.holders, &stack, None, &mut partitions) {
+            add_stacked(&mut stacked, disks.len(), part);
+        }
+
+        disks.push(disk);
+    }
+
+    // After the partitions of the disk they start from
+    for (index, partition) in stacked {
+        disks[index].add_partition(partition);
+    }
+
+    Ok(disks)
+}
+
+/// The filesystems at the top of the devices stacked on a partition, each
+/// with the layers leading to it
+///
+/// The LUKS volume opened from a partition whose encryption is declared is
+/// left out, unless mounted by its own UUID: the partition manages the
+/// mount points of the volume.
+fn stacked_partitions(
+    holders: Vec<BlockHolder>,
+    stack: &[StackLayer],
+    mut encryption: Option<&mut Encryption>,
+    partitions_map: &mut HashMap<PathBuf, Partition>,
+) -> Vec<Partition> {
+    let mut stacked = Vec::new();
+    for holder in holders {
+        let mut layers = stack.to_vec();
+        layers.push(StackLayer::new(holder.kind, holder.display_name()));
+        if !holder.holders.is_empty() {
+            stacked.extend(stacked_partitions(
+                holder.holders,
+                &layers,
+                None,
+                partitions_map,
+            ));
+            continue;
+        }
+
+        // Configured from /dev/mapper on a system where it does not resolve
+        if let Some(partition) = partitions_map.remove(&holder.dev_path()) {
+            partitions_map
+                .entry(holder.kernel_path())
+                .or_insert(partition);
+        }
+        if let Some(encryption) = encryption.as_deref_mut()
+            && holder.kind == LayerKind::Luks
+            && holder.mapper_name.as_deref() == Some(encryption.name.as_str())
+            && !partitions_map.contains_key(&holder.kernel_path())
+        {
+            if encryption.fs_type.is_none() {
+                encryption.fs_type = holder.fs_type;
+            }
+            continue;
+        }
+
+        let dev_path = holder.dev_path();
+        let block_partition = BlockPartition {
+            name: holder.name,
+            number: 0,
+            size: holder.size,
+            read_only: holder.read_only,
+            fs_type: holder.fs_type,
+            label: holder.label,
+            uuid: holder.uuid,
+            holders: Vec::new(),
+        };
+        if let Some(mut partition) = to_partition(block_partition, partitions_map) {
+            partition.path = dev_path;
+            partition.stack = layers;
+            stacked.push(partition);
+        }
+    }
+    stacked
+}
+
+/// Add a stacked filesystem, merging the layers of one built on several
+/// devices: the members of a RAID array, a logical volume over two disks
+fn add_stacked(stacked: &mut Vec<(usize, Partition)>, disk: usize, partition: Partition) {
+    let Some((_, existing)) = stacked.iter_mut().find(|(_, p)| p.path == partition.path) else {
+        stacked.push((disk, partition));
+        return;
+    };
+    for (index, (layer, other)) in existing.stack.iter_mut().zip(partition.stack).enumerate() {
+        // The first layer mixes partitions and disks used whole
+        if layer.kind != other.kind && index > 0 {
+            continue;
+        }
+        for device in other.devices {
+            if !layer.devices.contains(&device) {
+                layer.devices.push(device);
+            }
+        }
+    }
+}
+
+/// Turn a probed partition into a model partition, keeping the mount points
+/// configured for it
+fn to_partition(
+    block_partition: BlockPartition,
+    partitions_map: &mut HashMap<PathBuf, Partition>,
+) -> Option<Partition> {
+    let partition_path = block_partition.dev_path();
+
+    // Check if this partition is already in our config
+    if let Some(existing_partition) = partitions_map.get_mut(&partition_path) {
+        existing_partition.fs_type = block_partition.fs_type;
+        existing_partition.label = block_partition.label;
+        existing_partition.size = block_partition.size;
+        return Some(existing_partition.clone());
+    }
+
+    // Without a UUID the partition cannot be referenced from the configuration
+    let Some(uuid) = block_partition.uuid else {
+        eprintln!(
+            "ℹ️  Partition sans UUID ignorée: {} ({})",
+            partition_path.display(),
+            block_partition
+                .fs_type
+                .as_deref()
+                .unwrap_or("aucun système de fichiers")
+        );
+        return None;
+    };
+    Some(Partition::new(
+        partition_path,
+        PathBuf::from(format!("/dev/disk/by-uuid/{}", uuid)),
+        Vec::new(),
+        block_partition.fs_type,
+        block_partition.size,
+        block_partition.label,
+    ))
+}
+
+/// Compare configured partitions with existing ones and find missing partitions
+pub fn find_missing_partitions(
+    configured_partitions: &[Partition],
+    existing_disks: &[Disk],
+) -> Vec<Partition> {
+    // Collect all existing partition UUIDs - this is the most reliable way.
+    // Logical volumes and arrays are also configured by their path.
+    let existing_uuids: std::collections::HashSet<PathBuf> = existing_disks
+        .iter()
+        .flat_map(|disk| disk.partitions.iter())
+        .flat_map(|p| [p.uuid_path.clone(), p.path.clone()])
+        .collect();
+
+    // A partition is missing if its UUID path is not in the existing UUIDs
+    configured_partitions
+        .iter()
+        .filter(|p| !existing_uuids.contains(&p.uuid_path))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nix_filesystems() {
+        let config = r#"{ config, lib, ... }:
+{
+  # Closing brace in a comment }
+  fileSystems."/" = {
+    device = "/dev/disk/by-uuid/root";
+    fsType = "btrfs";
+    options = [
+      "subvol=root"
+      "compress=zstd"
+    ];
+  };
+
+  fileSystems."/data" = lib.mkForce {
+    device = "/dev/disk/by-uuid/data";
+    fsType = "ext4";
+    encrypted = { enable = true; label = "crypt"; };
+    neededForBoot = true;
+  };
+
+  fileSystems = {
+    "/srv" = { device = "/dev/disk/by-label/srv"; depends = [ "/data" ]; };
+  };
+
+  fileSystems."/tmp".device = "none";
+  fileSystems."/tmp".fsType = "tmpfs";
+
+  swapDevices = [ ];
+}
+"#;
+        let filesystems = parse_nix_filesystems(config).unwrap();
+        let mount_points: Vec<_> = filesystems
+            .entries
+            .iter()
+            .map(|e| e.mount_point.as_str())
+            .collect();
+        assert_eq!(mount_points, vec!["/", "/data", "/srv", "/tmp"]);
+
+        let root = filesystems.get("/").unwrap();
+        assert_eq!(root.options, vec!["subvol=root", "compress=zstd"]);
+        assert_eq!(
+            &config[root.device_span.clone().unwrap()],
+            "\"/dev/disk/by-uuid/root\""
+        );
+
+        let data = filesystems.get("/data").unwrap();
+        assert_eq!(data.device.as_deref(), Some("/dev/disk/by-uuid/data"));
+        assert!(data.needed_for_boot);
+
+        let srv = filesystems.get("/srv").unwrap();
+        assert!(srv.nested);
+        assert_eq!(srv.depends, vec!["/data"]);
+
+        let tmp = filesystems.get("/tmp").unwrap();
+        assert_eq!(tmp.fs_type.as_deref(), Some("tmpfs"));
+        assert_eq!(tmp.spans.len(), 2);
+
+        let partitions = configured_partitions(&filesystems);
+        assert!(!partitions.contains_key(Path::new("none")));
+    }
+
+    #[test]
+    fn test_parse_luks_devices() {
+        let config = r#"{ ... }:
+{
+  boot.initrd.luks.devices."crypt-data" = {
+    device = "/dev/disk/by-uuid/luks-data";
+  };
+  boot.initrd = {
+    luks.devices.crypt-home.device = "/dev/disk/by-uuid/luks-home";
+  };
+  environment.etc."crypttab".text = ''
+    # name device key options
+    crypt-backup UUID=luks-backup /etc/cryptsetup-keys.d/crypt-backup.key luks,nofail
+  '';
+
+  fileSystems."/media/data" = {
+    device = "/dev/mapper/crypt-data";
+    fsType = "btrfs";
+  };
+  fileSystems."/media/backup" = {
+    device = "/dev/mapper/crypt-backup";
+    fsType = "ext4";
+  };
+}
+"#;
+        let filesystems = parse_nix_filesystems(config).unwrap();
+        let names: Vec<_> = filesystems
+            .luks_devices
+            .iter()
+            .map(|d| (d.name.as_str(), d.unlock, d.nested))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("crypt-data", Unlock::Initrd, false),
+                ("crypt-home", Unlock::Initrd, true),
+                ("crypt-backup", Unlock::Crypttab, false),
+            ]
+        );
+        let backup = filesystems.luks_device("crypt-backup").unwrap();
+        assert_eq!(
+            backup.device.as_deref(),
+            Some("/dev/disk/by-uuid/luks-backup")
+        );
+        assert_eq!(
+            backup.key_file.as_deref(),
+            Some("/etc/cryptsetup-keys.d/crypt-backup.key")
+        );
+
+        let partitions = configured_partitions(&filesystems);
+        let data = &partitions[Path::new("/dev/disk/by-uuid/luks-data")];
+        assert_eq!(data.mount_points, vec!["/media/data"]);
+        assert_eq!(data.mount_fs_type("/media/data"), Some("btrfs"));
+        assert_eq!(data.mount_device(), Path::new("/dev/mapper/crypt-data"));
+        let home = &partitions[Path::new("/dev/disk/by-uuid/luks-home")];
+        assert!(home.mount_points.is_empty());
+        assert_eq!(home.encryption.as_ref().unwrap().name, "crypt-home");
+        assert!(!partitions.contains_key(Path::new("/dev/mapper/crypt-backup")));
+    }
+
+    fn holder(name: &str, kind: LayerKind, mapper_name: Option<&str>, fs: &str) -> BlockHolder {
+        BlockHolder {
+            name: name.to_string(),
+            kind,
+            mapper_name: mapper_name.map(str::to_string),
+            size: 1_000_000,
+            read_only: false,
+            fs_type: Some(fs.to_string()),
+            label: None,
+            uuid: Some(format!("{}-uuid", name)),
+            holders: Vec::new(),
+        }
+    }
+
+    fn partition(name: &str, fs: &str, holders: Vec<BlockHolder>) -> BlockPartition {
+        BlockPartition {
+            name: name.to_string(),
+            number: 1,
+            size: 1_000_000,
+            fs_type: Some(fs.to_string()),
+            uuid: Some(format!("{}-uuid", name)),
+            holders,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_stacked_disks() {
+        let mut cryptroot = holder("dm-0", LayerKind::Luks, Some("cryptroot"), "LVM2_member");
+        cryptroot.holders = vec![
+            holder("dm-1", LayerKind::Lvm, Some("vg-root"), "ext4"),
+            holder("dm-2", LayerKind::Lvm, Some("vg-home"), "ext4"),
+        ];
+        let raid = holder("md0", LayerKind::Raid, None, "ext4");
+        let devices = vec![
+            BlockDevice {
+                name: "sda".to_string(),
+                partitions: vec![
+                    partition("sda1", "vfat", Vec::new()),
+                    partition("sda2", "crypto_LUKS", vec![cryptroot]),
+                ],
+                ..Default::default()
+            },
+            BlockDevice {
+                name: "sdb".to_string(),
+                partitions: vec![partition(
+                    "sdb1",
+                    "crypto_LUKS",
+                    vec![holder("dm-3", LayerKind::Luks, Some("crypt-data"), "xfs")],
+                )],
+                ..Default::default()
+            },
+            BlockDevice {
+                name: "sdc".to_string(),
+                holders: vec![raid.clone()],
+                ..Default::default()
+            },
+            BlockDevice {
+                name: "sdd".to_string(),
+                holders: vec![raid],
+                ..Default::default()
+            },
+        ];
+        let config = r#"{
+  boot.initrd.luks.devices."crypt-data".device = "/dev/sdb1";
+  fileSystems."/media/data".device = "/dev/mapper/crypt-data";
+  fileSystems."/home" = { device = "/dev/mapper/vg-home"; fsType = "ext4"; };
+}"#;
+        let disks = build_disks(devices, &[config]).unwrap();
+
+        let paths: Vec<&Path> = disks[0]
+            .partitions
+            .iter()
+            .map(|p| p.path.as_path())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("/dev/sda1"),
+                Path::new("/dev/sda2"),
+                Path::new("/dev/mapper/vg-root"),
+                Path::new("/dev/mapper/vg-home"),
+            ]
+        );
+        let root = &disks[0].partitions[2];
+        assert_eq!(root.uuid_path, Path::new("/dev/disk/by-uuid/dm-1-uuid"));
+        assert!(root.mount_points.is_empty());
+        let home = &disks[0].partitions[3];
+        assert_eq!(home.mount_points, vec!["/home"]);
+        assert_eq!(home.mount_device(), Path::new("/dev/mapper/vg-home"));
+        assert_eq!(
+            home.stack_description().as_deref(),
+            Some("sda2 → LUKS cryptroot → LVM vg-home → ext4")
+        );
+        assert_eq!(disks[0].partitions[1].stack_description(), None);
+
+        // The opened volume of a declared LUKS partition is managed by it
+        assert_eq!(disks[1].partitions.len(), 1);
+        let data = &disks[1].partitions[0];
+        assert_eq!(data.mount_points, vec!["/media/data"]);
+        assert_eq!(data.mount_fs_type("/media/data"), Some("xfs"));
+
+        // An array over two disks is listed once
+        assert_eq!(disks[2].partitions.len(), 1);
+        assert!(disks[3].partitions.is_empty());
+        assert!(!disks[3].is_blank());
+        assert_eq!(
+            disks[2].partitions[0].stack_description().as_deref(),
+            Some("sdc + sdd → RAID md0 → ext4")
+        );
+
+        let filesystems = parse_nix_filesystems(config).unwrap();
+        let configured: Vec<Partition> =
+            configured_partitions(&filesystems).into_values().collect();
+        assert!(find_missing_partitions(&configured, &disks).is_empty());
+    }
+}
@@ -0,0 +1,920 @@
+use crate::models::{
+    Disk, Encryption, FileSystemEntry, LuksDevice, NixFileSystems, Partition, Unlock,
+};
+use crate::utils::format::{FormatOptions, Owner};
+use crate::utils::nix_parser::quote;
+use crate::utils::parse_nix_filesystems;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// List of critical mount points that should NEVER be removed
+pub(crate) const CRITICAL_MOUNT_POINTS: &[&str] =
+    &["/", "/boot", "/boot/efi", "/nix", "/nix/store"];
+
+/// Check if a mount point is critical for system boot/operation
+pub fn is_critical_mount_point(mount_point: &str) -> bool {
+    CRITICAL_MOUNT_POINTS.contains(&mount_point)
+}
+
+/// Entries nix-disk is allowed to rewrite: the ones pointing at a partition or
+/// LUKS volume it knows about, present or forgotten.
+/// Unplugged disks, tmpfs, network shares, bind mounts and labels are left alone.
+fn is_managed_entry(entry: &FileSystemEntry, known_devices: &HashSet<String>) -> bool {
+    entry
+        .device
+        .as_deref()
+        .is_some_and(|d| known_devices.contains(d))
+}
+
+/// A replacement of `range` in the original source
+pub(crate) struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Grow a removed span to the whole lines it occupies, so that deleting an
+/// entry does not leave an empty indented line behind
+pub(crate) fn expand_to_lines(source: &str, span: &Range<usize>) -> Range<usize> {
+    let line_start = source[..span.start].rfind('\n').map_or(0, |p| p + 1);
+    let line_end = source[span.end..]
+        .find('\n')
+        .map_or(source.len(), |p| span.end + p + 1);
+
+    if !source[line_start..span.start].trim().is_empty()
+        || !source[span.end..line_end].trim().is_empty()
+    {
+        return span.clone();
+    }
+
+    // Also swallow the blank line separating the entry from the previous one
+    let next_line_blank = source[line_end..]
+        .split('\n')
+        .next()
+        .is_none_or(|line| line.trim().is_empty() || line.trim() == "}");
+    if next_line_blank && line_start > 0 {
+        let prev_start = source[..line_start - 1].rfind('\n').map_or(0, |p| p + 1);
+        let prev_line = &source[prev_start..line_start];
+        if prev_line.trim().is_empty() {
+            return prev_start..line_end;
+        }
+        // or, for the first entry of a block, the one after it
+        if prev_line.trim_end().ends_with('{')
+            && let Some(next_end) = source[line_end..].find('\n')
+            && source[line_end..line_end + next_end].trim().is_empty()
+        {
+            return line_start..line_end + next_end + 1;
+        }
+    }
+    line_start..line_end
+}
+
+/// Deletions of `ranges`, merging the overlapping ones (several bindings on the same line)
+pub(crate) fn removal_edits(mut ranges: Vec<Range<usize>>) -> Vec<Edit> {
+    ranges.sort_by_key(|r| r.start);
+    let mut edits: Vec<Edit> = Vec::new();
+    for range in ranges {
+        match edits.last_mut() {
+            Some(last) if last.range.end >= range.start => {
+                last.range.end = last.range.end.max(range.end);
+            }
+            _ => edits.push(Edit {
+                range,
+                text: String::new(),
+            }),
+        }
+    }
+    edits
+}
+
+/// Leading whitespace of the line containing `offset`
+pub(crate) fn line_indent(source: &str, offset: usize) -> &str {
+    let line_start = source[..offset].rfind('\n').map_or(0, |p| p + 1);
+    let line = &source[line_start..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Render a new `fileSystems` entry
+fn render_entry(mount_point: &str, partition: &Partition, indent: &str, nested: bool) -> String {
+    let device = quote(&partition.mount_device().display().to_string());
+    let fs_type = partition.mount_fs_type(mount_point).unwrap_or("auto");
+    let fs_options = render_string_list(
+        &partition
+            .settings(mount_point)
+            .options
+            .or_else(|| partition.default_options.clone())
+            .unwrap_or_else(|| get_filesystem_options(fs_type, mount_point, None, None)),
+    );
+    let name = if nested {
+        quote(mount_point)
+    } else {
+        format!("fileSystems.{}", quote(mount_point))
+    };
+    let fs_type = quote(fs_type);
+
+    format!(
+        "{indent}{name} = {{\n\
+         {indent}  device = {device};\n\
+         {indent}  fsType = {fs_type};\n\
+         {indent}  options = {fs_options};\n\
+         {indent}}};"
+    )
+}
+
+/// Generate NixOS disk configuration from disk list
+///
+/// Only the entries whose mount points changed are touched: removed mount
+/// points have their bindings deleted, moved or edited ones get their
+/// `device`/`fsType`/`options` literals replaced and new ones are inserted
+/// after the last existing entry.
+/// Every other byte of the file is kept as is.
+///
+/// `forgotten` are configured partitions that are gone for good, missing
+/// ones the user chose to remove or the former partitions of a reformatted
+/// disk: their entries and LUKS volumes are deleted too.
+pub fn get_nix_disks_config(
+    nix_config: &str,
+    disks: &[Disk],
+    forgotten: &[Partition],
+) -> Result<String> {
+    let filesystems = parse_nix_filesystems(nix_config)?;
+
+    // Mount points we want, in disk order
+    let mut wanted: Vec<(&str, &Partition)> = Vec::new();
+    let mut known_devices = HashSet::new();
+    for partition in disks.iter().flat_map(|d| d.partitions.iter()) {
+        known_devices.insert(partition.uuid_path.display().to_string());
+        known_devices.insert(partition.path.display().to_string());
+        known_devices.insert(partition.mount_device().display().to_string());
+        for mount_point in &partition.mount_points {
+            if !is_critical_mount_point(mount_point)
+                && !wanted.iter().any(|(mp, _)| mp == mount_point)
+            {
+                wanted.push((mount_point, partition));
+            }
+        }
+    }
+    // Their paths may be the ones of new partitions already
+    for partition in forgotten {
+        known_devices.insert(partition.uuid_path.display().to_string());
+        known_devices.insert(partition.mount_device().display().to_string());
+    }
+
+    let mut removals: Vec<Range<usize>> = Vec::new();
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut to_add: Vec<(&str, &Partition)> = Vec::new();
+
+    for entry in &filesystems.entries {
+        if is_critical_mount_point(&entry.mount_point) || !is_managed_entry(entry, &known_devices) {
+            continue;
+        }
+
+        let Some((_, partition)) = wanted.iter().find(|(mp, _)| *mp == entry.mount_point) else {
+            eprintln!("🔧 Suppression de l'entrée: {}", entry.mount_point);
+            removals.extend(entry.spans.iter().map(|s| expand_to_lines(nix_config, s)));
+            continue;
+        };
+
+        let device = partition.mount_device().display().to_string();
+        if entry.device.as_deref() != Some(device.as_str()) {
+            match &entry.device_span {
+                Some(span) => edits.push(Edit {
+                    range: span.clone(),
+                    text: quote(&device),
+                }),
+                None => {
+                    // Device is not a plain literal, rewrite the whole entry
+                    removals.extend(entry.spans.iter().map(|s| expand_to_lines(nix_config, s)));
+                    to_add.push((&entry.mount_point, partition));
+                    continue;
+                }
+            }
+        }
+
+        if let (Some(fs_type), Some(span)) = (
+            partition.mount_fs_type(&entry.mount_point),
+            &entry.fs_type_span,
+        ) && entry.fs_type.as_deref() != Some(fs_type)
+        {
+            edits.push(Edit {
+                range: span.clone(),
+                text: quote(fs_type),
+            });
+        }
+
+        if let Some(options) = partition.settings(&entry.mount_point).options
+            && options != entry.options
+        {
+            match &entry.options_span {
+                Some(span) => edits.push(Edit {
+                    range: span.clone(),
+                    text: render_string_list(&options),
+                }),
+                None => {
+                    // No plain list to replace, rewrite the whole entry
+                    edits.retain(|edit| !entry.spans.iter().any(|s| s.contains(&edit.range.start)));
+                    removals.extend(entry.spans.iter().map(|s| expand_to_lines(nix_config, s)));
+                    to_add.push((&entry.mount_point, partition));
+                }
+            }
+        }
+    }
+
+    for (mount_point, partition) in &wanted {
+        match filesystems.get(mount_point) {
+            None => to_add.push((mount_point, partition)),
+            Some(entry) if !is_managed_entry(entry, &known_devices) => {
+                eprintln!(
+                    "⚠️  {} est déjà défini pour un autre périphérique, entrée ignorée",
+                    mount_point
+                );
+            }
+            Some(_) => {}
+        }
+    }
+
+    edits.extend(removal_edits(removals));
+
+    // Blocks appended to the module itself, LUKS volumes before the
+    // filesystems opened from them
+    let mut module_blocks: Vec<String> = Vec::new();
+    let volumes = luks_edits(
+        nix_config,
+        &filesystems,
+        disks,
+        forgotten,
+        &mut edits,
+        &mut module_blocks,
+    )?;
+
+    if !to_add.is_empty() {
+        // Append after the last entry that is kept, in the same style
+        let anchor = filesystems
+            .entries
+            .iter()
+            .filter(|e| {
+                !edits
+                    .iter()
+                    .any(|edit| edit.text.is_empty() && edit.range.contains(&e.spans[0].start))
+            })
+            .max_by_key(|e| e.spans.last().map_or(0, |s| s.end));
+
+        match anchor {
+            Some(anchor) => {
+                let indent = line_indent(nix_config, anchor.spans[0].start);
+                let blocks: Vec<String> = to_add
+                    .iter()
+                    .map(|(mp, p)| render_entry(mp, p, indent, anchor.nested))
+                    .collect();
+                let end = anchor.spans.last().map_or(0, |s| s.end);
+                edits.push(Edit {
+                    range: end..end,
+                    text: format!("\n\n{}", blocks.join("\n\n")),
+                });
+            }
+            None => module_blocks.extend(
+                to_add
+                    .iter()
+                    .map(|(mp, p)| render_entry(mp, p, "  ", false)),
+            ),
+        }
+    }
+
+    if !module_blocks.is_empty() {
+        let Some(module_span) = &filesystems.module_span else {
+            bail!("No attribute set found to add fileSystems entries to");
+        };
+        edits.push(append_to_attr_set(
+            nix_config,
+            module_span,
+            &module_blocks.join("\n\n"),
+        ));
+    }
+
+    eprintln!(
+        "🔧 Entrées fileSystems ajoutées: {}, volumes LUKS: {}, modifications: {}",
+        to_add.len(),
+        volumes,
+        edits.len()
+    );
+
+    Ok(apply_edits(nix_config, edits))
+}
+
+/// Declare the LUKS volumes of `disks` missing from the configuration and
+/// drop the ones nothing holds anymore; returns how many were added
+///
+/// A declaration is dropped when its device is a forgotten partition or a
+/// partition of `disks` that is not encrypted anymore; the volumes of
+/// unplugged disks are kept. A name declared for a device that is not
+/// plugged in is taken over by the new volume, only one of them can be
+/// opened under that name.
+///
+/// `boot.initrd.luks.devices` entries go after the last existing one or at
+/// the end of the module, crypttab lines at the end of its text.
+fn luks_edits(
+    source: &str,
+    filesystems: &NixFileSystems,
+    disks: &[Disk],
+    forgotten: &[Partition],
+    edits: &mut Vec<Edit>,
+    module_blocks: &mut Vec<String>,
+) -> Result<usize> {
+    let partitions: Vec<&Partition> = disks.iter().flat_map(|d| d.partitions.iter()).collect();
+    let encrypted: HashSet<String> = partitions
+        .iter()
+        .filter(|p| p.encryption.is_some())
+        .map(|p| p.uuid_path.display().to_string())
+        .collect();
+    let stale: HashSet<String> = forgotten
+        .iter()
+        .chain(
+            partitions
+                .iter()
+                .copied()
+                .filter(|p| p.encryption.is_none()),
+        )
+        .map(|p| p.uuid_path.display().to_string())
+        .filter(|device| !encrypted.contains(device))
+        .collect();
+    let present = |device: &str| {
+        partitions
+            .iter()
+            .any(|p| p.uuid_path.display().to_string() == device)
+    };
+
+    let mut dropped: Vec<&LuksDevice> = filesystems
+        .luks_devices
+        .iter()
+        .filter(|d| d.device.as_deref().is_some_and(|dev| stale.contains(dev)))
+        .collect();
+
+    let mut initrd: Vec<(&Encryption, &Partition)> = Vec::new();
+    let mut crypttab: Vec<(&Encryption, &Partition)> = Vec::new();
+    for partition in &partitions {
+        let Some(encryption) = &partition.encryption else {
+            continue;
+        };
+        let device = partition.uuid_path.display().to_string();
+        let declared: Vec<&LuksDevice> = filesystems
+            .luks_devices
+            .iter()
+            .filter(|d| d.name == encryption.name && !dropped.contains(d))
+            .collect();
+        if declared
+            .iter()
+            .any(|d| d.device.as_deref() == Some(&device))
+        {
+            continue;
+        }
+        if let Some(other) = declared
+            .iter()
+            .find(|d| d.device.as_deref().is_some_and(&present))
+        {
+            bail!(
+                "The LUKS volume {} is already declared for {}",
+                encryption.name,
+                other.device.as_deref().unwrap_or_default()
+            );
+        }
+        dropped.extend(declared);
+        if initrd
+            .iter()
+            .chain(&crypttab)
+            .any(|(e, _)| e.name == encryption.name)
+        {
+            bail!(
+                "Two partitions use the LUKS volume name {}",
+                encryption.name
+            );
+        }
+        eprintln!("🔐 Ajout du volume LUKS: {}", encryption.name);
+        match encryption.unlock {
+            Unlock::Initrd => initrd.push((encryption, partition)),
+            Unlock::Crypttab => crypttab.push((encryption, partition)),
+        }
+    }
+
+    let mut removals: Vec<Range<usize>> = Vec::new();
+    for device in &dropped {
+        eprintln!("🔐 Retrait du volume LUKS: {}", device.name);
+        match device.unlock {
+            Unlock::Initrd => {
+                removals.extend(device.spans.iter().map(|s| expand_to_lines(source, s)))
+            }
+            Unlock::Crypttab => {
+                let line = filesystems
+                    .crypttab_span
+                    .as_ref()
+                    .and_then(|span| crypttab_line(source, span, &device.name));
+                match line {
+                    Some(line) => removals.push(line),
+                    None => bail!(
+                        "The crypttab line of {} cannot be removed, remove it by hand",
+                        device.name
+                    ),
+                }
+            }
+        }
+    }
+    edits.extend(removal_edits(removals));
+
+    if !initrd.is_empty() {
+        let anchor = filesystems
+            .luks_devices
+            .iter()
+            .filter(|d| !d.nested && !dropped.contains(d))
+            .filter_map(|d| d.spans.last())
+            .max_by_key(|s| s.end);
+        match anchor {
+            Some(anchor) => {
+                let indent = line_indent(source, anchor.start);
+                let blocks: Vec<String> = initrd
+                    .iter()
+                    .map(|(e, p)| render_luks_device(e, p, indent))
+                    .collect();
+                edits.push(Edit {
+                    range: anchor.end..anchor.end,
+                    text: format!("\n\n{}", blocks.join("\n\n")),
+                });
+            }
+            None => {
+                module_blocks.extend(initrd.iter().map(|(e, p)| render_luks_device(e, p, "  ")))
+            }
+        }
+    }
+
+    if !crypttab.is_empty() {
+        let lines: Vec<String> = crypttab
+            .iter()
+            .map(|(e, p)| render_crypttab_line(e, p))
+            .collect();
+        match &filesystems.crypttab_span {
+            Some(span) => edits.push(append_to_indented_string(source, span, &lines)?),
+            None => module_blocks.push(format!(
+                "  environment.etc.\"crypttab\".text = ''\n{}\n  '';",
+                lines
+                    .iter()
+                    .map(|line| format!("    {}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )),
+        }
+    }
+
+    Ok(initrd.len() + crypttab.len())
+}
+
+/// The whole line of the crypttab text at `span` declaring `name`
+fn crypttab_line(source: &str, span: &Range<usize>, name: &str) -> Option<Range<usize>> {
+    let mut start = span.start;
+    for line in source[span.clone()].split_inclusive('\n') {
+        let end = start + line.len();
+        if line.ends_with('\n') && line.split_whitespace().next() == Some(name) {
+            return Some(start..end);
+        }
+        start = end;
+    }
+    None
+}
+
+/// Render a new `boot.initrd.luks.devices` entry
+fn render_luks_device(encryption: &Encryption, partition: &Partition, indent: &str) -> String {
+    let name = quote(&encryption.name);
+    let device = quote(&partition.uuid_path.display().to_string());
+    let key_file = encryption
+        .key_file
+        .as_deref()
+        .map(|key_file| format!("{indent}  keyFile = {};\n", quote(key_file)))
+        .unwrap_or_default();
+    format!(
+        "{indent}boot.initrd.luks.devices.{name} = {{\n\
+         {indent}  device = {device};\n\
+         {key_file}\
+         {indent}}};"
+    )
+}
+
+/// `name device key-file options`, `none` asking for the passphrase
+fn render_crypttab_line(encryption: &Encryption, partition: &Partition) -> String {
+    format!(
+        "{} {} {} luks,nofail",
+        encryption.name,
+        partition.uuid_path.display(),
+        encryption.key_file.as_deref().unwrap_or("none")
+    )
+}
+
+/// Add `lines` at the end of the indented string at `span`, whose closing
+/// `''` must be on a line of its own
+fn append_to_indented_string(source: &str, span: &Range<usize>, lines: &[String]) -> Result<Edit> {
+    let text = &source[span.clone()];
+    let closing = span.end.saturating_sub(2);
+    let line_start = source[..closing].rfind('\n').map_or(0, |p| p + 1);
+    if !text.starts_with("''")
+        || line_start <= span.start
+        || !source[line_start..closing].trim().is_empty()
+    {
+        bail!(
+            "environment.etc.\"crypttab\".text is not a multi-line '' string, add the volumes by hand"
+        );
+    }
+    // Same indentation as the last line of text
+    let last_line_start = source[..line_start - 1].rfind('\n').map_or(0, |p| p + 1);
+    let indent = if last_line_start > span.start {
+        line_indent(source, last_line_start).to_string()
+    } else {
+        format!("{}  ", &source[line_start..closing])
+    };
+    Ok(Edit {
+        range: line_start..line_start,
+        text: lines
+            .iter()
+            .map(|line| format!("{}{}\n", indent, line))
+            .collect(),
+    })
+}
+
+/// Insert `text` as the last lines of the attribute set at `span`
+pub(crate) fn append_to_attr_set(source: &str, span: &Range<usize>, text: &str) -> Edit {
+    let closing = span.end - 1;
+    let line_start = source[..closing].rfind('\n').map_or(0, |p| p + 1);
+
+    if line_start > span.start && source[line_start..closing].trim().is_empty() {
+        // Keep a blank line between the existing content and the new one
+        let separator = if source[span.start + 1..line_start].trim().is_empty() {
+            ""
+        } else {
+            "\n"
+        };
+        Edit {
+            range: line_start..line_start,
+            text: format!("{}{}\n", separator, text),
+        }
+    } else {
+        Edit {
+            range: closing..closing,
+            text: format!("\n{}\n", text),
+        }
+    }
+}
+
+/// Apply non-overlapping edits to `source`
+pub(crate) fn apply_edits(source: &str, mut edits: Vec<Edit>) -> String {
+    // Apply from the end so that earlier offsets stay valid
+    let mut result = source.to_string();
+    edits.sort_by_key(|e| std::cmp::Reverse(e.range.start));
+    for edit in edits {
+        result.replace_range(edit.range, &edit.text);
+    }
+    result
+}
+
+/// Default mount options of a filesystem type, for entries nix-disk adds
+///
+/// `format` are the options the partition was formatted with and `owner`
+/// the user its files were given to, when nix-disk formatted it: btrfs is
+/// compressed like its root directory, and filesystems without owners get
+/// the ones of `owner` rather than the first user.
+pub fn get_filesystem_options(
+    fs_type: &str,
+    mount_point: &str,
+    format: Option<&FormatOptions>,
+    owner: Option<Owner>,
+) -> Vec<String> {
+    let mut options: Vec<String> = ["defaults", "nofail", "x-gvfs-show"]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    match fs_type {
+        "btrfs" => {
+            let compression = match format {
+                Some(format) => format.compression.as_deref(),
+                None => Some("zstd"),
+            };
+            if let Some(compression) = compression {
+                options.push(format!("compress={}", compression));
+            }
+        }
+        // No owners on disk, files belong to the first user
+        "ntfs" | "ntfs3" | "exfat" | "vfat" => {
+            let owner = owner.unwrap_or(Owner {
+                uid: 1000,
+                gid: 100,
+            });
+            options.push(format!("uid={}", owner.uid));
+            options.push(format!("gid={}", owner.gid));
+            options.push("umask=022".to_string());
+        }
+        _ => {}
+    }
+
+    // Remove x-gvfs-show for root and boot partitions
+    if mount_point == "/" || mount_point == "/boot" {
+        options.retain(|opt| opt != "x-gvfs-show");
+    }
+
+    options
+}
+
+/// `[ "a" "b" ]`
+fn render_string_list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    format!(
+        "[ {} ]",
+        items.iter().map(|s| quote(s)).collect::<Vec<_>>().join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MountSettings;
+    use crate::utils::format::FsType;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_filesystem_options() {
+        let btrfs_opts = get_filesystem_options("btrfs", "/media/data", None, None);
+        assert!(btrfs_opts.iter().any(|o| o == "compress=zstd"));
+        assert!(btrfs_opts.iter().any(|o| o == "x-gvfs-show"));
+
+        let exfat_opts = get_filesystem_options("exfat", "/media/shared", None, None);
+        assert!(exfat_opts.iter().any(|o| o == "uid=1000"));
+
+        let root_opts = get_filesystem_options("ext4", "/", None, None);
+        assert!(!root_opts.iter().any(|o| o == "x-gvfs-show"));
+    }
+
+    #[test]
+    fn test_formatted_filesystem_options() {
+        let format = FormatOptions {
+            compression: None,
+            ..FormatOptions::new(FsType::Btrfs, "data")
+        };
+        let btrfs_opts = get_filesystem_options("btrfs", "/media/data", Some(&format), None);
+        assert!(!btrfs_opts.iter().any(|o| o.starts_with("compress=")));
+
+        let format = FormatOptions {
+            compression: Some("lzo".to_string()),
+            ..format
+        };
+        let btrfs_opts = get_filesystem_options("btrfs", "/media/data", Some(&format), None);
+        assert!(btrfs_opts.iter().any(|o| o == "compress=lzo"));
+
+        let owner = Owner {
+            uid: 1001,
+            gid: 1001,
+        };
+        let format = FormatOptions::new(FsType::Exfat, "shared");
+        let exfat_opts =
+            get_filesystem_options("exfat", "/media/shared", Some(&format), Some(owner));
+        assert!(exfat_opts.iter().any(|o| o == "uid=1001"));
+        assert!(exfat_opts.iter().any(|o| o == "gid=1001"));
+    }
+
+    const CONFIG: &str = r#"{ config, lib, ... }:
+
+{
+  fileSystems."/" =
+    { device = "/dev/disk/by-uuid/root";
+      fsType = "ext4";
+    };
+
+  # Photos, keep the custom options }
+  fileSystems."/media/photos" = {
+    device = "/dev/disk/by-uuid/old";
+    fsType = "ext4";
+    options = [ "noatime" ];
+  };
+
+  fileSystems."/media/old" = {
+    device = "/dev/disk/by-uuid/gone";
+    fsType = "ext4";
+  };
+
+  fileSystems."/mnt/nas" = {
+    device = "nas:/export";
+    fsType = "nfs";
+  };
+
+  swapDevices = [ ];
+}
+"#;
+
+    fn partition(uuid: &str, fs_type: &str, mount_points: &[&str]) -> Partition {
+        Partition::new(
+            PathBuf::from(format!("/dev/{}", uuid)),
+            PathBuf::from(format!("/dev/disk/by-uuid/{}", uuid)),
+            mount_points.iter().map(|mp| mp.to_string()).collect(),
+            Some(fs_type.to_string()),
+            0,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_unchanged_config_is_untouched() {
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sda"),
+            vec![
+                partition("root", "ext4", &["/"]),
+                partition("old", "ext4", &["/media/photos"]),
+                partition("gone", "ext4", &["/media/old"]),
+            ],
+            0,
+        )];
+        assert_eq!(get_nix_disks_config(CONFIG, &disks, &[]).unwrap(), CONFIG);
+    }
+
+    #[test]
+    fn test_minimal_edits() {
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sda"),
+            vec![
+                partition("root", "ext4", &["/"]),
+                partition("new", "btrfs", &["/media/photos", "/media/data"]),
+            ],
+            0,
+        )];
+        let forgotten = [
+            partition("old", "ext4", &["/media/photos"]),
+            partition("gone", "ext4", &["/media/old"]),
+        ];
+        let config = get_nix_disks_config(CONFIG, &disks, &forgotten).unwrap();
+
+        assert!(config.contains("# Photos, keep the custom options }"));
+        assert!(config.contains(
+            "    device = \"/dev/disk/by-uuid/new\";\n    fsType = \"btrfs\";\n    options = [ \"noatime\" ];"
+        ));
+        assert!(!config.contains("/media/old"));
+        assert!(config.contains("fileSystems.\"/mnt/nas\""));
+        assert!(config.contains(
+            "    fsType = \"nfs\";\n  };\n\n  fileSystems.\"/media/data\" = {\n    device = \"/dev/disk/by-uuid/new\";"
+        ));
+        assert!(config.ends_with("  swapDevices = [ ];\n}\n"));
+        assert!(!config.contains("\n\n\n"));
+    }
+
+    #[test]
+    fn test_unplugged_disks_are_kept() {
+        // The disks of /media/photos and /media/old are not plugged in
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sda"),
+            vec![
+                partition("root", "ext4", &["/"]),
+                partition("new", "btrfs", &["/media/data"]),
+            ],
+            0,
+        )];
+        let config = get_nix_disks_config(CONFIG, &disks, &[]).unwrap();
+        assert!(config.starts_with(&CONFIG[..CONFIG.find("  swapDevices").unwrap()]));
+        assert!(config.contains("fileSystems.\"/media/data\""));
+
+        // Nor taken over by another partition
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sda"),
+            vec![partition("new", "btrfs", &["/media/photos"])],
+            0,
+        )];
+        assert_eq!(get_nix_disks_config(CONFIG, &disks, &[]).unwrap(), CONFIG);
+    }
+
+    #[test]
+    fn test_edit_mount_settings() {
+        let mut photos = partition("old", "ntfs", &["/media/photos"]);
+        photos.mount_settings.insert(
+            "/media/photos".to_string(),
+            MountSettings {
+                fs_type: Some("ntfs3".to_string()),
+                options: Some(vec!["ro".to_string(), "noatime".to_string()]),
+            },
+        );
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sda"),
+            vec![
+                partition("root", "ext4", &["/"]),
+                photos,
+                partition("gone", "ext4", &["/media/old"]),
+            ],
+            0,
+        )];
+        let config = get_nix_disks_config(CONFIG, &disks, &[]).unwrap();
+        assert!(config.contains("    fsType = \"ntfs3\";\n    options = [ \"ro\" \"noatime\" ];"));
+        assert!(config.contains("# Photos, keep the custom options }"));
+    }
+
+    #[test]
+    fn test_insert_without_entries() {
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sdb"),
+            vec![partition("data", "ext4", &["/media/data"])],
+            0,
+        )];
+        let config =
+            get_nix_disks_config("{ ... }:\n{\n  imports = [ ];\n}\n", &disks, &[]).unwrap();
+        assert_eq!(
+            config,
+            "{ ... }:\n{\n  imports = [ ];\n\n  fileSystems.\"/media/data\" = {\n    device = \"/dev/disk/by-uuid/data\";\n    fsType = \"ext4\";\n    options = [ \"defaults\" \"nofail\" \"x-gvfs-show\" ];\n  };\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_add_encrypted_volumes() {
+        let encrypted = |uuid: &str, name: &str, unlock: Unlock, key_file: Option<&str>| {
+            let mut partition = partition(uuid, "crypto_LUKS", &[]);
+            partition.encryption = Some(Encryption {
+                name: name.to_string(),
+                unlock,
+                key_file: key_file.map(str::to_string),
+                fs_type: Some("ext4".to_string()),
+            });
+            partition
+        };
+        let mut vault = encrypted("vault", "crypt-vault", Unlock::Initrd, None);
+        vault.add_mount_point("/media/vault".to_string());
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sdb"),
+            vec![
+                vault,
+                encrypted(
+                    "backup",
+                    "crypt-backup",
+                    Unlock::Crypttab,
+                    Some("/etc/cryptsetup-keys.d/crypt-backup.key"),
+                ),
+            ],
+            0,
+        )];
+        let config =
+            get_nix_disks_config("{ ... }:\n{\n  imports = [ ];\n}\n", &disks, &[]).unwrap();
+        assert_eq!(
+            config,
+            "{ ... }:\n{\n  imports = [ ];\n\n  boot.initrd.luks.devices.\"crypt-vault\" = {\n    device = \"/dev/disk/by-uuid/vault\";\n  };\n\n  environment.etc.\"crypttab\".text = ''\n    crypt-backup /dev/disk/by-uuid/backup /etc/cryptsetup-keys.d/crypt-backup.key luks,nofail\n  '';\n\n  fileSystems.\"/media/vault\" = {\n    device = \"/dev/mapper/crypt-vault\";\n    fsType = \"ext4\";\n    options = [ \"defaults\" \"nofail\" \"x-gvfs-show\" ];\n  };\n}\n"
+        );
+
+        // Declared volumes are kept, new crypttab lines go after the others
+        let parsed = parse_nix_filesystems(&config).unwrap();
+        assert_eq!(parsed.luks_devices.len(), 2);
+        assert_eq!(get_nix_disks_config(&config, &disks, &[]).unwrap(), config);
+        let mut more = disks.clone();
+        more[0]
+            .partitions
+            .push(encrypted("photos", "crypt-photos", Unlock::Crypttab, None));
+        let config = get_nix_disks_config(&config, &more, &[]).unwrap();
+        assert!(config.contains(
+            "luks,nofail\n    crypt-photos /dev/disk/by-uuid/photos none luks,nofail\n  '';"
+        ));
+    }
+
+    #[test]
+    fn test_replace_encrypted_volumes() {
+        let encrypted = |uuid: &str, name: &str, unlock: Unlock| {
+            let mut partition = partition(uuid, "crypto_LUKS", &[]);
+            partition.encryption = Some(Encryption {
+                name: name.to_string(),
+                unlock,
+                key_file: None,
+                fs_type: Some("ext4".to_string()),
+            });
+            partition
+        };
+        let mut vault = encrypted("vault", "crypt-vault", Unlock::Initrd);
+        vault.add_mount_point("/media/vault".to_string());
+        let old = vec![vault, encrypted("backup", "crypt-backup", Unlock::Crypttab)];
+        let disks = vec![Disk::new(PathBuf::from("/dev/sdb"), old.clone(), 0)];
+        let config = get_nix_disks_config("{ ... }:\n{\n}\n", &disks, &[]).unwrap();
+
+        // The disk was formatted again, reusing one of the names
+        let mut new_vault = encrypted("new-vault", "crypt-vault", Unlock::Crypttab);
+        new_vault.add_mount_point("/media/vault".to_string());
+        let disks = vec![Disk::new(PathBuf::from("/dev/sdb"), vec![new_vault], 0)];
+        let formatted = get_nix_disks_config(&config, &disks, &old).unwrap();
+        assert_eq!(
+            formatted,
+            "{ ... }:\n{\n  environment.etc.\"crypttab\".text = ''\n    crypt-vault /dev/disk/by-uuid/new-vault none luks,nofail\n  '';\n\n  fileSystems.\"/media/vault\" = {\n    device = \"/dev/mapper/crypt-vault\";\n    fsType = \"ext4\";\n    options = [ \"defaults\" \"nofail\" \"x-gvfs-show\" ];\n  };\n}\n"
+        );
+
+        // A partition mounted without its volume
+        let mut plain = old.clone();
+        plain[0].encryption = None;
+        plain[0].remove_mount_point("/media/vault");
+        let disks = vec![Disk::new(PathBuf::from("/dev/sdb"), plain, 0)];
+        let config_without = get_nix_disks_config(&config, &disks, &[]).unwrap();
+        let parsed = parse_nix_filesystems(&config_without).unwrap();
+        let names: Vec<&str> = parsed
+            .luks_devices
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["crypt-backup"]);
+
+        // Two plugged-in partitions cannot share a name
+        let mut taken = old.clone();
+        taken.push(encrypted("other", "crypt-vault", Unlock::Crypttab));
+        let disks = vec![Disk::new(PathBuf::from("/dev/sdb"), taken, 0)];
+        assert!(get_nix_disks_config(&config, &disks, &[]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Writing the configuration files
+//!
+//! Files are replaced atomically, and only if nobody changed them since they
+//! were loaded, so that an edit made meanwhile in an editor is not lost.
+
+use crate::error::NixDiskError;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Replace a file atomically
+///
+/// The content goes to a temporary file of the same directory, synced to
+/// disk, then renamed over `path`: a crash leaves either the old or the new
+/// version, never a truncated one. The mode and owner of the existing file
+/// are kept, and a symlink is followed rather than replaced.
+pub fn write_file(path: &Path, content: &str) -> Result<(), NixDiskError> {
+    let target = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).map_err(|e| NixDiskError::io(path, e))?
+        }
+        _ => path.to_path_buf(),
+    };
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    // Created exclusively under a random name, and removed if anything fails
+    let mut temp = tempfile::Builder::new()
+        .prefix(".nix-disk-")
+        .tempfile_in(&dir)
+        .map_err(|e| NixDiskError::io(path, e))?;
+
+    let mut write = || -> io::Result<()> {
+        let file = temp.as_file_mut();
+        if let Ok(metadata) = fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+            // Only root can give a file away, other users keep their own files anyway
+            match std::os::unix::fs::fchown(&*file, Some(metadata.uid()), Some(metadata.gid())) {
+                Err(e) if e.kind() != io::ErrorKind::PermissionDenied => return Err(e),
+                _ => {}
+            }
+        }
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    };
+    write().map_err(|e| NixDiskError::io(path, e))?;
+    temp.persist(&target)
+        .map_err(|e| NixDiskError::io(path, e.error))?;
+    // Make the rename itself durable
+    File::open(&dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| NixDiskError::io(path, e))
+}
+
+/// Fingerprint of a file content, to notice changes made by other programs
+/// between loading a file and writing it back (FNV-1a, stable across builds)
+pub fn content_hash(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Check that `path` still has the content whose [`content_hash`] was taken
+/// when it was loaded; a missing file counts as empty
+pub fn check_unchanged(path: &Path, expected_hash: &str) -> Result<(), NixDiskError> {
+    let current = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(NixDiskError::io(path, e)),
+    };
+    if content_hash(&current) != expected_hash {
+        return Err(NixDiskError::ModifiedExternally {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_write_file_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("hardware-configuration.nix");
+        fs::write(&real, "{ }\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.path().join("link.nix");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let loaded = content_hash("{ }\n");
+        check_unchanged(&link, &loaded).unwrap();
+        write_file(&link, "{ boot.loader.grub.enable = true; }\n").unwrap();
+
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(
+            fs::read_to_string(&real).unwrap(),
+            "{ boot.loader.grub.enable = true; }\n"
+        );
+        assert_eq!(
+            fs::metadata(&real).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // No temporary file left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        assert!(matches!(
+            check_unchanged(&real, &loaded),
+            Err(NixDiskError::ModifiedExternally { .. })
+        ));
+    }
+}
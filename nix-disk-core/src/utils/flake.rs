@@ -0,0 +1,336 @@
+//! Flake-based NixOS configurations
+//!
+//! When `/etc/nixos/flake.nix` exists the system is built from one of its
+//! `nixosConfigurations`, whose hardware module can live anywhere in the
+//! flake. The host's modules are followed through their `imports` to find
+//! it.
+
+use crate::error::read_nix_file;
+use crate::utils::nix_module::{ConfigPaths, MODULE_FILE_NAME, imports_list};
+use crate::utils::nix_parser::{self, Expr};
+use anyhow::{Context, Result, bail};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Imports are followed this deep when looking for the hardware module
+const MAX_IMPORT_DEPTH: usize = 4;
+
+/// The flake output a rebuild applies
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlakeTarget {
+    pub dir: PathBuf,
+    pub host: String,
+}
+
+impl FlakeTarget {
+    /// `<dir>#<host>`, as given to `nixos-rebuild --flake`
+    pub fn flake_ref(&self) -> String {
+        format!("{}#{}", self.dir.display(), self.host)
+    }
+}
+
+/// A `nixosConfigurations.<name>` output of a flake
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlakeHost {
+    pub name: String,
+    /// Local modules passed to `nixosSystem`, resolved against the flake directory
+    pub modules: Vec<PathBuf>,
+}
+
+/// Resolve `.` and `..` without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Local file referenced by a path literal such as `./hosts/foo`
+fn path_literal(source: &str, expr: &Expr, base_dir: &Path) -> Option<PathBuf> {
+    let Expr::Other { span } = expr else {
+        return None;
+    };
+    let text = &source[span.clone()];
+    if !(text.starts_with("./") || text.starts_with("../") || text.starts_with('/')) {
+        return None;
+    }
+    let path = normalize(&base_dir.join(text));
+    // A directory module means its default.nix
+    if path.extension().is_none_or(|ext| ext != "nix") {
+        Some(path.join("default.nix"))
+    } else {
+        Some(path)
+    }
+}
+
+fn collect_hosts(source: &str, expr: &Expr, flake_dir: &Path, hosts: &mut Vec<FlakeHost>) {
+    match expr.strip_wrappers() {
+        Expr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                match binding.path_names().as_deref() {
+                    Some(["nixosConfigurations", name]) => {
+                        hosts.push(parse_host(source, name, &binding.value, flake_dir));
+                    }
+                    Some(["nixosConfigurations"]) => {
+                        if let Expr::AttrSet { bindings, .. } = binding.value.strip_wrappers() {
+                            for host in bindings {
+                                if let Some([name]) = host.path_names().as_deref() {
+                                    hosts.push(parse_host(source, name, &host.value, flake_dir));
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // `flake-utils.lib.eachDefaultSystem (...) // { nixosConfigurations = ...; }`
+        Expr::BinOp {
+            op: "//", lhs, rhs, ..
+        } => {
+            collect_hosts(source, lhs, flake_dir, hosts);
+            collect_hosts(source, rhs, flake_dir, hosts);
+        }
+        _ => {}
+    }
+}
+
+/// `nixpkgs.lib.nixosSystem { modules = [ ... ]; }`
+fn parse_host(source: &str, name: &str, value: &Expr, flake_dir: &Path) -> FlakeHost {
+    let mut modules = Vec::new();
+    if let Expr::Apply { arg, .. } = value.strip_wrappers()
+        && let Expr::AttrSet { bindings, .. } = arg.strip_wrappers()
+        && let Some(binding) = bindings
+            .iter()
+            .find(|b| b.path_names().as_deref() == Some(&["modules"][..]))
+        && let Expr::List { items, .. } = binding.value.strip_wrappers()
+    {
+        modules = items
+            .iter()
+            .filter_map(|item| path_literal(source, item, flake_dir))
+            .collect();
+    }
+
+    FlakeHost {
+        name: name.to_string(),
+        modules,
+    }
+}
+
+/// List the `nixosConfigurations` of a flake
+pub fn parse_flake_hosts(flake_source: &str, flake_dir: &Path) -> Result<Vec<FlakeHost>> {
+    let expr = nix_parser::parse(flake_source).context("Failed to parse flake.nix")?;
+    let Expr::AttrSet { bindings, .. } = expr.strip_wrappers() else {
+        bail!("flake.nix does not contain an attribute set");
+    };
+
+    let mut hosts = Vec::new();
+    if let Some(outputs) = bindings
+        .iter()
+        .find(|b| b.path_names().as_deref() == Some(&["outputs"][..]))
+    {
+        collect_hosts(flake_source, &outputs.value, flake_dir, &mut hosts);
+    }
+    Ok(hosts)
+}
+
+/// The hosts of `<config_dir>/flake.nix`, `None` for a classic configuration
+pub fn flake_hosts(config_dir: &Path) -> Result<Option<Vec<FlakeHost>>> {
+    let flake_file = config_dir.join("flake.nix");
+    if !flake_file.exists() {
+        return Ok(None);
+    }
+    let source = read_nix_file(&flake_file)?;
+    parse_flake_hosts(&source, config_dir).map(Some)
+}
+
+/// Host to use without asking: the only one, or the one named after this machine
+pub fn default_host(hosts: &[FlakeHost]) -> Option<&FlakeHost> {
+    if let [host] = hosts {
+        return Some(host);
+    }
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    hosts.iter().find(|h| h.name == hostname.trim())
+}
+
+impl ConfigPaths {
+    /// Find the hardware module of a flake host by following its imports
+    ///
+    /// nix-disk.nix goes next to it and is imported from the module that
+    /// imports hardware-configuration.nix, or from another module of the host
+    /// when hardware-configuration.nix is listed in `modules` itself.
+    pub fn for_flake_host(flake_dir: &Path, host: &FlakeHost) -> Result<Self> {
+        let mut queue: VecDeque<(PathBuf, Option<PathBuf>, usize)> =
+            host.modules.iter().map(|m| (m.clone(), None, 0)).collect();
+        let mut visited = HashSet::new();
+
+        while let Some((file, importer, depth)) = queue.pop_front() {
+            if !visited.insert(file.clone()) {
+                continue;
+            }
+
+            if file
+                .file_name()
+                .is_some_and(|n| n == "hardware-configuration.nix")
+            {
+                let dir = file.parent().unwrap_or(flake_dir);
+                let configuration = match importer {
+                    Some(importer) => importer,
+                    None => importing_module(host, &file)?,
+                };
+                return Ok(Self {
+                    module: dir.join(MODULE_FILE_NAME),
+                    hardware_config: file,
+                    configuration,
+                    flake: Some(FlakeTarget {
+                        dir: flake_dir.to_path_buf(),
+                        host: host.name.clone(),
+                    }),
+                });
+            }
+
+            if depth >= MAX_IMPORT_DEPTH {
+                continue;
+            }
+            let Ok(source) = fs::read_to_string(&file) else {
+                continue;
+            };
+            let Ok(expr) = nix_parser::parse(&source) else {
+                eprintln!("⚠️  Impossible d'analyser {}", file.display());
+                continue;
+            };
+            let base_dir = file.parent().unwrap_or(flake_dir);
+            if let Some(Expr::List { items, .. }) = imports_list(&expr) {
+                for item in items {
+                    if let Some(import) = path_literal(&source, item, base_dir) {
+                        queue.push_back((import, Some(file.clone()), depth + 1));
+                    }
+                }
+            }
+        }
+
+        bail!(
+            "No hardware-configuration.nix found in the modules of {}",
+            host.name
+        )
+    }
+}
+
+/// Module of `host` to import nix-disk.nix from when hardware-configuration.nix
+/// is listed directly in its `modules`: the configuration.nix next to it if
+/// the host uses it, its first other module otherwise
+fn importing_module(host: &FlakeHost, hardware_config: &Path) -> Result<PathBuf> {
+    let sibling = hardware_config.with_file_name("configuration.nix");
+    host.modules
+        .iter()
+        .find(|m| **m == sibling)
+        .or_else(|| {
+            host.modules
+                .iter()
+                .find(|m| *m != hardware_config && m.is_file())
+        })
+        .cloned()
+        .with_context(|| {
+            format!(
+                "{} has no module besides {} to import {} from",
+                host.name,
+                hardware_config.display(),
+                MODULE_FILE_NAME
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flake_hosts() {
+        let flake = r#"{
+  description = "My machines";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+
+  outputs = { self, nixpkgs, ... }@inputs:
+    let
+      system = "x86_64-linux";
+    in
+    {
+      nixosConfigurations.laptop = nixpkgs.lib.nixosSystem {
+        inherit system;
+        specialArgs = { inherit inputs; };
+        modules = [
+          ./hosts/laptop
+          ../shared/common.nix
+          inputs.home-manager.nixosModules.home-manager
+          { networking.hostName = "laptop"; }
+        ];
+      };
+      nixosConfigurations.server = nixpkgs.lib.nixosSystem {
+        modules = [ ./hosts/server/configuration.nix ];
+      };
+    };
+}
+"#;
+        let hosts = parse_flake_hosts(flake, Path::new("/etc/nixos")).unwrap();
+        assert_eq!(
+            hosts,
+            vec![
+                FlakeHost {
+                    name: "laptop".to_string(),
+                    modules: vec![
+                        PathBuf::from("/etc/nixos/hosts/laptop/default.nix"),
+                        PathBuf::from("/etc/shared/common.nix"),
+                    ],
+                },
+                FlakeHost {
+                    name: "server".to_string(),
+                    modules: vec![PathBuf::from("/etc/nixos/hosts/server/configuration.nix")],
+                },
+            ]
+        );
+
+        let target = FlakeTarget {
+            dir: PathBuf::from("/etc/nixos"),
+            host: "laptop".to_string(),
+        };
+        assert_eq!(target.flake_ref(), "/etc/nixos#laptop");
+    }
+
+    #[test]
+    fn test_hardware_module_listed_in_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let flake_dir = dir.path();
+        let host_dir = flake_dir.join("hosts/laptop");
+        fs::create_dir_all(&host_dir).unwrap();
+        fs::write(host_dir.join("hardware-configuration.nix"), "{ }\n").unwrap();
+        // Not a module of the host
+        fs::write(host_dir.join("configuration.nix"), "{ }\n").unwrap();
+        fs::write(flake_dir.join("common.nix"), "{ }\n").unwrap();
+
+        let mut host = FlakeHost {
+            name: "laptop".to_string(),
+            modules: vec![
+                host_dir.join("hardware-configuration.nix"),
+                flake_dir.join("common.nix"),
+            ],
+        };
+        let paths = ConfigPaths::for_flake_host(flake_dir, &host).unwrap();
+        assert_eq!(paths.configuration, flake_dir.join("common.nix"));
+        assert_eq!(paths.module, host_dir.join(MODULE_FILE_NAME));
+
+        host.modules.push(host_dir.join("configuration.nix"));
+        let paths = ConfigPaths::for_flake_host(flake_dir, &host).unwrap();
+        assert_eq!(paths.configuration, host_dir.join("configuration.nix"));
+
+        host.modules = vec![host_dir.join("hardware-configuration.nix")];
+        assert!(ConfigPaths::for_flake_host(flake_dir, &host).is_err());
+    }
+}
@@ -0,0 +1,930 @@
+//! Formatting a disk without a terminal
+//!
+//! Each step runs as its own process with its arguments passed as a vector,
+//! never through a shell, so the command line front-end can report which
+//! one failed and no label or user name is ever interpreted. LUKS
+//! passphrases are written to the standard input of `cryptsetup`, never
+//! passed as arguments.
+
+use crate::error::NixDiskError;
+use crate::models::{Encryption, Unlock};
+use crate::utils::layout::{DiskLayout, TableType};
+use crate::utils::sysfs::read_size;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Filesystems a disk can be formatted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsType {
+    Ext4,
+    Btrfs,
+    Xfs,
+    Exfat,
+    Ntfs,
+    Vfat,
+}
+
+impl FsType {
+    pub const ALL: [FsType; 6] = [
+        FsType::Ext4,
+        FsType::Btrfs,
+        FsType::Xfs,
+        FsType::Exfat,
+        FsType::Ntfs,
+        FsType::Vfat,
+    ];
+
+    /// Name used by blkid and in `fsType`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FsType::Ext4 => "ext4",
+            FsType::Btrfs => "btrfs",
+            FsType::Xfs => "xfs",
+            FsType::Exfat => "exfat",
+            FsType::Ntfs => "ntfs",
+            FsType::Vfat => "vfat",
+        }
+    }
+
+    /// Whether files have an owner, otherwise it comes from mount options
+    pub fn has_owners(self) -> bool {
+        matches!(self, FsType::Ext4 | FsType::Btrfs | FsType::Xfs)
+    }
+
+    /// Environment variable set by the Nix wrapper, and default program
+    fn mkfs_bin(self) -> (&'static str, &'static str) {
+        match self {
+            FsType::Ext4 => ("MKFS_EXT4_BIN", "mkfs.ext4"),
+            FsType::Btrfs => ("MKFS_BTRFS_BIN", "mkfs.btrfs"),
+            FsType::Xfs => ("MKFS_XFS_BIN", "mkfs.xfs"),
+            FsType::Exfat => ("MKFS_EXFAT_BIN", "mkfs.exfat"),
+            FsType::Ntfs => ("MKFS_NTFS_BIN", "mkfs.ntfs"),
+            FsType::Vfat => ("MKFS_VFAT_BIN", "mkfs.vfat"),
+        }
+    }
+
+    /// Type given to `parted mkpart`, which only sets the partition type
+    fn parted_type(self) -> &'static str {
+        match self {
+            // parted has no exfat, both get the Microsoft basic data type
+            FsType::Exfat | FsType::Ntfs => "ntfs",
+            FsType::Vfat => "fat32",
+            _ => self.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for FsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Unsupported filesystem type: {0}")]
+pub struct UnknownFsType(pub String);
+
+impl FromStr for FsType {
+    type Err = UnknownFsType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fat32" => Ok(FsType::Vfat),
+            "ntfs3" => Ok(FsType::Ntfs),
+            _ => FsType::ALL
+                .into_iter()
+                .find(|t| t.as_str() == s)
+                .ok_or_else(|| UnknownFsType(s.to_string())),
+        }
+    }
+}
+
+/// Algorithms accepted by the btrfs `compression` property
+pub const BTRFS_COMPRESSIONS: [&str; 3] = ["zstd", "lzo", "zlib"];
+
+/// Directory where systemd-cryptsetup looks for `<name>.key` key files
+pub const KEY_FILE_DIR: &str = "/etc/cryptsetup-keys.d";
+
+/// Size of generated key files, in bytes
+const KEY_FILE_SIZE: usize = 4096;
+
+/// LUKS2 volume holding a new filesystem
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LuksOptions {
+    /// Mapping name, the filesystem is opened as `/dev/mapper/<name>`
+    pub name: String,
+    pub unlock: Unlock,
+    /// Add a random key file in [`KEY_FILE_DIR`] so that the volume opens
+    /// without the passphrase; only for volumes opened after boot, the root
+    /// filesystem holding the key
+    pub key_file: bool,
+}
+
+impl LuksOptions {
+    /// Opened from crypttab with `nofail`: a data disk left unplugged must
+    /// not hold up the boot, asking in the initrd is opt-in
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            unlock: Unlock::Crypttab,
+            key_file: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        validate_mapper_name(&self.name)?;
+        if self.key_file && self.unlock == Unlock::Initrd {
+            bail!("A key file can only open volumes listed in crypttab");
+        }
+        Ok(())
+    }
+
+    pub fn key_file_path(&self) -> Option<PathBuf> {
+        self.key_file
+            .then(|| Path::new(KEY_FILE_DIR).join(format!("{}.key", self.name)))
+    }
+
+    /// The volume as its partition holds it once formatted
+    pub fn encryption(&self, fs_type: FsType) -> Encryption {
+        Encryption {
+            name: self.name.clone(),
+            unlock: self.unlock,
+            key_file: self
+                .key_file_path()
+                .map(|path| path.to_string_lossy().into_owned()),
+            fs_type: Some(fs_type.as_str().to_string()),
+        }
+    }
+}
+
+/// Check a device-mapper name: it ends up in `/dev/mapper`, in crypttab and
+/// on the command line of `cryptsetup`
+pub fn validate_mapper_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 127
+        && !name.starts_with('-')
+        && name != "."
+        && name != ".."
+        && name != "control"
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!(
+            "Invalid volume name {:?}: use letters, digits, '-', '_' and '.'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// What to create on a disk being formatted
+///
+/// Options that do not apply to `fs_type` are ignored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormatOptions {
+    pub fs_type: FsType,
+    pub label: String,
+    /// btrfs: compression of new files, `None` to store them as is
+    pub compression: Option<String>,
+    /// btrfs: subvolumes to create, the first one becomes the default one
+    /// mounted without `subvol=`
+    pub subvolumes: Vec<String>,
+    /// XFS: share blocks between copies (`cp --reflink`)
+    pub reflink: bool,
+    /// exFAT: cluster size in KiB, `None` lets mkfs choose from the size
+    pub cluster_size: Option<u32>,
+    /// Create the filesystem inside a LUKS volume
+    pub encryption: Option<LuksOptions>,
+}
+
+impl FormatOptions {
+    pub fn new(fs_type: FsType, label: &str) -> Self {
+        Self {
+            fs_type,
+            label: label.to_string(),
+            compression: Some("zstd".to_string()),
+            subvolumes: Vec::new(),
+            reflink: true,
+            cluster_size: None,
+            encryption: None,
+        }
+    }
+
+    /// Check everything that ends up on a command line
+    pub fn validate(&self) -> Result<()> {
+        validate_label(self.fs_type.as_str(), &self.label)?;
+        if self.fs_type == FsType::Btrfs {
+            if let Some(compression) = &self.compression
+                && !BTRFS_COMPRESSIONS.contains(&compression.as_str())
+            {
+                bail!("Unsupported btrfs compression: {}", compression);
+            }
+            for name in &self.subvolumes {
+                let valid = !name.is_empty()
+                    && name != "."
+                    && name != ".."
+                    && !name.starts_with('-')
+                    && !name.chars().any(|c| c == '/' || c.is_control());
+                if !valid {
+                    bail!("Invalid subvolume name: {:?}", name);
+                }
+            }
+        }
+        if self.fs_type == FsType::Exfat
+            && let Some(size) = self.cluster_size
+            && !(size.is_power_of_two() && (4..=32768).contains(&size))
+        {
+            bail!("Invalid exFAT cluster size: {} KiB", size);
+        }
+        if let Some(luks) = &self.encryption {
+            luks.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Arguments of the mkfs tool
+    fn mkfs_args(&self, partition: &str) -> Vec<String> {
+        let label = self.label.clone();
+        let mut args: Vec<String> = match self.fs_type {
+            FsType::Ext4 => vec!["-F".into(), "-L".into(), label],
+            FsType::Btrfs => vec!["-f".into(), "-L".into(), label],
+            FsType::Xfs => vec![
+                "-f".into(),
+                "-L".into(),
+                label,
+                "-m".into(),
+                format!("reflink={}", u8::from(self.reflink)),
+            ],
+            FsType::Exfat => {
+                let mut args = vec!["-L".into(), label];
+                if let Some(size) = self.cluster_size {
+                    args.extend(["-c".into(), format!("{}K", size)]);
+                }
+                args
+            }
+            FsType::Ntfs => vec!["-F".into(), "-Q".into(), "-L".into(), label],
+            FsType::Vfat => vec!["-F".into(), "32".into(), "-n".into(), label],
+        };
+        args.push(partition.to_string());
+        args
+    }
+}
+
+/// Owner given to the root directory of a new filesystem
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    /// Look up a user name or numeric uid with `id`
+    pub fn lookup(user: &str) -> Result<Self> {
+        if user.is_empty() || user.starts_with('-') {
+            bail!("Invalid user name: {:?}", user);
+        }
+        Self::id(Some(user))
+    }
+
+    /// The user running nix-disk, who owns what the helper formats for it
+    pub fn current() -> Result<Self> {
+        Self::id(None)
+    }
+
+    fn id(user: Option<&str>) -> Result<Self> {
+        let id = |flag: &str| -> Result<u32> {
+            let output = Command::new("id")
+                .arg(flag)
+                .args(user)
+                .output()
+                .map_err(|e| NixDiskError::tool("id", e))?;
+            if !output.status.success() {
+                bail!("Unknown user: {}", user.unwrap_or_default());
+            }
+            String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .with_context(|| format!("Unexpected output of id {}", flag))
+        };
+        Ok(Self {
+            uid: id("-u")?,
+            gid: id("-g")?,
+        })
+    }
+}
+
+/// Why a volume label was refused
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidLabel {
+    #[error("the label is empty")]
+    Empty,
+
+    /// `max` counts bytes, or UTF-16 code units for exFAT and NTFS
+    #[error("{fs_type} labels are limited to {max} characters")]
+    TooLong { fs_type: String, max: usize },
+
+    #[error("{fs_type} labels cannot contain {character:?}")]
+    Character { fs_type: String, character: char },
+
+    #[error("the label cannot start with '-'")]
+    LeadingDash,
+}
+
+/// Longest label of a `fs_type` filesystem, see [`validate_label`]
+pub fn max_label_length(fs_type: &str) -> usize {
+    match fs_type {
+        "btrfs" => 255,
+        "xfs" => 12,
+        "vfat" | "fat32" => 11,
+        "exfat" => 15,
+        "ntfs" | "ntfs3" => 128,
+        _ => 16,
+    }
+}
+
+/// Check that `label` can name a new `fs_type` filesystem
+///
+/// The limits are the ones of the `mkfs` tools: 16 bytes for ext4, 12 for
+/// XFS, 11 ASCII characters for FAT, 15 UTF-16 code units for exFAT...
+pub fn validate_label(fs_type: &str, label: &str) -> Result<(), InvalidLabel> {
+    if label.is_empty() {
+        return Err(InvalidLabel::Empty);
+    }
+    // Tools would read it as an option
+    if label.starts_with('-') {
+        return Err(InvalidLabel::LeadingDash);
+    }
+
+    let max = max_label_length(fs_type);
+    let len = match fs_type {
+        "exfat" | "ntfs" | "ntfs3" => label.encode_utf16().count(),
+        _ => label.len(),
+    };
+    if len > max {
+        return Err(InvalidLabel::TooLong {
+            fs_type: fs_type.to_string(),
+            max,
+        });
+    }
+
+    let forbidden = |c: char| {
+        c.is_control()
+            || match fs_type {
+                "vfat" | "fat32" => !c.is_ascii() || "\"*+,./:;<=>?[\\]|".contains(c),
+                "exfat" => "\"*/:<>?\\|".contains(c),
+                _ => false,
+            }
+    };
+    if let Some(character) = label.chars().find(|&c| forbidden(c)) {
+        return Err(InvalidLabel::Character {
+            fs_type: fs_type.to_string(),
+            character,
+        });
+    }
+    Ok(())
+}
+
+fn tool(variable: &str, default: &str) -> String {
+    std::env::var(variable).unwrap_or_else(|_| default.to_string())
+}
+
+fn run(progress: &dyn Fn(&str), step: &str, program: &str, args: &[&str]) -> Result<()> {
+    progress(step);
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| NixDiskError::tool(program, e))?;
+    if !status.success() {
+        bail!("{} failed ({})", step, status);
+    }
+    Ok(())
+}
+
+/// [`run`] writing `input` to the standard input of the program
+fn run_with_input(
+    progress: &dyn Fn(&str),
+    step: &str,
+    program: &str,
+    args: &[&str],
+    input: &str,
+) -> Result<()> {
+    progress(step);
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| NixDiskError::tool(program, e))?;
+    // Dropped right away so that the program sees the end of the input
+    child
+        .stdin
+        .take()
+        .context("stdin not captured")?
+        .write_all(input.as_bytes())
+        .with_context(|| format!("Failed to write to {}", program))?;
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for {}", program))?;
+    if !status.success() {
+        bail!("{} failed ({})", step, status);
+    }
+    Ok(())
+}
+
+/// Partition `number` of a disk: `/dev/sdb1`, or `/dev/nvme0n1p1` when the
+/// disk name ends with a digit
+pub fn partition_path(disk: &Path, number: u32) -> PathBuf {
+    let disk = disk.to_string_lossy();
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        PathBuf::from(format!("{}p{}", disk, number))
+    } else {
+        PathBuf::from(format!("{}{}", disk, number))
+    }
+}
+
+/// Fail with [`NixDiskError::DeviceBusy`] if the disk, one of its partitions
+/// or a device built on them is mounted or used as swap
+pub fn check_not_in_use(disk: &Path) -> Result<()> {
+    let disk = fs::canonicalize(disk).unwrap_or_else(|_| disk.to_path_buf());
+    let Some(disk_name) = disk.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+        return Ok(());
+    };
+    // Partitions are the sysfs subdirectories carrying a `partition` attribute
+    let class_dir = Path::new("/sys/class/block");
+    let mut devices: Vec<String> = fs::read_dir(class_dir.join(&disk_name))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.path().join("partition").exists())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    devices.push(disk_name.clone());
+    // LUKS volumes, logical volumes and arrays built on them are mounted
+    // in their place
+    let mut index = 0;
+    while index < devices.len() {
+        if let Ok(entries) = fs::read_dir(class_dir.join(&devices[index]).join("holders")) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !devices.contains(&name) {
+                    devices.push(name);
+                }
+            }
+        }
+        index += 1;
+    }
+    let belongs_to_disk = |device: &str| {
+        let device = fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+        device
+            .file_name()
+            .is_some_and(|name| devices.iter().any(|d| *d == name.to_string_lossy()))
+    };
+
+    for (table, swap) in [("/proc/mounts", false), ("/proc/swaps", true)] {
+        let Ok(content) = fs::read_to_string(table) else {
+            continue;
+        };
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(device), Some(target)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if device.starts_with("/dev/") && belongs_to_disk(device) {
+                let reason = if swap {
+                    format!("{} is used as swap", device)
+                } else {
+                    format!("{} is mounted on {}", device, target)
+                };
+                return Err(NixDiskError::DeviceBusy {
+                    device: disk.clone(),
+                    reason,
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Size of a disk in bytes, from sysfs
+fn disk_size(disk: &Path) -> Result<u64> {
+    let disk = fs::canonicalize(disk).unwrap_or_else(|_| disk.to_path_buf());
+    let name = disk
+        .file_name()
+        .with_context(|| format!("{} is not a disk", disk.display()))?;
+    read_size(&Path::new("/sys/class/block").join(name))
+}
+
+/// Refuse encrypted partitions whose mapping or key file already exists,
+/// before anything is written
+fn check_luks_targets(layout: &DiskLayout, passphrase: Option<&str>) -> Result<()> {
+    for luks in layout
+        .partitions
+        .iter()
+        .filter_map(|p| p.format.encryption.as_ref())
+    {
+        if passphrase.is_none_or(str::is_empty) {
+            bail!("A passphrase is needed to encrypt {}", luks.name);
+        }
+        let mapper = Path::new("/dev/mapper").join(&luks.name);
+        if mapper.exists() {
+            bail!("{} already exists", mapper.display());
+        }
+        if let Some(key_file) = luks.key_file_path()
+            && key_file.exists()
+        {
+            bail!("{} already exists", key_file.display());
+        }
+    }
+    Ok(())
+}
+
+/// Write the partition table of `layout`, create the filesystem of each
+/// partition, and return the partitions
+///
+/// `progress` receives the name of each step as it starts. Tools come from
+/// `PARTED_BIN`, `MKFS_<TYPE>_BIN`, `BTRFS_BIN` and `CRYPTSETUP_BIN` when
+/// set by the Nix wrapper. `owner` is ignored by filesystems without owners,
+/// whose mount options set it instead. `passphrase` opens every encrypted
+/// partition of the layout.
+pub fn format_disk(
+    disk: &Path,
+    layout: &DiskLayout,
+    passphrase: Option<&str>,
+    owner: Option<Owner>,
+    progress: &dyn Fn(&str),
+) -> Result<Vec<PathBuf>> {
+    layout.validate()?;
+    check_not_in_use(disk)?;
+    check_luks_targets(layout, passphrase)?;
+    let planned = layout.plan(disk_size(disk)?)?;
+
+    let parted_bin = tool("PARTED_BIN", "parted");
+    let disk_str = disk.to_string_lossy();
+
+    run(
+        progress,
+        &format!("Creating {} partition table", layout.table),
+        &parted_bin,
+        &["-s", &disk_str, "mklabel", layout.table.as_str()],
+    )?;
+    for partition in &planned {
+        // parted splits its arguments on spaces unless they are quoted
+        let name = match layout.table {
+            TableType::Gpt => format!("\"{}\"", partition.spec.gpt_name()),
+            TableType::Msdos => "primary".to_string(),
+        };
+        let start = format!("{}B", partition.start);
+        let end = format!("{}B", partition.end - 1);
+        run(
+            progress,
+            &format!("Creating partition {}", partition.number),
+            &parted_bin,
+            &[
+                "-s",
+                &disk_str,
+                "unit",
+                "B",
+                "mkpart",
+                &name,
+                partition.spec.format.fs_type.parted_type(),
+                &start,
+                &end,
+            ],
+        )?;
+        if let Some(guid) = &partition.spec.type_guid {
+            run(
+                progress,
+                &format!("Setting the type of partition {}", partition.number),
+                &parted_bin,
+                &["-s", &disk_str, "type", &partition.number.to_string(), guid],
+            )?;
+        }
+    }
+
+    // Wait for udev to create the partition nodes
+    let _ = Command::new("udevadm").arg("settle").status();
+    let mut partitions = Vec::with_capacity(planned.len());
+    for planned in &planned {
+        let partition = partition_path(disk, planned.number);
+        if !partition.exists() {
+            bail!(
+                "Partition {} not found after partitioning",
+                partition.display()
+            );
+        }
+        match (&planned.spec.format.encryption, passphrase) {
+            (Some(luks), Some(passphrase)) => create_encrypted_filesystem(
+                &partition,
+                luks,
+                passphrase,
+                &planned.spec.format,
+                owner,
+                progress,
+            )?,
+            _ => create_filesystem(&partition, &planned.spec.format, owner, progress)?,
+        }
+        partitions.push(partition);
+    }
+    // So that the new UUIDs are known when the disks are probed again
+    let _ = Command::new("udevadm").arg("settle").status();
+    Ok(partitions)
+}
+
+/// Set up a LUKS volume on `partition` and create the filesystem inside it
+fn create_encrypted_filesystem(
+    partition: &Path,
+    luks: &LuksOptions,
+    passphrase: &str,
+    options: &FormatOptions,
+    owner: Option<Owner>,
+    progress: &dyn Fn(&str),
+) -> Result<()> {
+    let cryptsetup_bin = tool("CRYPTSETUP_BIN", "cryptsetup");
+    let partition_str = partition.to_string_lossy();
+
+    run_with_input(
+        progress,
+        &format!("Encrypting {}", partition.display()),
+        &cryptsetup_bin,
+        &[
+            "luksFormat",
+            "--batch-mode",
+            "--type",
+            "luks2",
+            "--key-file",
+            "-",
+            &partition_str,
+        ],
+        passphrase,
+    )?;
+    if let Some(key_file) = luks.key_file_path() {
+        write_key_file(&key_file)?;
+        run_with_input(
+            progress,
+            "Adding the key file",
+            &cryptsetup_bin,
+            &[
+                "luksAddKey",
+                "--key-file",
+                "-",
+                &partition_str,
+                &key_file.to_string_lossy(),
+            ],
+            passphrase,
+        )?;
+    }
+    run_with_input(
+        progress,
+        &format!("Opening {}", luks.name),
+        &cryptsetup_bin,
+        &["open", "--key-file", "-", &partition_str, &luks.name],
+        passphrase,
+    )?;
+    let result = create_filesystem(
+        &Path::new("/dev/mapper").join(&luks.name),
+        options,
+        owner,
+        progress,
+    );
+    run(
+        progress,
+        &format!("Closing {}", luks.name),
+        &cryptsetup_bin,
+        &["close", &luks.name],
+    )?;
+    result
+}
+
+/// Fill a new key file readable by root only with random bytes
+fn write_key_file(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| NixDiskError::io(dir, e))?;
+    }
+    let mut key = vec![0; KEY_FILE_SIZE];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut key))
+        .context("Failed to read /dev/urandom")?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o400)
+        .open(path)
+        .and_then(|mut file| file.write_all(&key))
+        .map_err(|e| NixDiskError::io(path, e))?;
+    Ok(())
+}
+
+/// Run mkfs on `partition`, then set up its root directory
+fn create_filesystem(
+    partition: &Path,
+    options: &FormatOptions,
+    owner: Option<Owner>,
+    progress: &dyn Fn(&str),
+) -> Result<()> {
+    let fs_type = options.fs_type;
+    let (mkfs_variable, mkfs_default) = fs_type.mkfs_bin();
+    let mkfs_bin = tool(mkfs_variable, mkfs_default);
+    let partition_str = partition.to_string_lossy();
+
+    let mkfs_args = options.mkfs_args(&partition_str);
+    run(
+        progress,
+        &format!("Creating {} filesystem on {}", fs_type, partition.display()),
+        &mkfs_bin,
+        &mkfs_args.iter().map(String::as_str).collect::<Vec<_>>(),
+    )?;
+
+    let owner = owner.filter(|_| fs_type.has_owners());
+    if owner.is_some() || fs_type == FsType::Btrfs {
+        let temp_mount = tempfile::Builder::new()
+            .prefix("nix-disk-mount-")
+            .tempdir()
+            .context("Failed to create a temporary mount point")?;
+        let temp_str = temp_mount.path().to_string_lossy().into_owned();
+
+        run(progress, "Mounting", "mount", &[&partition_str, &temp_str])?;
+        let result = set_up_root(temp_mount.path(), options, owner, progress);
+        if let Err(e) = run(progress, "Unmounting", "umount", &[&temp_str]) {
+            // Removing it would delete the files of the new filesystem
+            let _ = temp_mount.keep();
+            return Err(e);
+        }
+        result?;
+    }
+    Ok(())
+}
+
+/// Create the btrfs subvolumes and hand the new filesystem, mounted on
+/// `root`, over to `owner`
+fn set_up_root(
+    root: &Path,
+    options: &FormatOptions,
+    owner: Option<Owner>,
+    progress: &dyn Fn(&str),
+) -> Result<()> {
+    let mut directories = vec![root.to_path_buf()];
+    if options.fs_type == FsType::Btrfs {
+        let btrfs_bin = tool("BTRFS_BIN", "btrfs");
+        for (i, name) in options.subvolumes.iter().enumerate() {
+            let subvolume = root.join(name);
+            let subvolume_str = subvolume.to_string_lossy();
+            run(
+                progress,
+                &format!("Creating subvolume {}", name),
+                &btrfs_bin,
+                &["subvolume", "create", &subvolume_str],
+            )?;
+            if i == 0 {
+                run(
+                    progress,
+                    "Setting the default subvolume",
+                    &btrfs_bin,
+                    &["subvolume", "set-default", &subvolume_str],
+                )?;
+            }
+            directories.push(subvolume);
+        }
+        // New files inherit the property of their directory
+        let compression = options.compression.as_deref().unwrap_or("none");
+        for directory in &directories {
+            run(
+                progress,
+                "Setting compression",
+                &btrfs_bin,
+                &[
+                    "property",
+                    "set",
+                    &directory.to_string_lossy(),
+                    "compression",
+                    compression,
+                ],
+            )?;
+        }
+    }
+
+    if let Some(owner) = owner {
+        for directory in &directories {
+            std::os::unix::fs::chown(directory, Some(owner.uid), Some(owner.gid))
+                .and_then(|_| {
+                    std::fs::set_permissions(directory, std::fs::Permissions::from_mode(0o755))
+                })
+                .context("Failed to set permissions")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(
+            partition_path(Path::new("/dev/sdb"), 1),
+            PathBuf::from("/dev/sdb1")
+        );
+        assert_eq!(
+            partition_path(Path::new("/dev/nvme0n1"), 2),
+            PathBuf::from("/dev/nvme0n1p2")
+        );
+    }
+
+    #[test]
+    fn test_validate_label() {
+        assert_eq!(validate_label("ext4", "données"), Ok(()));
+        assert!(matches!(
+            validate_label("ext4", "seventeen_chars__"),
+            Err(InvalidLabel::TooLong { max: 16, .. })
+        ));
+        assert_eq!(
+            validate_label("ext4", "a\nb"),
+            Err(InvalidLabel::Character {
+                fs_type: "ext4".to_string(),
+                character: '\n',
+            })
+        );
+        assert_eq!(validate_label("ext4", "-O"), Err(InvalidLabel::LeadingDash));
+        assert!(validate_label("xfs", "thirteen_char").is_err());
+        assert!(validate_label("vfat", "USB/KEY").is_err());
+        assert!(validate_label("exfat", "ééééééééééééééé").is_ok());
+    }
+
+    #[test]
+    fn test_fs_type_names() {
+        for fs_type in FsType::ALL {
+            assert_eq!(fs_type.as_str().parse::<FsType>(), Ok(fs_type));
+        }
+        assert_eq!("fat32".parse::<FsType>(), Ok(FsType::Vfat));
+        assert!("zfs".parse::<FsType>().is_err());
+    }
+
+    #[test]
+    fn test_format_options() {
+        let mut options = FormatOptions::new(FsType::Btrfs, "data");
+        options.subvolumes = vec!["@data".to_string(), "@snapshots".to_string()];
+        assert!(options.validate().is_ok());
+        options.subvolumes.push("../etc".to_string());
+        assert!(options.validate().is_err());
+
+        let mut options = FormatOptions::new(FsType::Exfat, "SHARED");
+        options.cluster_size = Some(128);
+        assert_eq!(
+            options.mkfs_args("/dev/sdb1"),
+            ["-L", "SHARED", "-c", "128K", "/dev/sdb1"]
+        );
+        options.cluster_size = Some(100);
+        assert!(options.validate().is_err());
+
+        let mut options = FormatOptions::new(FsType::Xfs, "media");
+        options.reflink = false;
+        assert_eq!(
+            options.mkfs_args("/dev/sdb1"),
+            ["-f", "-L", "media", "-m", "reflink=0", "/dev/sdb1"]
+        );
+        assert!(
+            FormatOptions::new(FsType::Vfat, "USB/KEY")
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_luks_options() {
+        let mut options = FormatOptions::new(FsType::Ext4, "vault");
+        options.encryption = Some(LuksOptions::new("crypt-vault"));
+        assert!(options.validate().is_ok());
+
+        let mut luks = LuksOptions::new("vault");
+        assert_eq!(luks.unlock, Unlock::Crypttab);
+        luks.key_file = true;
+        assert!(luks.validate().is_ok());
+        luks.unlock = Unlock::Initrd;
+        assert!(luks.validate().is_err());
+        luks.unlock = Unlock::Crypttab;
+        assert_eq!(
+            luks.encryption(FsType::Btrfs).key_file.as_deref(),
+            Some("/etc/cryptsetup-keys.d/vault.key")
+        );
+
+        for name in ["", "-v", "..", "a/b", "a b", "control"] {
+            assert!(validate_mapper_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::models::{
+    ConfiguredMount, Disk, INVENTORY_SCHEMA_VERSION, Inventory, NixConfig, Partition,
+};
+use crate::utils::{
+    ConfigPaths, configured_partitions, find_missing_partitions, parse_nix_filesystems,
+};
+use anyhow::Result;
+
+/// Collect detected disks and configured mounts from every configuration source
+pub fn build_inventory(
+    paths: &ConfigPaths,
+    config: &NixConfig,
+    disks: &[Disk],
+) -> Result<Inventory> {
+    let mut mounts = Vec::new();
+    let mut configured: Vec<Partition> = Vec::new();
+
+    for (index, source) in config.sources().into_iter().enumerate() {
+        let filesystems = parse_nix_filesystems(source)?;
+        mounts.extend(filesystems.entries.iter().map(|entry| ConfiguredMount {
+            mount_point: entry.mount_point.clone(),
+            device: entry.device.clone(),
+            fs_type: entry.fs_type.clone(),
+            options: entry.options.clone(),
+            readonly: index > 0,
+        }));
+        configured.extend(configured_partitions(&filesystems).into_values());
+    }
+
+    // Stable output: same order on every run
+    configured.sort_by(|a, b| a.path.cmp(&b.path));
+    configured.dedup_by(|a, b| a.path == b.path);
+
+    Ok(Inventory {
+        schema_version: INVENTORY_SCHEMA_VERSION,
+        config_file: config.path.clone(),
+        flake: paths.flake.as_ref().map(|flake| flake.flake_ref()),
+        disks: disks.to_vec(),
+        mounts,
+        missing_partitions: find_missing_partitions(&configured, disks),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_inventory_schema() {
+        let content = r#"{
+  fileSystems."/mnt/gone" = {
+    device = "/dev/disk/by-uuid/0000-dead";
+    fsType = "ext4";
+  };
+}"#;
+        let config = NixConfig::new(PathBuf::from("/etc/nixos/nix-disk.nix"), content.into());
+        let disks = vec![Disk::new(
+            PathBuf::from("/dev/sdb"),
+            vec![Partition::new(
+                PathBuf::from("/dev/sdb1"),
+                PathBuf::from("/dev/disk/by-uuid/1234-abcd"),
+                Vec::new(),
+                Some("ext4".to_string()),
+                1_000_000,
+                Some("data".to_string()),
+            )],
+            2_000_000,
+        )];
+
+        let inventory = build_inventory(&ConfigPaths::default(), &config, &disks).unwrap();
+        let json = serde_json::to_value(&inventory).unwrap();
+
+        assert_eq!(json["schema_version"], INVENTORY_SCHEMA_VERSION);
+        assert_eq!(json["disks"][0]["partitions"][0]["uuid"], "1234-abcd");
+        assert_eq!(json["disks"][0]["partitions"][0]["label"], "data");
+        assert_eq!(json["mounts"][0]["mount_point"], "/mnt/gone");
+        assert_eq!(json["mounts"][0]["readonly"], false);
+        assert_eq!(
+            json["missing_partitions"][0]["uuid_path"],
+            "/dev/disk/by-uuid/0000-dead"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Partition layouts created when formatting a disk
+//!
+//! A layout lists the partitions in disk order. [`DiskLayout::plan`] turns
+//! their sizes into byte ranges aligned on 1 MiB, which is what gets passed
+//! to `parted` and what the format dialog previews before anything is written.
+
+use crate::utils::format::FormatOptions;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+pub const MIB: u64 = 1024 * 1024;
+
+/// MBR stores sectors in 32 bits
+const MSDOS_MAX_SIZE: u64 = (1 << 32) * 512;
+
+/// GPT partition names are 36 UTF-16 code units
+const GPT_NAME_MAX: usize = 36;
+
+/// Well-known GPT partition types, offered by the layout editor
+pub const PARTITION_TYPES: [(&str, &str); 5] = [
+    ("Linux filesystem", "0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+    ("Linux home", "933AC7E1-2EB4-4F13-B844-0E14E2AEF915"),
+    ("Linux swap", "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F"),
+    ("EFI system", "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"),
+    (
+        "Microsoft basic data",
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+    ),
+];
+
+/// Partition table written by `parted mklabel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableType {
+    Gpt,
+    Msdos,
+}
+
+impl TableType {
+    /// Name given to `parted mklabel`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TableType::Gpt => "gpt",
+            TableType::Msdos => "msdos",
+        }
+    }
+}
+
+impl fmt::Display for TableType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TableType::Gpt => "GPT",
+            TableType::Msdos => "MBR",
+        })
+    }
+}
+
+impl FromStr for TableType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gpt" => Ok(TableType::Gpt),
+            "msdos" | "mbr" => Ok(TableType::Msdos),
+            _ => Err(format!("Unknown partition table: {}", s)),
+        }
+    }
+}
+
+/// Size of a partition
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionSize {
+    Bytes(u64),
+    /// Share of the space usable by partitions
+    Percent(u32),
+    /// Everything left, only for the last partition
+    Rest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Invalid size {0:?}, expected e.g. 512MiB, 20G, 25% or rest")]
+pub struct InvalidSize(pub String);
+
+impl FromStr for PartitionSize {
+    type Err = InvalidSize;
+
+    /// `25%`, `rest`, or a number with an optional unit: `K`, `M`, `G`, `T`
+    /// and `KiB`... are powers of 1024, `KB`... powers of 1000
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSize(s.to_string());
+        let text = s.trim();
+        if text.is_empty() || text.eq_ignore_ascii_case("rest") {
+            return Ok(PartitionSize::Rest);
+        }
+        if let Some(percent) = text.strip_suffix('%') {
+            return match percent.trim().parse::<u32>() {
+                Ok(p) if (1..=100).contains(&p) => Ok(PartitionSize::Percent(p)),
+                _ => Err(invalid()),
+            };
+        }
+
+        let split = text
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "t" | "tib" => 1 << 40,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "tb" => 1_000_000_000_000,
+            _ => return Err(invalid()),
+        };
+        let bytes = number * multiplier as f64;
+        if !bytes.is_finite() || bytes < 1.0 || bytes > u64::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(PartitionSize::Bytes(bytes as u64))
+    }
+}
+
+impl fmt::Display for PartitionSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionSize::Bytes(bytes) => f.write_str(&format_size(*bytes)),
+            PartitionSize::Percent(p) => write!(f, "{}%", p),
+            PartitionSize::Rest => f.write_str("rest"),
+        }
+    }
+}
+
+/// `512 MiB`, `1.5 TiB`
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 || value.fract() == 0.0 {
+        format!("{} {}", value, units[unit])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// One partition of a layout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionSpec {
+    pub size: PartitionSize,
+    pub format: FormatOptions,
+    /// GPT partition name, the filesystem label when unset
+    pub name: Option<String>,
+    /// GPT partition type, otherwise derived from the filesystem by parted
+    pub type_guid: Option<String>,
+}
+
+impl PartitionSpec {
+    pub fn new(size: PartitionSize, format: FormatOptions) -> Self {
+        Self {
+            size,
+            format,
+            name: None,
+            type_guid: None,
+        }
+    }
+
+    /// Name written in the GPT entry
+    pub fn gpt_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.format.label)
+    }
+}
+
+/// Partition table and partitions to create on a disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskLayout {
+    pub table: TableType,
+    pub partitions: Vec<PartitionSpec>,
+}
+
+/// A partition of a layout placed on a disk of a given size
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedPartition {
+    pub number: u32,
+    /// First byte
+    pub start: u64,
+    /// First byte after the partition
+    pub end: u64,
+    pub spec: PartitionSpec,
+}
+
+impl PlannedPartition {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+impl DiskLayout {
+    /// A GPT table with one partition filling the disk
+    pub fn single(format: FormatOptions) -> Self {
+        Self {
+            table: TableType::Gpt,
+            partitions: vec![PartitionSpec::new(PartitionSize::Rest, format)],
+        }
+    }
+
+    /// Check everything that does not depend on the size of the disk
+    pub fn validate(&self) -> Result<()> {
+        if self.partitions.is_empty() {
+            bail!("The layout has no partition");
+        }
+        if self.table == TableType::Msdos && self.partitions.len() > 4 {
+            bail!("An MBR table holds at most 4 partitions");
+        }
+        for (i, partition) in self.partitions.iter().enumerate() {
+            let number = i + 1;
+            if partition.size == PartitionSize::Rest && number != self.partitions.len() {
+                bail!("Only the last partition can take the rest of the disk");
+            }
+            partition.format.validate()?;
+            if let Some(luks) = &partition.format.encryption
+                && self.partitions[..i]
+                    .iter()
+                    .filter_map(|p| p.format.encryption.as_ref())
+                    .any(|other| other.name == luks.name)
+            {
+                bail!(
+                    "Partition {}: the volume name {} is already used",
+                    number,
+                    luks.name
+                );
+            }
+
+            if self.table == TableType::Msdos
+                && (partition.name.is_some() || partition.type_guid.is_some())
+            {
+                bail!(
+                    "Partition {}: names and type GUIDs need a GPT table",
+                    number
+                );
+            }
+            // Without a name of its own, the label is written in its place
+            if self.table == TableType::Gpt {
+                let name = partition.gpt_name();
+                let valid = !name.is_empty()
+                    && name.encode_utf16().count() <= GPT_NAME_MAX
+                    && !name
+                        .chars()
+                        .any(|c| c == '"' || c == '\'' || c.is_control());
+                if !valid {
+                    bail!("Partition {}: invalid GPT name {:?}", number, name);
+                }
+            }
+            if let Some(guid) = &partition.type_guid
+                && !is_guid(guid)
+            {
+                bail!("Partition {}: invalid type GUID {:?}", number, guid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Place the partitions on a disk of `disk_size` bytes
+    ///
+    /// The first MiB holds the partition table, the last one the GPT backup.
+    pub fn plan(&self, disk_size: u64) -> Result<Vec<PlannedPartition>> {
+        self.validate()?;
+        if self.table == TableType::Msdos && disk_size > MSDOS_MAX_SIZE {
+            bail!("An MBR table cannot address disks larger than 2 TiB, use GPT");
+        }
+        let first = MIB;
+        let last = disk_size.saturating_sub(MIB) / MIB * MIB;
+        let usable = last.saturating_sub(first);
+
+        let mut start = first;
+        let mut planned = Vec::with_capacity(self.partitions.len());
+        for (i, spec) in self.partitions.iter().enumerate() {
+            let number = i as u32 + 1;
+            let size = match spec.size {
+                PartitionSize::Bytes(bytes) if bytes > disk_size => {
+                    bail!(
+                        "Partition {} is larger than the disk ({})",
+                        number,
+                        format_size(disk_size)
+                    );
+                }
+                PartitionSize::Bytes(bytes) => bytes / MIB * MIB,
+                PartitionSize::Percent(p) => usable / 100 * u64::from(p) / MIB * MIB,
+                PartitionSize::Rest => last.saturating_sub(start),
+            };
+            if size == 0 {
+                bail!("Partition {} is smaller than 1 MiB", number);
+            }
+            let end = match start.checked_add(size) {
+                Some(end) if end <= last => end,
+                _ => bail!(
+                    "Partition {} does not fit: {} left on the disk",
+                    number,
+                    format_size(last.saturating_sub(start))
+                ),
+            };
+            planned.push(PlannedPartition {
+                number,
+                start,
+                end,
+                spec: spec.clone(),
+            });
+            start = end;
+        }
+        Ok(planned)
+    }
+}
+
+fn is_guid(text: &str) -> bool {
+    let groups: Vec<&str> = text.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::format::FsType;
+
+    fn spec(size: &str, fs_type: FsType, label: &str) -> PartitionSpec {
+        PartitionSpec::new(size.parse().unwrap(), FormatOptions::new(fs_type, label))
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!("512MiB".parse(), Ok(PartitionSize::Bytes(512 * MIB)));
+        assert_eq!("1.5G".parse(), Ok(PartitionSize::Bytes(1536 * MIB)));
+        assert_eq!("2 GB".parse(), Ok(PartitionSize::Bytes(2_000_000_000)));
+        assert_eq!("25%".parse(), Ok(PartitionSize::Percent(25)));
+        assert_eq!("rest".parse(), Ok(PartitionSize::Rest));
+        assert!("150%".parse::<PartitionSize>().is_err());
+        assert!("12 parsecs".parse::<PartitionSize>().is_err());
+        assert_eq!(format_size(1536 * MIB), "1.5 GiB");
+    }
+
+    #[test]
+    fn test_plan() {
+        let layout = DiskLayout {
+            table: TableType::Gpt,
+            partitions: vec![
+                spec("512MiB", FsType::Vfat, "EFI"),
+                spec("50%", FsType::Ext4, "system"),
+                spec("rest", FsType::Btrfs, "data"),
+            ],
+        };
+        let disk_size = 10_241 * MIB;
+        let planned = layout.plan(disk_size).unwrap();
+        assert_eq!(planned.len(), 3);
+        assert_eq!((planned[0].start, planned[0].end), (MIB, 513 * MIB));
+        assert_eq!(planned[1].size(), 5119 * MIB);
+        assert_eq!(planned[2].end, 10_240 * MIB);
+        assert_eq!(planned[2].number, 3);
+
+        assert!(layout.plan(500 * MIB).is_err());
+
+        // Sizes that would wrap around once added to the start
+        let layout = DiskLayout {
+            table: TableType::Gpt,
+            partitions: vec![
+                spec("512MiB", FsType::Vfat, "EFI"),
+                spec("18446744073709551615", FsType::Ext4, "data"),
+            ],
+        };
+        assert!(layout.plan(disk_size).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut layout = DiskLayout::single(FormatOptions::new(FsType::Ext4, "data"));
+        layout.partitions.insert(0, spec("rest", FsType::Ext4, "a"));
+        assert!(layout.validate().is_err());
+
+        let mut layout = DiskLayout::single(FormatOptions::new(FsType::Ext4, "data"));
+        layout.partitions[0].type_guid = Some(PARTITION_TYPES[0].1.to_string());
+        layout.partitions[0].name = Some("Data disk".to_string());
+        assert!(layout.validate().is_ok());
+        layout.table = TableType::Msdos;
+        assert!(layout.validate().is_err());
+
+        layout.table = TableType::Gpt;
+        layout.partitions[0].type_guid = Some("not-a-guid".to_string());
+        assert!(layout.validate().is_err());
+
+        // The label stands for the name, and must be a valid one too
+        let mut layout = DiskLayout::single(FormatOptions::new(FsType::Ntfs, "Bob's files"));
+        assert!(layout.validate().is_err());
+        layout.partitions[0].name = Some("Bob files".to_string());
+        assert!(layout.validate().is_ok());
+        layout.table = TableType::Msdos;
+        layout.partitions[0].name = None;
+        assert!(layout.validate().is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+//! Block device enumeration with a single `lsblk --json` call
+//!
+//! One process gives the disk/partition tree together with filesystem
+//! types, labels and UUIDs, instead of one `blkid` per partition.
+
+use crate::error::NixDiskError;
+use crate::models::{BlockDevice, BlockHolder, BlockPartition, LayerKind};
+use crate::utils::sysfs::is_ignored;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer, de};
+use serde_json::Value;
+use std::process::Command;
+
+#[derive(Debug, Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<LsblkDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LsblkDevice {
+    name: String,
+    /// Kernel name (`dm-0` rather than the mapper name)
+    kname: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default, deserialize_with = "number")]
+    size: u64,
+    fstype: Option<String>,
+    label: Option<String>,
+    uuid: Option<String>,
+    #[serde(default, deserialize_with = "flag")]
+    rm: bool,
+    #[serde(default, deserialize_with = "flag")]
+    ro: bool,
+    #[serde(default, deserialize_with = "flag")]
+    rota: bool,
+    #[serde(default)]
+    children: Vec<LsblkDevice>,
+}
+
+impl LsblkDevice {
+    fn kernel_name(&self) -> &str {
+        self.kname.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The name column shows device-mapper devices by their mapper name
+    fn mapper_name(&self) -> Option<String> {
+        (self.kernel_name().starts_with("dm-") && self.name != self.kernel_name())
+            .then(|| self.name.clone())
+    }
+
+    /// Children that are not partitions: LUKS volumes, logical volumes and
+    /// RAID arrays built on the device
+    fn holders(&self) -> Vec<BlockHolder> {
+        self.children
+            .iter()
+            .filter(|child| child.kind != "part")
+            .filter_map(LsblkDevice::to_holder)
+            .collect()
+    }
+
+    fn to_holder(&self) -> Option<BlockHolder> {
+        let kind = match self.kind.as_str() {
+            "crypt" => LayerKind::Luks,
+            "lvm" => LayerKind::Lvm,
+            "part" => LayerKind::Partition,
+            "md" | "linear" => LayerKind::Raid,
+            kind if kind.starts_with("raid") => LayerKind::Raid,
+            "dm" | "mpath" => LayerKind::Mapper,
+            _ => return None,
+        };
+        Some(BlockHolder {
+            name: self.kernel_name().to_string(),
+            kind,
+            mapper_name: self.mapper_name(),
+            size: self.size,
+            read_only: self.ro,
+            fs_type: non_empty(&self.fstype),
+            label: non_empty(&self.label),
+            uuid: non_empty(&self.uuid),
+            // Partitions of a RAID array included
+            holders: self
+                .children
+                .iter()
+                .filter_map(LsblkDevice::to_holder)
+                .collect(),
+        })
+    }
+}
+
+// util-linux before 2.33 prints every value as a string
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Number(n)) => n
+            .as_u64()
+            .ok_or_else(|| de::Error::custom(format!("invalid size {}", n))),
+        Some(Value::String(s)) => s
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid size {:?}", s))),
+        _ => Ok(0),
+    }
+}
+
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Bool(b)) => b,
+        Some(Value::String(s)) => s == "1",
+        Some(Value::Number(n)) => n.as_u64() == Some(1),
+        _ => false,
+    })
+}
+
+/// Trailing digits of a partition name: 2 for `sda2` and `nvme0n1p2`
+fn partition_number(name: &str) -> u32 {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    name[name.len() - digits..].parse().unwrap_or(0)
+}
+
+/// Fields that are empty strings in old lsblk versions
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|v| !v.is_empty())
+}
+
+/// Build the device tree from the output of `lsblk -J -b -O`
+pub fn parse_lsblk_json(json: &str) -> Result<Vec<BlockDevice>> {
+    let output: LsblkOutput = serde_json::from_str(json).context("Invalid lsblk output")?;
+
+    let mut disks = Vec::new();
+    for device in output.blockdevices {
+        let name = device.kernel_name();
+        if !matches!(device.kind.as_str(), "disk" | "loop") || is_ignored(name) {
+            continue;
+        }
+        // Unused loop devices
+        if device.size == 0 {
+            continue;
+        }
+
+        let partitions = device
+            .children
+            .iter()
+            .filter(|child| child.kind == "part")
+            .map(|child| BlockPartition {
+                name: child.kernel_name().to_string(),
+                number: partition_number(child.kernel_name()),
+                size: child.size,
+                read_only: child.ro,
+                fs_type: non_empty(&child.fstype),
+                label: non_empty(&child.label),
+                uuid: non_empty(&child.uuid),
+                holders: child.holders(),
+            })
+            .collect();
+
+        disks.push(BlockDevice {
+            name: name.to_string(),
+            size: device.size,
+            removable: device.rm,
+            read_only: device.ro,
+            rotational: device.rota,
+            partitions,
+            holders: device.holders(),
+        });
+    }
+
+    disks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(disks)
+}
+
+/// Run `lsblk -J -b -O` once and parse its output
+pub fn probe_lsblk() -> Result<Vec<BlockDevice>> {
+    let output = Command::new("lsblk")
+        .args(["-J", "-b", "-O"])
+        .output()
+        .map_err(|e| NixDiskError::tool("lsblk", e))?;
+    if !output.status.success() {
+        bail!(
+            "lsblk failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    parse_lsblk_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_lsblk_json() {
+        let json = r#"{
+   "blockdevices": [
+      {"name": "sr0", "kname": "sr0", "type": "rom", "size": 1073741312, "rm": true, "ro": false, "rota": true},
+      {"name": "zram0", "kname": "zram0", "type": "disk", "size": 8589934592, "rm": false, "ro": false, "rota": false},
+      {"name": "nvme0n1", "kname": "nvme0n1", "type": "disk", "size": 512110190592, "rm": false, "ro": false, "rota": false,
+         "children": [
+            {"name": "nvme0n1p1", "kname": "nvme0n1p1", "type": "part", "size": 536870912, "fstype": "vfat", "label": null, "uuid": "12CE-A600", "ro": false},
+            {"name": "nvme0n1p2", "kname": "nvme0n1p2", "type": "part", "size": 511571214336, "fstype": "crypto_LUKS", "label": null, "uuid": "9f2c",
+               "children": [
+                  {"name": "cryptroot", "kname": "dm-0", "type": "crypt", "size": 511554437120, "fstype": "LVM2_member", "uuid": "pv-1",
+                     "children": [
+                        {"name": "vg-root", "kname": "dm-1", "type": "lvm", "size": 107374182400, "fstype": "ext4", "uuid": "e1f0"}
+                     ]
+                  }
+               ]
+            }
+         ]
+      },
+      {"name": "sdb", "kname": "sdb", "type": "disk", "size": "31004295168", "rm": "1", "ro": "0", "rota": "1",
+         "children": [
+            {"name": "sdb1", "kname": "sdb1", "type": "part", "size": "31003246592", "fstype": "ext4", "label": "backup", "uuid": "4a7e-11", "ro": "0"}
+         ]
+      }
+   ]
+}"#;
+        let disks = parse_lsblk_json(json).unwrap();
+        let names: Vec<&str> = disks.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["nvme0n1", "sdb"]);
+
+        let nvme = &disks[0];
+        assert_eq!(nvme.partitions.len(), 2);
+        assert_eq!(nvme.partitions[1].number, 2);
+        assert_eq!(nvme.partitions[1].fs_type.as_deref(), Some("crypto_LUKS"));
+        let luks = &nvme.partitions[1].holders[0];
+        assert_eq!(luks.kind, LayerKind::Luks);
+        assert_eq!(luks.name, "dm-0");
+        assert_eq!(luks.mapper_name.as_deref(), Some("cryptroot"));
+        let lv = &luks.holders[0];
+        assert_eq!(lv.kind, LayerKind::Lvm);
+        assert_eq!(lv.dev_path(), PathBuf::from("/dev/mapper/vg-root"));
+        assert_eq!(lv.fs_type.as_deref(), Some("ext4"));
+
+        // Old util-linux output with string values
+        let sdb = &disks[1];
+        assert_eq!(sdb.size, 31004295168);
+        assert!(sdb.removable && sdb.rotational && !sdb.read_only);
+        assert_eq!(
+            sdb.partitions[0],
+            BlockPartition {
+                name: "sdb1".to_string(),
+                number: 1,
+                size: 31003246592,
+                read_only: false,
+                fs_type: Some("ext4".to_string()),
+                label: Some("backup".to_string()),
+                uuid: Some("4a7e-11".to_string()),
+                holders: Vec::new(),
+            }
+        );
+    }
+}
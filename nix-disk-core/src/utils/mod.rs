@@ -0,0 +1,33 @@
+pub mod backup;
+pub mod diff;
+pub mod disk_parser;
+pub mod disk_writer;
+pub mod file;
+pub mod flake;
+pub mod format;
+pub mod inventory;
+pub mod layout;
+pub mod lsblk;
+pub mod mount_point;
+pub mod nix_module;
+pub mod nix_parser;
+pub mod preflight;
+pub mod rebuild;
+pub mod sysfs;
+
+pub use backup::{BACKUP_DIR, Backup, list_backups, write_with_backup};
+pub use diff::{DiffLine, diff_lines, unified_diff};
+pub use disk_parser::{
+    configured_partitions, find_missing_partitions, get_disks, get_disks_with_sysfs_root,
+    parse_nix_filesystems,
+};
+pub use disk_writer::{get_filesystem_options, get_nix_disks_config, is_critical_mount_point};
+pub use file::{check_unchanged, content_hash, write_file};
+pub use flake::{FlakeHost, FlakeTarget, default_host, flake_hosts};
+pub use inventory::build_inventory;
+pub use mount_point::{InvalidMountPoint, validate_mount_point};
+pub use nix_module::{
+    ConfigPaths, ModuleSetup, enable_module, load_config, module_in_use, plan_module_setup,
+};
+pub use preflight::{StagedConfig, check_syntax};
+pub use rebuild::{RebuildFailure, RebuildPhase, parse_failure, parse_phase, rebuild_command};
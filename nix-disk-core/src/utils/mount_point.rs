@@ -0,0 +1,149 @@
+//! Checks on the mount points typed by the user, before they reach the
+//! configuration
+
+use crate::models::Disk;
+use crate::utils::disk_writer::CRITICAL_MOUNT_POINTS;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Directories the system manages, nothing may be mounted in them
+const RESERVED_DIRECTORIES: &[&str] = &["/proc", "/sys", "/dev", "/run", "/etc", "/bin", "/usr"];
+
+/// Why a mount point was refused
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidMountPoint {
+    #[error("the mount point is empty")]
+    Empty,
+
+    #[error("{0} is not an absolute path")]
+    NotAbsolute(String),
+
+    #[error("{0} contains \"..\"")]
+    ParentDirectory(String),
+
+    #[error("{0} contains a control character")]
+    ControlCharacter(String),
+
+    #[error("{0} is a system mount point")]
+    Critical(String),
+
+    #[error("{path} is inside {reserved}, which the system manages")]
+    Reserved { path: String, reserved: String },
+
+    #[error("{path} is already used by {}", partition.display())]
+    AlreadyUsed { path: String, partition: PathBuf },
+}
+
+/// Check a mount point about to be added to `disks`, returns it normalized
+/// (no repeated, trailing or `.` components)
+pub fn validate_mount_point(input: &str, disks: &[Disk]) -> Result<String, InvalidMountPoint> {
+    let mount_point = normalize(input)?;
+
+    if CRITICAL_MOUNT_POINTS.contains(&mount_point.as_str()) {
+        return Err(InvalidMountPoint::Critical(mount_point));
+    }
+    // Everything is under "/", only the other critical mounts are off limits
+    let protected = RESERVED_DIRECTORIES
+        .iter()
+        .chain(CRITICAL_MOUNT_POINTS.iter().filter(|&&mp| mp != "/"));
+    for reserved in protected {
+        if mount_point == *reserved || is_inside(&mount_point, reserved) {
+            return Err(InvalidMountPoint::Reserved {
+                path: mount_point,
+                reserved: reserved.to_string(),
+            });
+        }
+    }
+
+    if let Some(partition) = disks
+        .iter()
+        .flat_map(|d| &d.partitions)
+        .find(|p| p.mount_points.contains(&mount_point))
+    {
+        return Err(InvalidMountPoint::AlreadyUsed {
+            path: mount_point,
+            partition: partition.path.clone(),
+        });
+    }
+
+    Ok(mount_point)
+}
+
+fn normalize(input: &str) -> Result<String, InvalidMountPoint> {
+    if input.is_empty() {
+        return Err(InvalidMountPoint::Empty);
+    }
+    if !input.starts_with('/') {
+        return Err(InvalidMountPoint::NotAbsolute(input.to_string()));
+    }
+    if input.chars().any(char::is_control) {
+        return Err(InvalidMountPoint::ControlCharacter(input.to_string()));
+    }
+
+    let mut components = Vec::new();
+    for component in input.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(InvalidMountPoint::ParentDirectory(input.to_string())),
+            name => components.push(name),
+        }
+    }
+    Ok(format!("/{}", components.join("/")))
+}
+
+/// `path` is below `dir`, both normalized
+fn is_inside(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Partition;
+
+    #[test]
+    fn test_validate_mount_point() {
+        let partition = Partition::new(
+            PathBuf::from("/dev/sdb1"),
+            PathBuf::from("/dev/disk/by-uuid/1234"),
+            vec!["/media/data".to_string()],
+            Some("ext4".to_string()),
+            0,
+            None,
+        );
+        let disks = vec![Disk::new(PathBuf::from("/dev/sdb"), vec![partition], 0)];
+
+        assert_eq!(
+            validate_mount_point("//media/./photos/", &disks),
+            Ok("/media/photos".to_string())
+        );
+        assert_eq!(
+            validate_mount_point("/media/a \"b\" ${c}", &disks),
+            Ok("/media/a \"b\" ${c}".to_string())
+        );
+        assert_eq!(
+            validate_mount_point("/media/../etc", &disks),
+            Err(InvalidMountPoint::ParentDirectory(
+                "/media/../etc".to_string()
+            ))
+        );
+        assert!(matches!(
+            validate_mount_point("/boot/", &disks),
+            Err(InvalidMountPoint::Critical(_))
+        ));
+        assert!(matches!(
+            validate_mount_point("/nix/store/x", &disks),
+            Err(InvalidMountPoint::Reserved { .. })
+        ));
+        assert!(matches!(
+            validate_mount_point("/proc", &disks),
+            Err(InvalidMountPoint::Reserved { .. })
+        ));
+        assert!(validate_mount_point("/processing", &disks).is_ok());
+        assert!(matches!(
+            validate_mount_point("/media/data/", &disks),
+            Err(InvalidMountPoint::AlreadyUsed { .. })
+        ));
+    }
+}
@@ -0,0 +1,403 @@
+//! nix-disk's own NixOS module
+//!
+//! `nixos-generate-config` regenerates hardware-configuration.nix, so the
+//! mount points managed by nix-disk live in a separate `nix-disk.nix`
+//! imported from configuration.nix.
+
+use crate::error::{NixDiskError, read_nix_file};
+use crate::models::NixConfig;
+use crate::utils::FlakeTarget;
+use crate::utils::disk_writer::{
+    Edit, append_to_attr_set, apply_edits, expand_to_lines, is_critical_mount_point, line_indent,
+    removal_edits,
+};
+use crate::utils::file::write_file;
+use crate::utils::nix_parser::{self, Expr};
+use crate::utils::parse_nix_filesystems;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const MODULE_FILE_NAME: &str = "nix-disk.nix";
+
+/// Location of the configuration files nix-disk works with
+#[derive(Debug, Clone)]
+pub struct ConfigPaths {
+    pub hardware_config: PathBuf,
+    pub module: PathBuf,
+    pub configuration: PathBuf,
+    /// Set when the system is built from a flake
+    pub flake: Option<FlakeTarget>,
+}
+
+impl ConfigPaths {
+    pub fn new(config_dir: &Path) -> Self {
+        Self {
+            hardware_config: config_dir.join("hardware-configuration.nix"),
+            module: config_dir.join(MODULE_FILE_NAME),
+            configuration: config_dir.join("configuration.nix"),
+            flake: None,
+        }
+    }
+
+    /// `nixos-rebuild` arguments applying this configuration
+    pub fn rebuild_args(&self) -> Vec<String> {
+        let mut args = vec!["switch".to_string()];
+        if let Some(flake) = &self.flake {
+            args.push("--flake".to_string());
+            args.push(flake.flake_ref());
+        }
+        args
+    }
+}
+
+impl Default for ConfigPaths {
+    fn default() -> Self {
+        Self::new(Path::new("/etc/nixos"))
+    }
+}
+
+/// Content of a freshly created nix-disk.nix, taking the arguments the
+/// entries moved from hardware-configuration.nix may use (`config`,
+/// `lib.mkForce`...)
+pub fn empty_module() -> String {
+    "# Mount points managed by nix-disk.\n\
+     # Manual edits are preserved, but nix-disk adds and removes entries here.\n\
+     { config, lib, pkgs, ... }:\n\
+     \n\
+     {\n\
+     }\n"
+    .to_string()
+}
+
+/// The top-level `imports` list of a module
+pub(crate) fn imports_list(expr: &Expr) -> Option<&Expr> {
+    let Expr::AttrSet { bindings, .. } = expr.strip_wrappers() else {
+        return None;
+    };
+    bindings
+        .iter()
+        .find(|b| b.path_names().as_deref() == Some(&["imports"][..]))
+        .map(|b| b.value.strip_wrappers())
+}
+
+fn is_module_path(source: &str, item: &Expr) -> bool {
+    let path = match item {
+        Expr::Str {
+            value: Some(value), ..
+        } => value.as_str(),
+        other => &source[other.span()],
+    };
+    path == MODULE_FILE_NAME || path.ends_with(&format!("/{}", MODULE_FILE_NAME))
+}
+
+/// Whether `configuration` imports nix-disk.nix
+pub fn is_module_imported(configuration: &str) -> Result<bool> {
+    let expr = nix_parser::parse(configuration).context("Failed to parse configuration.nix")?;
+    Ok(match imports_list(&expr) {
+        Some(Expr::List { items, .. }) => items.iter().any(|i| is_module_path(configuration, i)),
+        _ => false,
+    })
+}
+
+/// Add `./nix-disk.nix` to the imports of `configuration`
+pub fn add_module_import(configuration: &str) -> Result<String> {
+    let expr = nix_parser::parse(configuration).context("Failed to parse configuration.nix")?;
+    let Expr::AttrSet { bindings, span, .. } = expr.strip_wrappers() else {
+        bail!("configuration.nix does not evaluate to an attribute set");
+    };
+    let import = format!("./{}", MODULE_FILE_NAME);
+
+    let edit = match imports_list(&expr) {
+        Some(Expr::List { items, span }) => match items.last() {
+            // Multi-line list: one import per line, aligned with the others
+            Some(last) if configuration[span.clone()].contains('\n') => {
+                let end = last.span().end;
+                Edit {
+                    range: end..end,
+                    text: format!(
+                        "\n{}{}",
+                        line_indent(configuration, last.span().start),
+                        import
+                    ),
+                }
+            }
+            Some(last) => {
+                let end = last.span().end;
+                Edit {
+                    range: end..end,
+                    text: format!(" {}", import),
+                }
+            }
+            None => Edit {
+                range: span.start + 1..span.end - 1,
+                text: format!(" {} ", import),
+            },
+        },
+        Some(_) => bail!("imports of configuration.nix is not a plain list, add {import} manually"),
+        None => match bindings.first() {
+            Some(first) => Edit {
+                range: first.span.start..first.span.start,
+                text: format!(
+                    "imports = [ {} ];\n\n{}",
+                    import,
+                    line_indent(configuration, first.span.start)
+                ),
+            },
+            None => {
+                append_to_attr_set(configuration, span, &format!("  imports = [ {} ];", import))
+            }
+        },
+    };
+
+    Ok(apply_edits(configuration, vec![edit]))
+}
+
+/// Text of a binding moved to the top level of nix-disk.nix
+fn relocated_binding(source: &str, span: &Range<usize>, nested: bool) -> String {
+    let text = &source[span.clone()];
+    let text = text.strip_prefix("config.").unwrap_or(text);
+    let prefix = if nested { "fileSystems." } else { "" };
+    let indent = line_indent(source, span.start);
+
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| match line.strip_prefix(indent) {
+            _ if i == 0 => format!("  {}{}", prefix, line),
+            _ if line.trim().is_empty() => String::new(),
+            Some(stripped) => format!("  {}", stripped),
+            None => format!("  {}", line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Move the non-critical mount points of `hardware_config` to `module`
+///
+/// Returns the new content of both files and the moved mount points. Entries
+/// needed for boot and entries not referring to a partition by UUID stay
+/// where they are.
+pub fn migrate_filesystems(
+    hardware_config: &str,
+    module: &str,
+) -> Result<(String, String, Vec<String>)> {
+    let hardware_fs = parse_nix_filesystems(hardware_config)?;
+    let module_fs = parse_nix_filesystems(module)?;
+
+    let mut removals = Vec::new();
+    let mut blocks = Vec::new();
+    let mut moved = Vec::new();
+
+    for entry in &hardware_fs.entries {
+        if is_critical_mount_point(&entry.mount_point)
+            || entry.needed_for_boot
+            || !entry
+                .device
+                .as_deref()
+                .is_some_and(|d| d.starts_with("/dev/disk/by-uuid/"))
+        {
+            continue;
+        }
+
+        // Already declared in the module, only drop the duplicate
+        if module_fs.get(&entry.mount_point).is_none() {
+            blocks.extend(
+                entry
+                    .spans
+                    .iter()
+                    .map(|s| relocated_binding(hardware_config, s, entry.nested)),
+            );
+        }
+        removals.extend(
+            entry
+                .spans
+                .iter()
+                .map(|s| expand_to_lines(hardware_config, s)),
+        );
+        moved.push(entry.mount_point.clone());
+    }
+
+    if moved.is_empty() {
+        return Ok((hardware_config.to_string(), module.to_string(), moved));
+    }
+
+    let new_module = if blocks.is_empty() {
+        module.to_string()
+    } else {
+        let module_span = module_fs
+            .module_span
+            .context("nix-disk.nix does not contain an attribute set")?;
+        apply_edits(
+            module,
+            vec![append_to_attr_set(
+                module,
+                &module_span,
+                &blocks.join("\n\n"),
+            )],
+        )
+    };
+
+    Ok((
+        apply_edits(hardware_config, removal_edits(removals)),
+        new_module,
+        moved,
+    ))
+}
+
+/// Whether nix-disk.nix exists and is imported from configuration.nix
+pub fn module_in_use(paths: &ConfigPaths) -> bool {
+    paths.module.exists()
+        && fs::read_to_string(&paths.configuration)
+            .ok()
+            .and_then(|c| is_module_imported(&c).ok())
+            .unwrap_or(false)
+}
+
+/// Load the file nix-disk should manage: nix-disk.nix when it is in use,
+/// hardware-configuration.nix otherwise
+pub fn load_config(paths: &ConfigPaths) -> Result<NixConfig> {
+    let hardware_config = read_nix_file(&paths.hardware_config)?;
+
+    if !module_in_use(paths) {
+        return Ok(NixConfig::new(
+            paths.hardware_config.clone(),
+            hardware_config,
+        ));
+    }
+
+    let module = read_nix_file(&paths.module)?;
+    let mut config = NixConfig::new(paths.module.clone(), module);
+    config.readonly.push(hardware_config);
+    Ok(config)
+}
+
+/// Flakes only see files known to git, register a new file in the flake's repository
+pub fn track_in_git(flake_dir: &Path, file: &Path) -> Result<()> {
+    if !flake_dir.join(".git").exists() {
+        return Ok(());
+    }
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(flake_dir)
+        .args(["add", "--intent-to-add", "--"])
+        .arg(file)
+        .status()
+        .map_err(|e| NixDiskError::tool("git", e))?;
+    if !status.success() {
+        bail!("git add failed for {}", file.display());
+    }
+    Ok(())
+}
+
+/// Files to write to set up nix-disk.nix
+#[derive(Debug, Clone)]
+pub struct ModuleSetup {
+    /// New content of each file, in the order they must be written: the
+    /// module first so that no mount point is lost if a later write fails
+    pub writes: Vec<(PathBuf, String)>,
+    /// Mount points moved out of hardware-configuration.nix
+    pub moved: Vec<String>,
+}
+
+/// Prepare nix-disk.nix, its import from configuration.nix and the move of
+/// the non-critical mount points of hardware-configuration.nix into it
+pub fn plan_module_setup(paths: &ConfigPaths) -> Result<ModuleSetup> {
+    let hardware_config = read_nix_file(&paths.hardware_config)?;
+    let configuration = read_nix_file(&paths.configuration)?;
+    let module = if paths.module.exists() {
+        read_nix_file(&paths.module)?
+    } else {
+        empty_module()
+    };
+
+    let (new_hardware_config, new_module, moved) = migrate_filesystems(&hardware_config, &module)?;
+    let new_configuration = if is_module_imported(&configuration)? {
+        configuration.clone()
+    } else {
+        add_module_import(&configuration)?
+    };
+
+    let mut writes = vec![(paths.module.clone(), new_module)];
+    if new_configuration != configuration {
+        writes.push((paths.configuration.clone(), new_configuration));
+    }
+    if new_hardware_config != hardware_config {
+        writes.push((paths.hardware_config.clone(), new_hardware_config));
+    }
+
+    Ok(ModuleSetup { writes, moved })
+}
+
+/// Set up nix-disk.nix by writing the files directly
+///
+/// Returns the moved mount points.
+pub fn enable_module(paths: &ConfigPaths) -> Result<Vec<String>> {
+    let setup = plan_module_setup(paths)?;
+    for (path, content) in &setup.writes {
+        let created = !path.exists();
+        write_file(path, content)?;
+        if created && let Some(flake) = &paths.flake {
+            track_in_git(&flake.dir, path)?;
+        }
+    }
+    Ok(setup.moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_module_import() {
+        let configuration =
+            "{ pkgs, ... }:\n{\n  imports = [\n    ./hardware-configuration.nix\n  ];\n}\n";
+        assert!(!is_module_imported(configuration).unwrap());
+
+        let updated = add_module_import(configuration).unwrap();
+        assert_eq!(
+            updated,
+            "{ pkgs, ... }:\n{\n  imports = [\n    ./hardware-configuration.nix\n    ./nix-disk.nix\n  ];\n}\n"
+        );
+        assert!(is_module_imported(&updated).unwrap());
+
+        let updated =
+            add_module_import("{ ... }:\n{\n  boot.loader.grub.enable = true;\n}\n").unwrap();
+        assert_eq!(
+            updated,
+            "{ ... }:\n{\n  imports = [ ./nix-disk.nix ];\n\n  boot.loader.grub.enable = true;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_filesystems() {
+        let hardware_config = r#"{ ... }:
+{
+  fileSystems."/" = {
+    device = "/dev/disk/by-uuid/root";
+    fsType = "ext4";
+  };
+
+  fileSystems."/media/data" = {
+    device = "/dev/disk/by-uuid/data";
+    fsType = "ext4";
+    options = [ "noatime" ];
+  };
+
+  swapDevices = [ ];
+}
+"#;
+        let (hardware_config, module, moved) =
+            migrate_filesystems(hardware_config, &empty_module()).unwrap();
+
+        assert_eq!(moved, vec!["/media/data"]);
+        // Moved entries may refer to the module arguments
+        assert!(module.contains("{ config, lib, pkgs, ... }:"));
+        assert!(!hardware_config.contains("/media/data"));
+        assert!(hardware_config.contains("fileSystems.\"/\""));
+        assert!(module.ends_with(
+            "{\n  fileSystems.\"/media/data\" = {\n    device = \"/dev/disk/by-uuid/data\";\n    fsType = \"ext4\";\n    options = [ \"noatime\" ];\n  };\n}\n"
+        ));
+    }
+}
@@ -0,0 +1,1138 @@
+//! Minimal Nix expression parser
+//!
+//! The whole expression grammar is recognised so that nested attribute sets,
+//! multi-line lists, string interpolation and comments never confuse us, but
+//! only the nodes nix-disk cares about (attribute sets, lists, strings,
+//! identifiers, applications) keep their structure in the tree. Every node
+//! carries the byte range it was parsed from so callers can rewrite the
+//! source in place.
+
+use std::fmt;
+use std::ops::Range;
+use thiserror::Error;
+
+/// Error raised when the Nix source cannot be parsed
+#[derive(Debug, Clone, Error)]
+#[error("{message} at line {line}, column {column}")]
+pub struct NixParseError {
+    pub message: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl NixParseError {
+    fn new(source: &str, offset: usize, message: impl Into<String>) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source.as_bytes()[..offset];
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |p| p + 1);
+        let column = offset - line_start + 1;
+        Self {
+            message: message.into(),
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+/// A single attribute name in an attribute path (`a."b".${c}`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrName {
+    /// Literal name, `None` for `${...}` or interpolated strings
+    pub name: Option<String>,
+    pub span: Range<usize>,
+}
+
+/// `path = value;` inside an attribute set or a `let` block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub path: Vec<AttrName>,
+    pub value: Expr,
+    /// From the first attribute name up to and including the `;`
+    pub span: Range<usize>,
+}
+
+impl Binding {
+    /// Literal attribute names of the path, `None` if any of them is dynamic
+    pub fn path_names(&self) -> Option<Vec<&str>> {
+        self.path.iter().map(|a| a.name.as_deref()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    AttrSet {
+        recursive: bool,
+        bindings: Vec<Binding>,
+        span: Range<usize>,
+    },
+    List {
+        items: Vec<Expr>,
+        span: Range<usize>,
+    },
+    /// String literal, `value` is `None` when it contains interpolation
+    Str {
+        value: Option<String>,
+        span: Range<usize>,
+    },
+    Ident {
+        name: String,
+        span: Range<usize>,
+    },
+    Select {
+        expr: Box<Expr>,
+        path: Vec<AttrName>,
+        span: Range<usize>,
+    },
+    Apply {
+        func: Box<Expr>,
+        arg: Box<Expr>,
+        span: Range<usize>,
+    },
+    Lambda {
+        body: Box<Expr>,
+        span: Range<usize>,
+    },
+    LetIn {
+        bindings: Vec<Binding>,
+        body: Box<Expr>,
+        span: Range<usize>,
+    },
+    With {
+        body: Box<Expr>,
+        span: Range<usize>,
+    },
+    Assert {
+        body: Box<Expr>,
+        span: Range<usize>,
+    },
+    BinOp {
+        op: &'static str,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Range<usize>,
+    },
+    /// Anything else: numbers, paths, URIs, `if`, unary operators...
+    Other {
+        span: Range<usize>,
+    },
+}
+
+/// Module system functions that only wrap their last argument
+const WRAPPER_FUNCTIONS: &[&str] = &[
+    "mkIf",
+    "mkForce",
+    "mkDefault",
+    "mkOverride",
+    "mkBefore",
+    "mkAfter",
+    "mkOrder",
+];
+
+impl Expr {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Expr::AttrSet { span, .. }
+            | Expr::List { span, .. }
+            | Expr::Str { span, .. }
+            | Expr::Ident { span, .. }
+            | Expr::Select { span, .. }
+            | Expr::Apply { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::LetIn { span, .. }
+            | Expr::With { span, .. }
+            | Expr::Assert { span, .. }
+            | Expr::BinOp { span, .. }
+            | Expr::Other { span } => span.clone(),
+        }
+    }
+
+    /// Name of the called function for `f x`, `lib.f x y`...
+    pub fn function_name(&self) -> Option<&str> {
+        match self {
+            Expr::Apply { func, .. } => func.function_name(),
+            Expr::Ident { name, .. } => Some(name),
+            Expr::Select { path, .. } => path.last().and_then(|a| a.name.as_deref()),
+            _ => None,
+        }
+    }
+
+    /// Strip function headers, `let`, `with`, `assert` and module system
+    /// wrappers such as `lib.mkForce` to get at the value that matters
+    pub fn strip_wrappers(&self) -> &Expr {
+        match self {
+            Expr::Lambda { body, .. }
+            | Expr::LetIn { body, .. }
+            | Expr::With { body, .. }
+            | Expr::Assert { body, .. } => body.strip_wrappers(),
+            Expr::Apply { arg, .. }
+                if self
+                    .function_name()
+                    .is_some_and(|name| WRAPPER_FUNCTIONS.contains(&name)) =>
+            {
+                arg.strip_wrappers()
+            }
+            _ => self,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.strip_wrappers() {
+            Expr::Str { value, .. } => value.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.strip_wrappers() {
+            Expr::Ident { name, .. } if name == "true" => Some(true),
+            Expr::Ident { name, .. } if name == "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Literal strings of a list, non-literal items are skipped
+    pub fn as_string_list(&self) -> Option<Vec<String>> {
+        match self.strip_wrappers() {
+            Expr::List { items, .. } => Some(
+                items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(str::to_string))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a complete Nix expression
+pub fn parse(source: &str) -> Result<Expr, NixParseError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    let mut parser = Parser {
+        source,
+        tokens,
+        pos: 0,
+    };
+    let expr = parser.parse_expr()?;
+    if !parser.at_eof() {
+        return Err(parser.error("expected end of file"));
+    }
+    Ok(expr)
+}
+
+/// `value` as a double-quoted Nix string, with `"`, `\` and `${` escaped
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Str(Option<String>),
+    /// `${` outside of a string
+    DollarBrace,
+    /// Numbers, paths and URIs
+    Literal,
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(name) => write!(f, "'{}'", name),
+            TokenKind::Str(_) => write!(f, "string"),
+            TokenKind::DollarBrace => write!(f, "'${{'"),
+            TokenKind::Literal => write!(f, "literal"),
+            TokenKind::Punct(p) => write!(f, "'{}'", p),
+            TokenKind::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+/// Longest operators first so that `//` wins over `/`
+const PUNCTUATION: &[&str] = &[
+    "...", "${", "==", "!=", "<=", ">=", "&&", "||", "->", "//", "++", "{", "}", "[", "]", "(",
+    ")", ";", ":", ",", ".", "=", "@", "?", "+", "-", "*", "/", "<", ">", "!",
+];
+
+const BINARY_OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "->", "//", "++", "+", "-", "*", "/", "<", ">",
+];
+
+const KEYWORDS: &[&str] = &[
+    "let", "in", "rec", "with", "if", "then", "else", "assert", "inherit", "or",
+];
+
+fn is_path_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-' | b'+')
+}
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'\'' | b'-')
+}
+
+fn is_uri_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"%/?:@&=+$,-_.!~*'".contains(&b)
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            bytes: source.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, NixParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let eof = token.kind == TokenKind::Eof;
+            tokens.push(token);
+            if eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> NixParseError {
+        NixParseError::new(self.source, offset, message)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.bytes[self.pos..].starts_with(s.as_bytes())
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), NixParseError> {
+        loop {
+            match self.peek_at(0) {
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(b'#') => {
+                    while let Some(b) = self.peek_at(0) {
+                        if b == b'\n' {
+                            break;
+                        }
+                        self.pos += 1;
+                    }
+                }
+                Some(b'/') if self.peek_at(1) == Some(b'*') => {
+                    let start = self.pos;
+                    match self.source[self.pos + 2..].find("*/") {
+                        Some(end) => self.pos += end + 4,
+                        None => return Err(self.error(start, "unterminated comment")),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, NixParseError> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let Some(b) = self.peek_at(0) else {
+            return Ok(Token {
+                kind: TokenKind::Eof,
+                span: start..start,
+            });
+        };
+
+        let kind = if b == b'"' {
+            self.pos += 1;
+            TokenKind::Str(self.lex_string(start)?)
+        } else if self.starts_with("''") {
+            self.pos += 2;
+            TokenKind::Str(self.lex_indented_string(start)?)
+        } else if self.starts_with("${") {
+            self.pos += 2;
+            TokenKind::DollarBrace
+        } else if let Some(len) = self.match_search_path() {
+            self.pos += len;
+            TokenKind::Literal
+        } else if let Some(len) = self.match_path() {
+            self.pos += len;
+            self.lex_path_interpolations()?;
+            TokenKind::Literal
+        } else if let Some(len) = self.match_uri() {
+            self.pos += len;
+            TokenKind::Literal
+        } else if is_ident_start(b) {
+            while self.peek_at(0).is_some_and(is_ident_char) {
+                self.pos += 1;
+            }
+            TokenKind::Ident(self.source[start..self.pos].to_string())
+        } else if b.is_ascii_digit() {
+            while self
+                .peek_at(0)
+                .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'.')
+            {
+                self.pos += 1;
+            }
+            TokenKind::Literal
+        } else if let Some(punct) = PUNCTUATION.iter().find(|p| self.starts_with(p)) {
+            self.pos += punct.len();
+            TokenKind::Punct(punct)
+        } else {
+            return Err(self.error(start, format!("unexpected character '{}'", b as char)));
+        };
+
+        Ok(Token {
+            kind,
+            span: start..self.pos,
+        })
+    }
+
+    /// `<nixpkgs>` or `<nixpkgs/lib>`
+    fn match_search_path(&self) -> Option<usize> {
+        if self.peek_at(0) != Some(b'<') {
+            return None;
+        }
+        let mut len = 1;
+        while let Some(b) = self.peek_at(len) {
+            if b == b'>' {
+                return (len > 1).then_some(len + 1);
+            }
+            if !is_path_char(b) && b != b'/' {
+                return None;
+            }
+            len += 1;
+        }
+        None
+    }
+
+    /// `./foo`, `../foo/bar.nix`, `/etc/nixos`, `~/foo`, `foo/bar`, up to
+    /// the first interpolation of `./foo/${name}.nix`
+    fn match_path(&self) -> Option<usize> {
+        let mut len = 0;
+        if self.peek_at(0) == Some(b'~') {
+            len = 1;
+        } else {
+            while self.peek_at(len).is_some_and(is_path_char) {
+                len += 1;
+            }
+        }
+        let mut segments = 0;
+        while self.peek_at(len) == Some(b'/')
+            && (self.peek_at(len + 1).is_some_and(is_path_char)
+                || self.bytes[self.pos + len + 1..].starts_with(b"${"))
+        {
+            len += 1;
+            while self.peek_at(len).is_some_and(is_path_char) {
+                len += 1;
+            }
+            segments += 1;
+        }
+        (segments > 0).then_some(len)
+    }
+
+    /// The rest of a path after its first interpolation: `${name}.nix`,
+    /// `${dir}/default.nix`...
+    fn lex_path_interpolations(&mut self) -> Result<(), NixParseError> {
+        loop {
+            if self.starts_with("${") {
+                self.pos += 2;
+                self.skip_interpolation()?;
+            } else if self.peek_at(0).is_some_and(is_path_char)
+                || self.starts_with("/${")
+                || (self.peek_at(0) == Some(b'/') && self.peek_at(1).is_some_and(is_path_char))
+            {
+                self.pos += 1;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// `https://example.org`, Nix treats anything like `scheme:rest` as a URI
+    fn match_uri(&self) -> Option<usize> {
+        if !self.peek_at(0).is_some_and(|b| b.is_ascii_alphabetic()) {
+            return None;
+        }
+        let mut len = 1;
+        while self
+            .peek_at(len)
+            .is_some_and(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'))
+        {
+            len += 1;
+        }
+        if self.peek_at(len) != Some(b':') || !self.peek_at(len + 1).is_some_and(is_uri_char) {
+            return None;
+        }
+        len += 1;
+        while self.peek_at(len).is_some_and(is_uri_char) {
+            len += 1;
+        }
+        Some(len)
+    }
+
+    /// Skip over the expression of a `${ ... }` inside a string
+    fn skip_interpolation(&mut self) -> Result<(), NixParseError> {
+        let mut depth = 1;
+        while depth > 0 {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Punct("{") | TokenKind::DollarBrace => depth += 1,
+                TokenKind::Punct("}") => depth -= 1,
+                TokenKind::Eof => {
+                    return Err(self.error(token.span.start, "unterminated interpolation"));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn lex_string(&mut self, start: usize) -> Result<Option<String>, NixParseError> {
+        let mut value = Vec::new();
+        let mut interpolated = false;
+        loop {
+            match self.peek_at(0) {
+                None => return Err(self.error(start, "unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let Some(escaped) = self.peek_at(1) else {
+                        return Err(self.error(start, "unterminated string"));
+                    };
+                    value.push(match escaped {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'r' => b'\r',
+                        other => other,
+                    });
+                    self.pos += 2;
+                }
+                Some(b'$') if self.peek_at(1) == Some(b'$') => {
+                    value.extend_from_slice(b"$$");
+                    self.pos += 2;
+                }
+                Some(b'$') if self.peek_at(1) == Some(b'{') => {
+                    self.pos += 2;
+                    self.skip_interpolation()?;
+                    interpolated = true;
+                }
+                Some(b) => {
+                    value.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok((!interpolated).then(|| String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    fn lex_indented_string(&mut self, start: usize) -> Result<Option<String>, NixParseError> {
+        let mut value = Vec::new();
+        let mut interpolated = false;
+        loop {
+            if self.starts_with("'''") {
+                value.extend_from_slice(b"''");
+                self.pos += 3;
+            } else if self.starts_with("''$") {
+                value.push(b'$');
+                self.pos += 3;
+            } else if self.starts_with("''\\") {
+                let Some(escaped) = self.peek_at(3) else {
+                    return Err(self.error(start, "unterminated string"));
+                };
+                value.push(match escaped {
+                    b'n' => b'\n',
+                    b't' => b'\t',
+                    b'r' => b'\r',
+                    other => other,
+                });
+                self.pos += 4;
+            } else if self.starts_with("''") {
+                self.pos += 2;
+                break;
+            } else if self.starts_with("$$") {
+                value.extend_from_slice(b"$$");
+                self.pos += 2;
+            } else if self.starts_with("${") {
+                self.pos += 2;
+                self.skip_interpolation()?;
+                interpolated = true;
+            } else if let Some(b) = self.peek_at(0) {
+                value.push(b);
+                self.pos += 1;
+            } else {
+                return Err(self.error(start, "unterminated string"));
+            }
+        }
+        Ok((!interpolated).then(|| strip_indentation(&String::from_utf8_lossy(&value))))
+    }
+}
+
+/// Remove the common indentation of an indented string, like Nix does
+fn strip_indentation(raw: &str) -> String {
+    let raw = match raw.split_once('\n') {
+        Some((first, rest)) if first.trim().is_empty() => rest,
+        _ => raw,
+    };
+    let indent = raw
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or(0);
+    let mut stripped: Vec<&str> = raw
+        .split('\n')
+        .map(|line| line.get(indent..).unwrap_or(line.trim_start_matches(' ')))
+        .collect();
+    if stripped.last().is_some_and(|line| line.trim().is_empty()) {
+        stripped.pop();
+        stripped.push("");
+    }
+    stripped.join("\n")
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &TokenKind {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> &TokenKind {
+        let index = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    fn at_eof(&self) -> bool {
+        *self.peek() == TokenKind::Eof
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), TokenKind::Punct(p) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), TokenKind::Ident(name) if name == keyword)
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos.min(self.tokens.len() - 1)].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// End offset of the last consumed token
+    fn last_end(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .map_or(0, |index| self.tokens[index].span.end)
+    }
+
+    fn start(&self) -> usize {
+        self.tokens[self.pos.min(self.tokens.len() - 1)].span.start
+    }
+
+    fn error(&self, message: impl Into<String>) -> NixParseError {
+        NixParseError::new(self.source, self.start(), message)
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<Token, NixParseError> {
+        if self.is_punct(punct) {
+            Ok(self.bump())
+        } else {
+            Err(self.error(format!("expected '{}', found {}", punct, self.peek())))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), NixParseError> {
+        if self.is_keyword(keyword) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}', found {}", keyword, self.peek())))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+
+        if self.is_keyword("let") && !matches!(self.peek_nth(1), TokenKind::Punct("{")) {
+            self.bump();
+            let bindings = self.parse_bindings(|p| p.is_keyword("in"))?;
+            self.expect_keyword("in")?;
+            let body = Box::new(self.parse_expr()?);
+            return Ok(Expr::LetIn {
+                bindings,
+                body,
+                span: start..self.last_end(),
+            });
+        }
+
+        if self.is_keyword("with") || self.is_keyword("assert") {
+            let is_with = self.is_keyword("with");
+            self.bump();
+            self.parse_expr()?;
+            self.expect_punct(";")?;
+            let body = Box::new(self.parse_expr()?);
+            let span = start..self.last_end();
+            return Ok(if is_with {
+                Expr::With { body, span }
+            } else {
+                Expr::Assert { body, span }
+            });
+        }
+
+        if self.is_keyword("if") {
+            self.bump();
+            self.parse_expr()?;
+            self.expect_keyword("then")?;
+            self.parse_expr()?;
+            self.expect_keyword("else")?;
+            self.parse_expr()?;
+            return Ok(Expr::Other {
+                span: start..self.last_end(),
+            });
+        }
+
+        if self.at_lambda() {
+            return self.parse_lambda();
+        }
+
+        self.parse_operation()
+    }
+
+    fn at_lambda(&self) -> bool {
+        match self.peek() {
+            TokenKind::Ident(name) if !KEYWORDS.contains(&name.as_str()) => matches!(
+                self.peek_nth(1),
+                TokenKind::Punct(":") | TokenKind::Punct("@")
+            ),
+            TokenKind::Punct("{") => match (self.peek_nth(1), self.peek_nth(2)) {
+                (TokenKind::Punct("..."), _) => true,
+                (TokenKind::Punct("}"), next) => {
+                    matches!(next, TokenKind::Punct(":") | TokenKind::Punct("@"))
+                }
+                (TokenKind::Ident(name), next) if !KEYWORDS.contains(&name.as_str()) => matches!(
+                    next,
+                    TokenKind::Punct(",") | TokenKind::Punct("?") | TokenKind::Punct("}")
+                ),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn parse_lambda(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+        if matches!(self.peek(), TokenKind::Ident(_)) {
+            self.bump();
+            if self.is_punct("@") {
+                self.bump();
+                self.parse_formals()?;
+            }
+        } else {
+            self.parse_formals()?;
+            if self.is_punct("@") {
+                self.bump();
+                match self.bump().kind {
+                    TokenKind::Ident(_) => {}
+                    other => return Err(self.error(format!("expected identifier, found {other}"))),
+                }
+            }
+        }
+        self.expect_punct(":")?;
+        let body = Box::new(self.parse_expr()?);
+        Ok(Expr::Lambda {
+            body,
+            span: start..self.last_end(),
+        })
+    }
+
+    /// `{ a, b ? default, ... }`
+    fn parse_formals(&mut self) -> Result<(), NixParseError> {
+        self.expect_punct("{")?;
+        loop {
+            match self.peek().clone() {
+                TokenKind::Punct("}") => break,
+                TokenKind::Punct("...") => {
+                    self.bump();
+                }
+                TokenKind::Ident(_) => {
+                    self.bump();
+                    if self.is_punct("?") {
+                        self.bump();
+                        self.parse_expr()?;
+                    }
+                }
+                other => {
+                    return Err(self.error(format!("expected function argument, found {other}")));
+                }
+            }
+            if !self.is_punct(",") {
+                break;
+            }
+            self.bump();
+        }
+        self.expect_punct("}")?;
+        Ok(())
+    }
+
+    fn parse_operation(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+        let mut lhs = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                TokenKind::Punct(op) if BINARY_OPERATORS.contains(op) => {
+                    let op = *op;
+                    self.bump();
+                    let rhs = self.parse_unary()?;
+                    lhs = Expr::BinOp {
+                        op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                        span: start..self.last_end(),
+                    };
+                }
+                TokenKind::Punct("?") => {
+                    self.bump();
+                    self.parse_attrpath()?;
+                    lhs = Expr::Other {
+                        span: start..self.last_end(),
+                    };
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, NixParseError> {
+        if self.is_punct("!") || self.is_punct("-") {
+            let start = self.start();
+            self.bump();
+            self.parse_unary()?;
+            return Ok(Expr::Other {
+                span: start..self.last_end(),
+            });
+        }
+        self.parse_application()
+    }
+
+    fn at_argument(&self) -> bool {
+        match self.peek() {
+            TokenKind::Ident(name) => name == "rec" || !KEYWORDS.contains(&name.as_str()),
+            TokenKind::Str(_) | TokenKind::Literal => true,
+            TokenKind::Punct(p) => matches!(*p, "(" | "{" | "["),
+            _ => false,
+        }
+    }
+
+    fn parse_application(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+        let mut func = self.parse_select()?;
+        while self.at_argument() {
+            let arg = self.parse_select()?;
+            func = Expr::Apply {
+                func: Box::new(func),
+                arg: Box::new(arg),
+                span: start..self.last_end(),
+            };
+        }
+        Ok(func)
+    }
+
+    fn parse_select(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+        let expr = self.parse_primary()?;
+        if !self.is_punct(".") {
+            return Ok(expr);
+        }
+        self.bump();
+        let path = self.parse_attrpath()?;
+        if self.is_keyword("or") {
+            self.bump();
+            self.parse_select()?;
+        }
+        Ok(Expr::Select {
+            expr: Box::new(expr),
+            path,
+            span: start..self.last_end(),
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, NixParseError> {
+        let start = self.start();
+        match self.peek().clone() {
+            TokenKind::Ident(name) if name == "rec" => {
+                self.bump();
+                self.parse_attr_set(start, true)
+            }
+            TokenKind::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let token = self.bump();
+                Ok(Expr::Ident {
+                    name,
+                    span: token.span,
+                })
+            }
+            TokenKind::Str(value) => {
+                let token = self.bump();
+                Ok(Expr::Str {
+                    value,
+                    span: token.span,
+                })
+            }
+            TokenKind::Literal => Ok(Expr::Other {
+                span: self.bump().span,
+            }),
+            TokenKind::Punct("(") => {
+                self.bump();
+                let inner = self.parse_expr()?;
+                self.expect_punct(")")?;
+                Ok(inner)
+            }
+            TokenKind::Punct("{") => self.parse_attr_set(start, false),
+            TokenKind::Punct("[") => {
+                self.bump();
+                let mut items = Vec::new();
+                while !self.is_punct("]") {
+                    if self.at_eof() {
+                        return Err(self.error("unterminated list"));
+                    }
+                    items.push(self.parse_list_item()?);
+                }
+                self.bump();
+                Ok(Expr::List {
+                    items,
+                    span: start..self.last_end(),
+                })
+            }
+            other => Err(self.error(format!("unexpected {other}"))),
+        }
+    }
+
+    /// A list item: a selection, or a negated one such as `-1`
+    fn parse_list_item(&mut self) -> Result<Expr, NixParseError> {
+        if !self.is_punct("-") {
+            return self.parse_select();
+        }
+        let start = self.start();
+        self.bump();
+        self.parse_select()?;
+        Ok(Expr::Other {
+            span: start..self.last_end(),
+        })
+    }
+
+    fn parse_attr_set(&mut self, start: usize, recursive: bool) -> Result<Expr, NixParseError> {
+        self.expect_punct("{")?;
+        let bindings = self.parse_bindings(|p| p.is_punct("}"))?;
+        self.expect_punct("}")?;
+        Ok(Expr::AttrSet {
+            recursive,
+            bindings,
+            span: start..self.last_end(),
+        })
+    }
+
+    fn parse_bindings(
+        &mut self,
+        at_end: impl Fn(&Self) -> bool,
+    ) -> Result<Vec<Binding>, NixParseError> {
+        let mut bindings = Vec::new();
+        while !at_end(self) {
+            if self.at_eof() {
+                return Err(self.error("unexpected end of file"));
+            }
+            let start = self.start();
+            if self.is_keyword("inherit") {
+                self.bump();
+                if self.is_punct("(") {
+                    self.bump();
+                    self.parse_expr()?;
+                    self.expect_punct(")")?;
+                }
+                while !self.is_punct(";") {
+                    self.parse_attr_name()?;
+                }
+                self.bump();
+                continue;
+            }
+            let path = self.parse_attrpath()?;
+            self.expect_punct("=")?;
+            let value = self.parse_expr()?;
+            self.expect_punct(";")?;
+            bindings.push(Binding {
+                path,
+                value,
+                span: start..self.last_end(),
+            });
+        }
+        Ok(bindings)
+    }
+
+    fn parse_attrpath(&mut self) -> Result<Vec<AttrName>, NixParseError> {
+        let mut path = vec![self.parse_attr_name()?];
+        while self.is_punct(".") {
+            self.bump();
+            path.push(self.parse_attr_name()?);
+        }
+        Ok(path)
+    }
+
+    fn parse_attr_name(&mut self) -> Result<AttrName, NixParseError> {
+        let start = self.start();
+        match self.peek().clone() {
+            TokenKind::Ident(name) => {
+                let token = self.bump();
+                Ok(AttrName {
+                    name: Some(name),
+                    span: token.span,
+                })
+            }
+            TokenKind::Str(value) => {
+                let token = self.bump();
+                Ok(AttrName {
+                    name: value,
+                    span: token.span,
+                })
+            }
+            TokenKind::DollarBrace => {
+                self.bump();
+                self.parse_expr()?;
+                self.expect_punct("}")?;
+                Ok(AttrName {
+                    name: None,
+                    span: start..self.last_end(),
+                })
+            }
+            other => Err(self.error(format!("expected attribute name, found {other}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_module() {
+        let source = r#"
+            # hardware scan }
+            { config, lib, pkgs, ... }:
+            let
+              data = "/data";
+            in
+            {
+              imports = [ (modulesPath + "/installer/scan/not-detected.nix") ];
+              /* { not a block */
+              boot.kernelModules = [ "kvm-intel" ];
+              networking.hostName = "nixos";
+              fileSystems."/" = {
+                device = "/dev/disk/by-uuid/abc";
+                options = [ "subvol=${data}" ];
+              };
+            }
+        "#;
+        let expr = parse(source).unwrap();
+        let Expr::AttrSet { bindings, .. } = expr.strip_wrappers() else {
+            panic!("expected attribute set");
+        };
+        assert_eq!(bindings.len(), 4);
+        assert_eq!(bindings[3].path_names().unwrap(), vec!["fileSystems", "/"]);
+        assert!(source[bindings[3].span.clone()].ends_with("};"));
+    }
+
+    #[test]
+    fn test_strings() {
+        let expr = parse(
+            r#"[ "a\"b" "x${y}" ''
+            first
+              second
+          '' ]"#,
+        )
+        .unwrap();
+        let Expr::List { items, .. } = expr else {
+            panic!("expected list");
+        };
+        assert_eq!(items[0].as_str(), Some("a\"b"));
+        assert_eq!(items[1].as_str(), None);
+        assert_eq!(items[2].as_str(), Some("first\n  second\n"));
+
+        let tricky = "a \"b\" \\ ${c} $${d}\n";
+        assert_eq!(parse(&quote(tricky)).unwrap().as_str(), Some(tricky));
+    }
+
+    #[test]
+    fn test_list_items() {
+        let source = r#"[ -1 ./hosts/${host}.nix ./${name} /etc/${dir}/x "a" ]"#;
+        let Expr::List { items, .. } = parse(source).unwrap() else {
+            panic!("expected list");
+        };
+        let items: Vec<&str> = items.iter().map(|i| &source[i.span()]).collect();
+        assert_eq!(
+            items,
+            vec![
+                "-1",
+                "./hosts/${host}.nix",
+                "./${name}",
+                "/etc/${dir}/x",
+                "\"a\""
+            ]
+        );
+
+        // Still a division outside of paths
+        assert!(matches!(
+            parse("a / b").unwrap(),
+            Expr::BinOp { op: "/", .. }
+        ));
+    }
+
+    #[test]
+    fn test_error_location() {
+        let err = parse("{\n  a = 1;\n  b = ;\n}").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 7));
+    }
+}
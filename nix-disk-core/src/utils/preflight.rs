@@ -0,0 +1,219 @@
+//! Checks run on a generated configuration before it is written
+//!
+//! The syntax is checked with `nix-instantiate --parse`. A dry build can then
+//! evaluate the whole system with the new file, from a copy of the
+//! configuration directory so that nothing is written before it passes.
+
+use crate::error::NixDiskError;
+use crate::utils::nix_module::ConfigPaths;
+use crate::utils::nix_parser::{self, NixParseError};
+use anyhow::{Result, bail};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+/// Check that `content`, about to be written to `path`, is valid Nix
+///
+/// The built-in parser gives a precise location for most mistakes, then
+/// `nix-instantiate --parse` has the last word when it is installed.
+pub fn check_syntax(path: &Path, content: &str) -> Result<()> {
+    let invalid = |source: NixParseError| NixDiskError::CheckFailed {
+        path: path.to_path_buf(),
+        reason: source.to_string(),
+    };
+    nix_parser::parse(content).map_err(invalid)?;
+
+    let child = Command::new("nix-instantiate")
+        .args(["--parse", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("⚠️  nix-instantiate introuvable, seule l'analyse interne est faite");
+            return Ok(());
+        }
+        Err(e) => return Err(NixDiskError::tool("nix-instantiate", e).into()),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // An early exit closes the pipe, the exit status tells why
+        let _ = stdin.write_all(content.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| NixDiskError::tool("nix-instantiate", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(invalid(parse_nix_error(content, &stderr)).into())
+}
+
+/// Message and location of a `nix-instantiate --parse` error
+///
+/// Nix 2.3 ends the message with `, at (string):3:1`; later versions print
+/// `at «stdin»:3:1:` on the next line.
+fn parse_nix_error(source: &str, stderr: &str) -> NixParseError {
+    let mut message = None;
+    let mut location = None;
+    for line in stderr.lines().map(str::trim) {
+        if message.is_none()
+            && let Some(text) = line.strip_prefix("error: ")
+        {
+            message = Some(text.rsplit_once(", at ").map_or(text, |(text, _)| text));
+        }
+        if location.is_none() {
+            location = line
+                .rfind("at ")
+                .and_then(|start| parse_location(&line[start + 3..]));
+        }
+    }
+
+    let (line, column) = location.unwrap_or((1, 1));
+    let offset = source
+        .split_inclusive('\n')
+        .take(line - 1)
+        .map(str::len)
+        .sum::<usize>()
+        + column
+        - 1;
+    NixParseError {
+        message: message.unwrap_or("syntax error").to_string(),
+        offset: offset.min(source.len()),
+        line,
+        column,
+    }
+}
+
+/// `3:1` at the end of `«stdin»:3:1:`
+fn parse_location(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.trim_end_matches(':').rsplitn(3, ':');
+    let column = parts.next()?.parse().ok().filter(|&c| c > 0)?;
+    let line = parts.next()?.parse().ok().filter(|&l| l > 0)?;
+    parts.next()?;
+    Some((line, column))
+}
+
+/// Copy of the configuration directory with one file replaced, deleted when
+/// dropped
+pub struct StagedConfig {
+    /// Removes the copy when dropped
+    _root: TempDir,
+    paths: ConfigPaths,
+}
+
+impl StagedConfig {
+    /// Copy the directory holding the configuration (the flake for a flake
+    /// system) and write `content` to the copy of `path`
+    pub fn new(paths: &ConfigPaths, path: &Path, content: &str) -> Result<Self> {
+        let source = match &paths.flake {
+            Some(flake) => flake.dir.clone(),
+            None => paths
+                .configuration
+                .parent()
+                .unwrap_or(Path::new("/"))
+                .to_path_buf(),
+        };
+        let Ok(relative) = path.strip_prefix(&source) else {
+            bail!("{} is outside of {}", path.display(), source.display());
+        };
+
+        // Private and unpredictable, other users cannot slip files in
+        let temp_dir = tempfile::Builder::new()
+            .prefix("nix-disk-preflight-")
+            .tempdir()
+            .map_err(|e| NixDiskError::io(&std::env::temp_dir(), e))?;
+        let root = temp_dir.path().to_path_buf();
+        let relocate = |file: &Path| {
+            file.strip_prefix(&source)
+                .map_or_else(|_| file.to_path_buf(), |relative| root.join(relative))
+        };
+        let mut staged_paths = ConfigPaths {
+            hardware_config: relocate(&paths.hardware_config),
+            module: relocate(&paths.module),
+            configuration: relocate(&paths.configuration),
+            flake: paths.flake.clone(),
+        };
+        if let Some(flake) = &mut staged_paths.flake {
+            flake.dir = root.clone();
+        }
+        let staged = Self {
+            _root: temp_dir,
+            paths: staged_paths,
+        };
+
+        copy_dir(&source, &root)?;
+        let target = root.join(relative);
+        // A symlink would write through to the real file
+        if target.is_symlink() {
+            fs::remove_file(&target).map_err(|e| NixDiskError::io(&target, e))?;
+        }
+        fs::write(&target, content).map_err(|e| NixDiskError::io(&target, e))?;
+        Ok(staged)
+    }
+
+    /// `nixos-rebuild dry-build` of the copy: evaluates the whole system
+    /// without building or activating anything
+    pub fn dry_build_command(&self) -> Command {
+        let mut command = Command::new("nixos-rebuild");
+        command.arg("dry-build");
+        match &self.paths.flake {
+            // path: so that files git does not track yet are seen too
+            Some(flake) => command.args([
+                "--flake".to_string(),
+                format!("path:{}#{}", flake.dir.display(), flake.host),
+            ]),
+            None => command.args([
+                "-I".to_string(),
+                format!("nixos-config={}", self.paths.configuration.display()),
+            ]),
+        };
+        command
+    }
+}
+
+/// Copy a directory tree, keeping symlinks as they are and leaving out `.git`
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).map_err(|e| NixDiskError::io(to, e))?;
+    for entry in fs::read_dir(from).map_err(|e| NixDiskError::io(from, e))? {
+        let entry = entry.map_err(|e| NixDiskError::io(from, e))?;
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        let file_type = entry
+            .file_type()
+            .map_err(|e| NixDiskError::io(&source, e))?;
+        if file_type.is_symlink() {
+            let link = fs::read_link(&source).map_err(|e| NixDiskError::io(&source, e))?;
+            std::os::unix::fs::symlink(link, &target).map_err(|e| NixDiskError::io(&target, e))?;
+        } else if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                copy_dir(&source, &target)?;
+            }
+        } else {
+            fs::copy(&source, &target).map_err(|e| NixDiskError::io(&source, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nix_error() {
+        let source = "{\n  a = 1\n}\n";
+        let stderr = "error: syntax error, unexpected '}', expecting ';'\n\n       at «stdin»:3:1:\n\n            2|   a = 1\n            3| }\n             | ^\n";
+        let error = parse_nix_error(source, stderr);
+        assert_eq!(error.message, "syntax error, unexpected '}', expecting ';'");
+        assert_eq!((error.line, error.column, error.offset), (3, 1, 10));
+
+        let old = "error: syntax error, unexpected '}', expecting ';', at (string):3:1\n";
+        let error = parse_nix_error(source, old);
+        assert_eq!(error.message, "syntax error, unexpected '}', expecting ';'");
+        assert_eq!((error.line, error.column), (3, 1));
+    }
+}
@@ -0,0 +1,172 @@
+//! Applying the configuration with `nixos-rebuild` and following its progress
+
+use crate::utils::ConfigPaths;
+use std::process::Command;
+
+/// `nixos-rebuild switch`, with `--flake` when the system is built from a flake
+///
+/// The command is returned unspawned so front-ends can choose how to run it
+/// (in the foreground, behind `sudo`, with captured output...).
+pub fn rebuild_command(paths: &ConfigPaths) -> Command {
+    let mut command = Command::new("nixos-rebuild");
+    command.args(paths.rebuild_args());
+    command
+}
+
+/// Stage of a running `nixos-rebuild switch`, as told by its output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebuildPhase {
+    Building,
+    Activating,
+    RestartingUnits,
+}
+
+/// Phase a line of `nixos-rebuild` output starts, if any
+pub fn parse_phase(line: &str) -> Option<RebuildPhase> {
+    let line = line.trim();
+    if line.starts_with("building the system configuration")
+        || line.starts_with("building Nix")
+        || line.contains("will be built:")
+    {
+        Some(RebuildPhase::Building)
+    } else if line.starts_with("activating the configuration")
+        || line.starts_with("updating GRUB")
+        || line.starts_with("setting up /etc")
+    {
+        Some(RebuildPhase::Activating)
+    } else if line.starts_with("stopping the following units")
+        || line.starts_with("starting the following units")
+        || line.starts_with("restarting the following units")
+        || line.starts_with("reloading the following units")
+        || line.starts_with("reloading user units")
+        || line.starts_with("restarting sysinit-reactivation.target")
+        || line.starts_with("the following new units were started")
+    {
+        Some(RebuildPhase::RestartingUnits)
+    } else {
+        None
+    }
+}
+
+/// What made a rebuild fail, found in its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebuildFailure {
+    /// A derivation failed to build, by name (e.g. `etc`, `unit-foo.service`)
+    Derivation(String),
+    /// The new configuration was built but switching to it failed
+    Activation(String),
+    /// Any other `error:` line, usually an evaluation error
+    Error(String),
+}
+
+/// Name of a derivation from its store path:
+/// `/nix/store/<hash>-etc.drv` gives `etc`
+fn derivation_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let file = file.strip_suffix(".drv").unwrap_or(file);
+    file.split_once('-')
+        .map(|(_, name)| name)
+        .unwrap_or(file)
+        .to_string()
+}
+
+/// Most useful explanation of a failed rebuild from its output
+pub fn parse_failure(output: &[String]) -> Option<RebuildFailure> {
+    for line in output {
+        let line = line.trim();
+        for marker in ["builder for '", "Cannot build '"] {
+            if let Some(rest) = line.split_once(marker).map(|(_, rest)| rest)
+                && let Some((path, _)) = rest.split_once('\'')
+            {
+                return Some(RebuildFailure::Derivation(derivation_name(path)));
+            }
+        }
+    }
+
+    let activation_failed = output
+        .iter()
+        .any(|l| l.contains("error(s) occurred while switching to the new configuration"));
+    if activation_failed {
+        let failed_units = output.iter().find_map(|l| {
+            l.trim()
+                .strip_prefix("warning: the following units failed:")
+                .map(|units| units.trim().to_string())
+        });
+        return Some(RebuildFailure::Activation(failed_units.unwrap_or_default()));
+    }
+
+    output.iter().find_map(|l| {
+        l.trim()
+            .strip_prefix("error:")
+            .map(|message| RebuildFailure::Error(message.trim().to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phase() {
+        let output = [
+            "building the system configuration...",
+            "these 12 derivations will be built:",
+            "  /nix/store/abc-etc.drv",
+            "activating the configuration...",
+            "setting up /etc...",
+            "reloading user units for alice...",
+            "restarting the following units: systemd-journald.service",
+            "Done. The new configuration is /nix/store/xyz-nixos-system",
+        ];
+        let phases: Vec<_> = output.iter().map(|l| parse_phase(l)).collect();
+        assert_eq!(
+            phases,
+            [
+                Some(RebuildPhase::Building),
+                Some(RebuildPhase::Building),
+                None,
+                Some(RebuildPhase::Activating),
+                Some(RebuildPhase::Activating),
+                Some(RebuildPhase::RestartingUnits),
+                Some(RebuildPhase::RestartingUnits),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        let lines = |text: &str| text.lines().map(String::from).collect::<Vec<_>>();
+
+        let build = lines(
+            "building the system configuration...\n\
+             error: builder for '/nix/store/0abc-unit-backup.service.drv' failed with exit code 1;\n\
+             error: 1 dependencies of derivation '/nix/store/1def-etc.drv' failed to build",
+        );
+        assert_eq!(
+            parse_failure(&build),
+            Some(RebuildFailure::Derivation(
+                "unit-backup.service".to_string()
+            ))
+        );
+
+        let activation = lines(
+            "activating the configuration...\n\
+             warning: the following units failed: mnt-data.mount\n\
+             warning: error(s) occurred while switching to the new configuration",
+        );
+        assert_eq!(
+            parse_failure(&activation),
+            Some(RebuildFailure::Activation("mnt-data.mount".to_string()))
+        );
+
+        let evaluation =
+            lines("error: undefined variable 'foo'\n       at /etc/nixos/nix-disk.nix:3:5:");
+        assert_eq!(
+            parse_failure(&evaluation),
+            Some(RebuildFailure::Error(
+                "undefined variable 'foo'".to_string()
+            ))
+        );
+    }
+}
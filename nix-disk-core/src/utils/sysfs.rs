@@ -0,0 +1,411 @@
+//! Block device enumeration from sysfs
+//!
+//! `/sys/class/block` lists every disk and partition. A partition has a
+//! `partition` attribute and its sysfs directory sits inside the one of its
+//! disk, which gives the tree without guessing from device names.
+//!
+//! LUKS volumes, LVM logical volumes and RAID arrays are not disks of their
+//! own: they are read from the `holders` of the devices they are built on.
+
+use crate::models::{BlockDevice, BlockHolder, BlockPartition, LayerKind};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Where sysfs is mounted on a running system
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+/// sysfs sizes are always counted in 512-byte sectors
+const SECTOR_SIZE: u64 = 512;
+
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    fs::read_to_string(dir.join(attr))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn read_flag(dir: &Path, attr: &str) -> bool {
+    read_attr(dir, attr).is_some_and(|value| value == "1")
+}
+
+pub(crate) fn read_size(dir: &Path) -> Result<u64> {
+    let value =
+        read_attr(dir, "size").with_context(|| format!("Failed to read {}/size", dir.display()))?;
+    let sectors = value
+        .parse::<u64>()
+        .with_context(|| format!("Invalid size in {}: {:?}", dir.display(), value))?;
+    Ok(sectors * SECTOR_SIZE)
+}
+
+/// Devices that never hold user data: compressed swap, RAM disks, optical
+/// drives and the eMMC boot/RPMB areas
+pub(crate) fn is_ignored(name: &str) -> bool {
+    if ["zram", "ram", "sr"].iter().any(|p| name.starts_with(p)) {
+        return true;
+    }
+    name.strip_prefix("mmcblk").is_some_and(|rest| {
+        let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        rest.starts_with("boot") || rest == "rpmb"
+    })
+}
+
+/// Devices built on others list them in `slaves`
+fn is_stacked(dir: &Path) -> bool {
+    fs::read_dir(dir.join("slaves")).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// LUKS volumes and logical volumes are told apart by the prefix of their
+/// device-mapper UUID, `CRYPT-LUKS2-…` or `LVM-…`; plain dm-crypt and other
+/// `CRYPT-` targets have no LUKS header and are generic mappers
+fn holder_kind(name: &str, dm_uuid: Option<&str>) -> LayerKind {
+    if name.starts_with("md") {
+        return LayerKind::Raid;
+    }
+    match dm_uuid {
+        Some(uuid) if uuid.starts_with("CRYPT-LUKS") => LayerKind::Luks,
+        Some(uuid) if uuid.starts_with("LVM-") => LayerKind::Lvm,
+        _ => LayerKind::Mapper,
+    }
+}
+
+/// Devices listed in `<dir>/holders`, each with the devices built on it
+///
+/// A holder whose size cannot be read, typically a mapping being torn down,
+/// is left out rather than failing the whole probe.
+fn read_holders(class_dir: &Path, dir: &Path) -> Vec<BlockHolder> {
+    let Ok(entries) = fs::read_dir(dir.join("holders")) else {
+        return Vec::new();
+    };
+    let mut holders = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let holder_dir = class_dir.join(&name);
+        let holder_dir = fs::canonicalize(&holder_dir).unwrap_or(holder_dir);
+        let kind = holder_kind(&name, read_attr(&holder_dir, "dm/uuid").as_deref());
+        holders.extend(read_holder(class_dir, &holder_dir, kind));
+    }
+    holders.sort_by(|a, b| a.name.cmp(&b.name));
+    holders
+}
+
+fn read_holder(class_dir: &Path, dir: &Path, kind: LayerKind) -> Option<BlockHolder> {
+    let size = read_size(dir).ok()?;
+    let mut holders = read_holders(class_dir, dir);
+    // Partitions of a RAID array sit in its directory, like those of a disk
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.path().join("partition").exists() {
+                holders.extend(read_holder(class_dir, &entry.path(), LayerKind::Partition));
+            }
+        }
+    }
+    Some(BlockHolder {
+        name: dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        kind,
+        mapper_name: read_attr(dir, "dm/name"),
+        size,
+        read_only: read_flag(dir, "ro"),
+        fs_type: None,
+        label: None,
+        uuid: None,
+        holders,
+    })
+}
+
+/// List the disks and partitions found under `<sysfs_root>/class/block`
+pub fn probe_block_devices(sysfs_root: &Path) -> Result<Vec<BlockDevice>> {
+    let class_dir = sysfs_root.join("class/block");
+    let entries = fs::read_dir(&class_dir)
+        .with_context(|| format!("Failed to read {}", class_dir.display()))?;
+
+    let mut disks: Vec<BlockDevice> = Vec::new();
+    // (parent disk name, partition)
+    let mut partitions: Vec<(String, BlockPartition)> = Vec::new();
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_ignored(&name) {
+            continue;
+        }
+        // Entries are links into /sys/devices, resolve them to find the parent
+        let dir = fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path());
+        if is_stacked(&dir) {
+            continue;
+        }
+
+        match read_attr(&dir, "partition") {
+            Some(number) => {
+                let Some(parent) = dir.parent().and_then(|p| p.file_name()) else {
+                    continue;
+                };
+                partitions.push((
+                    parent.to_string_lossy().into_owned(),
+                    BlockPartition {
+                        name,
+                        number: number.parse().unwrap_or(0),
+                        size: read_size(&dir)?,
+                        read_only: read_flag(&dir, "ro"),
+                        holders: read_holders(&class_dir, &dir),
+                        ..Default::default()
+                    },
+                ));
+            }
+            None => {
+                let size = read_size(&dir)?;
+                // Unused loop devices
+                if size == 0 {
+                    continue;
+                }
+                disks.push(BlockDevice {
+                    name,
+                    size,
+                    removable: read_flag(&dir, "removable"),
+                    read_only: read_flag(&dir, "ro"),
+                    rotational: read_flag(&dir, "queue/rotational"),
+                    partitions: Vec::new(),
+                    holders: read_holders(&class_dir, &dir),
+                });
+            }
+        }
+    }
+
+    for (parent, partition) in partitions {
+        if let Some(disk) = disks.iter_mut().find(|d| d.name == parent) {
+            disk.partitions.push(partition);
+        }
+    }
+
+    // Directory order is arbitrary
+    disks.sort_by(|a, b| a.name.cmp(&b.name));
+    for disk in &mut disks {
+        disk.partitions.sort_by_key(|p| p.number);
+    }
+
+    Ok(disks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// Build `devices/<path>` with its attributes and link it from `class/block`
+    fn add_device(root: &Path, path: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join("devices").join(path);
+        fs::create_dir_all(dir.join("queue")).unwrap();
+        for (attr, value) in attrs {
+            fs::create_dir_all(dir.join(attr).parent().unwrap()).unwrap();
+            fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+        }
+        let name = dir.file_name().unwrap();
+        symlink(&dir, root.join("class/block").join(name)).unwrap();
+    }
+
+    /// Record that the device at `holder` is built on the one at `slave`
+    fn add_holder(root: &Path, slave: &str, holder: &str) {
+        let slave = root.join("devices").join(slave);
+        let holder = root.join("devices").join(holder);
+        for (dir, link, target) in [("holders", &slave, &holder), ("slaves", &holder, &slave)] {
+            fs::create_dir_all(link.join(dir)).unwrap();
+            symlink(target, link.join(dir).join(target.file_name().unwrap())).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_probe_block_devices() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("class/block")).unwrap();
+
+        add_device(
+            root,
+            "pci/block/sda",
+            &[("size", "2048"), ("queue/rotational", "1")],
+        );
+        add_device(
+            root,
+            "pci/block/sda/sda2",
+            &[("size", "1024"), ("partition", "2")],
+        );
+        add_device(
+            root,
+            "pci/block/sda/sda1",
+            &[("size", "512"), ("partition", "1")],
+        );
+        // Must not be taken for a partition of sda
+        add_device(
+            root,
+            "pci/block/sdaa",
+            &[("size", "4096"), ("removable", "1")],
+        );
+        add_device(
+            root,
+            "pci/block/sdaa/sdaa1",
+            &[("size", "4096"), ("partition", "1")],
+        );
+        add_device(root, "mmc/block/mmcblk0", &[("size", "8192")]);
+        add_device(
+            root,
+            "mmc/block/mmcblk0/mmcblk0p1",
+            &[("size", "8192"), ("partition", "1")],
+        );
+        add_device(
+            root,
+            "mmc/block/mmcblk0boot0",
+            &[("size", "8"), ("ro", "1")],
+        );
+        add_device(root, "virtual/block/loop0", &[("size", "0")]);
+        add_device(root, "virtual/block/loop1", &[("size", "100")]);
+        add_device(
+            root,
+            "virtual/block/loop1/loop1p1",
+            &[("size", "100"), ("partition", "1")],
+        );
+        add_device(root, "virtual/block/zram0", &[("size", "100")]);
+
+        let disks = probe_block_devices(root).unwrap();
+        let tree: Vec<(&str, Vec<&str>)> = disks
+            .iter()
+            .map(|d| {
+                (
+                    d.name.as_str(),
+                    d.partitions.iter().map(|p| p.name.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                ("loop1", vec!["loop1p1"]),
+                ("mmcblk0", vec!["mmcblk0p1"]),
+                ("sda", vec!["sda1", "sda2"]),
+                ("sdaa", vec!["sdaa1"]),
+            ]
+        );
+
+        let sda = &disks[2];
+        assert_eq!(sda.size, 2048 * 512);
+        assert!(sda.rotational);
+        assert!(!sda.removable);
+        assert_eq!(sda.partitions[1].size, 1024 * 512);
+        assert_eq!(sda.partitions[1].dev_path(), PathBuf::from("/dev/sda2"));
+        assert!(disks[3].removable);
+    }
+
+    #[test]
+    fn test_probe_holders() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("class/block")).unwrap();
+
+        // sda2 → LUKS cryptroot → LVM vg-root
+        add_device(root, "pci/block/sda", &[("size", "4096")]);
+        add_device(
+            root,
+            "pci/block/sda/sda2",
+            &[("size", "4096"), ("partition", "2")],
+        );
+        add_device(
+            root,
+            "virtual/block/dm-0",
+            &[
+                ("size", "4000"),
+                ("dm/name", "cryptroot"),
+                ("dm/uuid", "CRYPT-LUKS2-9f2c-cryptroot"),
+            ],
+        );
+        add_device(
+            root,
+            "virtual/block/dm-1",
+            &[
+                ("size", "2000"),
+                ("dm/name", "vg-root"),
+                ("dm/uuid", "LVM-abcdef"),
+            ],
+        );
+        add_holder(root, "pci/block/sda/sda2", "virtual/block/dm-0");
+        add_holder(root, "virtual/block/dm-0", "virtual/block/dm-1");
+
+        // sdb1 + sdc → partitioned md0
+        add_device(root, "pci/block/sdb", &[("size", "2048")]);
+        add_device(
+            root,
+            "pci/block/sdb/sdb1",
+            &[("size", "2048"), ("partition", "1")],
+        );
+        add_device(root, "pci/block/sdc", &[("size", "2048")]);
+        add_device(root, "virtual/block/md0", &[("size", "2048")]);
+        add_device(
+            root,
+            "virtual/block/md0/md0p1",
+            &[("size", "2000"), ("partition", "1")],
+        );
+        add_holder(root, "pci/block/sdb/sdb1", "virtual/block/md0");
+        add_holder(root, "pci/block/sdc", "virtual/block/md0");
+
+        let disks = probe_block_devices(root).unwrap();
+        let names: Vec<&str> = disks.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["sda", "sdb", "sdc"]);
+
+        let luks = &disks[0].partitions[0].holders[0];
+        assert_eq!(luks.kind, LayerKind::Luks);
+        assert_eq!(luks.dev_path(), PathBuf::from("/dev/mapper/cryptroot"));
+        assert_eq!(luks.kernel_path(), PathBuf::from("/dev/dm-0"));
+        let lv = &luks.holders[0];
+        assert_eq!(lv.kind, LayerKind::Lvm);
+        assert_eq!(lv.display_name(), "vg-root");
+        assert_eq!(lv.size, 2000 * 512);
+        assert!(lv.holders.is_empty());
+
+        let raid = &disks[1].partitions[0].holders[0];
+        assert_eq!(raid.kind, LayerKind::Raid);
+        assert_eq!(raid.dev_path(), PathBuf::from("/dev/md0"));
+        assert_eq!(raid.holders[0].name, "md0p1");
+        assert_eq!(raid.holders[0].kind, LayerKind::Partition);
+        // A disk used whole
+        assert!(disks[2].partitions.is_empty());
+        assert_eq!(disks[2].holders[0].name, "md0");
+    }
+
+    #[test]
+    fn test_probe_unusual_holders() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("class/block")).unwrap();
+
+        // Plain dm-crypt on sda1, and a mapping being removed on sda2
+        add_device(root, "pci/block/sda", &[("size", "4096")]);
+        add_device(
+            root,
+            "pci/block/sda/sda1",
+            &[("size", "2048"), ("partition", "1")],
+        );
+        add_device(
+            root,
+            "pci/block/sda/sda2",
+            &[("size", "2048"), ("partition", "2")],
+        );
+        add_device(
+            root,
+            "virtual/block/dm-0",
+            &[
+                ("size", "2000"),
+                ("dm/name", "swap"),
+                ("dm/uuid", "CRYPT-PLAIN-swap"),
+            ],
+        );
+        add_device(root, "virtual/block/dm-1", &[("dm/name", "gone")]);
+        add_holder(root, "pci/block/sda/sda1", "virtual/block/dm-0");
+        add_holder(root, "pci/block/sda/sda2", "virtual/block/dm-1");
+
+        let disks = probe_block_devices(root).unwrap();
+        let partitions = &disks[0].partitions;
+        assert_eq!(partitions[0].holders[0].kind, LayerKind::Mapper);
+        assert_eq!(partitions[0].holders[0].display_name(), "swap");
+        assert!(partitions[1].holders.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! nix-disk-helper: the privileged half of nix-disk
+//!
+//! Started by D-Bus activation on the system bus, it exposes the
+//! `org.glfos.nixdiskmanager.Helper` interface so the window can run as the
+//! logged-in user. Every method is checked against polkit before running.
+
+mod polkit;
+mod service;
+
+use nix_disk_core::helper::BUS_NAME;
+use std::path::PathBuf;
+
+fn main() {
+    let mut config_dir = PathBuf::from("/etc/nixos");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config-dir" => match args.next() {
+                Some(dir) => config_dir = PathBuf::from(dir),
+                None => {
+                    eprintln!("Error: --config-dir needs a value");
+                    std::process::exit(2);
+                }
+            },
+            _ => {
+                eprintln!("Usage: nix-disk-helper [--config-dir DIR]");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let main_loop = glib::MainLoop::new(None, false);
+    let service = service::Service::new(config_dir);
+
+    let lost_loop = main_loop.clone();
+    let _owner_id = gio::bus_own_name(
+        gio::BusType::System,
+        BUS_NAME,
+        gio::BusNameOwnerFlags::NONE,
+        move |connection, _| {
+            if let Err(e) = service::register(&connection, service.clone()) {
+                eprintln!("❌ Impossible d'enregistrer l'objet D-Bus: {:#}", e);
+            }
+        },
+        |_, name| eprintln!("✅ Nom {} acquis sur le bus système", name),
+        move |_, name| {
+            eprintln!("❌ Nom {} perdu ou indisponible", name);
+            lost_loop.quit();
+        },
+    );
+
+    main_loop.run();
+}
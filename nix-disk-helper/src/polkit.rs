@@ -0,0 +1,73 @@
+//! Authorization of the callers through polkit
+
+use anyhow::{Context, Result};
+use glib::variant::ToVariant;
+use nix_disk_core::NixDiskError;
+use std::collections::HashMap;
+
+/// Let polkit ask the user for a password when the action requires it
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// Ask polkit whether the D-Bus client `sender` may perform `action`
+///
+/// Blocks until the user answers the authentication dialog, so it must not
+/// run on the main loop.
+pub fn check_authorization(
+    connection: &gio::DBusConnection,
+    sender: &str,
+    action: &str,
+) -> Result<()> {
+    let mut subject_details = HashMap::new();
+    subject_details.insert("name".to_string(), sender.to_variant());
+    let subject = ("system-bus-name".to_string(), subject_details);
+    let details: HashMap<String, String> = HashMap::new();
+    let parameters = (subject, details, ALLOW_USER_INTERACTION, String::new()).to_variant();
+
+    let reply = connection
+        .call_sync(
+            Some("org.freedesktop.PolicyKit1"),
+            "/org/freedesktop/PolicyKit1/Authority",
+            "org.freedesktop.PolicyKit1.Authority",
+            "CheckAuthorization",
+            Some(&parameters),
+            Some(glib::VariantTy::new("((bba{ss}))").expect("valid type")),
+            gio::DBusCallFlags::NONE,
+            i32::MAX,
+            None::<&gio::Cancellable>,
+        )
+        .context("polkit CheckAuthorization failed")?;
+
+    let authorized = reply
+        .child_value(0)
+        .child_value(0)
+        .get::<bool>()
+        .unwrap_or(false);
+    if !authorized {
+        return Err(NixDiskError::NotAuthorized {
+            action: action.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Unix uid of the D-Bus client `sender`
+pub fn caller_uid(connection: &gio::DBusConnection, sender: &str) -> Result<u32> {
+    let reply = connection
+        .call_sync(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetConnectionUnixUser",
+            Some(&(sender,).to_variant()),
+            Some(glib::VariantTy::new("(u)").expect("valid type")),
+            gio::DBusCallFlags::NONE,
+            -1,
+            None::<&gio::Cancellable>,
+        )
+        .context("GetConnectionUnixUser failed")?;
+    reply
+        .child_value(0)
+        .get::<u32>()
+        .context("Unexpected reply of GetConnectionUnixUser")
+}
@@ -0,0 +1,314 @@
+//! The `org.glfos.nixdiskmanager.Helper` object
+//!
+//! Arguments are checked before polkit is asked, so a bogus call never
+//! shows an authentication dialog. The work itself runs on a worker thread
+//! and the reply is sent back from the main loop.
+
+use crate::polkit::{caller_uid, check_authorization};
+use anyhow::{Context, Result, bail};
+use glib::variant::ToVariant;
+use nix_disk_core::NixDiskError;
+use nix_disk_core::helper::{
+    ACTION_FORMAT, ACTION_REBUILD, ACTION_WRITE_CONFIG, ERROR_PREFIX, INTERFACE, INTROSPECTION_XML,
+    OBJECT_PATH, OUTPUT_SIGNAL, encode_error,
+};
+use nix_disk_core::utils::format::{Owner, format_disk};
+use nix_disk_core::utils::layout::DiskLayout;
+use nix_disk_core::utils::nix_module::track_in_git;
+use nix_disk_core::utils::nix_parser;
+use nix_disk_core::utils::{
+    BACKUP_DIR, ConfigPaths, FlakeTarget, get_disks, rebuild_command, write_with_backup,
+};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct Service {
+    config_dir: PathBuf,
+    /// Process group of the running nixos-rebuild, for CancelRebuild
+    rebuild_group: Arc<Mutex<Option<u32>>>,
+}
+
+/// Export the helper object on `connection`
+pub fn register(connection: &gio::DBusConnection, service: Service) -> Result<gio::RegistrationId> {
+    let node = gio::DBusNodeInfo::for_xml(INTROSPECTION_XML)?;
+    let interface = node
+        .lookup_interface(INTERFACE)
+        .context("Interface missing from the introspection data")?;
+
+    let id = connection
+        .register_object(OBJECT_PATH, &interface)
+        .method_call(
+            move |connection, sender, _path, _interface, method, parameters, invocation| {
+                let service = service.clone();
+                let sender = sender.to_string();
+                let method = method.to_string();
+                glib::spawn_future_local(async move {
+                    let result = gio::spawn_blocking(move || {
+                        service.call(&connection, &sender, &method, &parameters)
+                    })
+                    .await;
+                    match result {
+                        Ok(Ok(value)) => invocation.return_value(value.as_ref()),
+                        Ok(Err(e)) => {
+                            eprintln!("❌ {:#}", e);
+                            let (name, message) = encode_error(&e);
+                            invocation.return_dbus_error(&name, &message);
+                        }
+                        Err(_) => invocation.return_dbus_error(
+                            &format!("{}.Failed", ERROR_PREFIX),
+                            "The helper thread panicked",
+                        ),
+                    }
+                });
+            },
+        )
+        .build()?;
+    Ok(id)
+}
+
+impl Service {
+    pub fn new(config_dir: PathBuf) -> Self {
+        Self {
+            config_dir,
+            rebuild_group: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn call(
+        &self,
+        connection: &gio::DBusConnection,
+        sender: &str,
+        method: &str,
+        parameters: &glib::Variant,
+    ) -> Result<Option<glib::Variant>> {
+        let config_dir = &self.config_dir;
+        eprintln!("📨 {} demandé par {}", method, sender);
+        match method {
+            "WriteConfig" => {
+                let (path, content, action, expected_hash) = parameters
+                    .get::<(String, String, String, String)>()
+                    .context("Invalid arguments")?;
+                let path = PathBuf::from(path);
+                check_config_path(config_dir, &path)?;
+                nix_parser::parse(&content).map_err(|source| NixDiskError::Parse {
+                    path: path.clone(),
+                    source,
+                })?;
+                check_authorization(connection, sender, ACTION_WRITE_CONFIG)?;
+                // An empty hash skips the check, for files that were not loaded first
+                let expected_hash = Some(expected_hash.as_str()).filter(|h| !h.is_empty());
+                write_config(config_dir, &path, &content, &action, expected_hash)?;
+                Ok(None)
+            }
+            "Rebuild" => {
+                let (flake_ref,) = parameters.get::<(String,)>().context("Invalid arguments")?;
+                let paths = rebuild_paths(config_dir, &flake_ref)?;
+                check_authorization(connection, sender, ACTION_REBUILD)?;
+                let code = self.rebuild(connection, sender, &paths)?;
+                Ok(Some((code,).to_variant()))
+            }
+            "CancelRebuild" => {
+                check_authorization(connection, sender, ACTION_REBUILD)?;
+                self.cancel_rebuild()?;
+                Ok(None)
+            }
+            "Format" => {
+                let (disk, layout, passphrase) = parameters
+                    .get::<(String, String, String)>()
+                    .context("Invalid arguments")?;
+                let disk = PathBuf::from(disk);
+                let layout: DiskLayout =
+                    serde_json::from_str(&layout).context("Invalid partition layout")?;
+                let disk_size = check_format_target(&disk, &layout)?;
+                layout.plan(disk_size)?;
+                check_authorization(connection, sender, ACTION_FORMAT)?;
+                let owner = Owner::lookup(&caller_uid(connection, sender)?.to_string())?;
+                let passphrase = Some(passphrase.as_str()).filter(|p| !p.is_empty());
+                let partitions = format_disk(&disk, &layout, passphrase, Some(owner), &|step| {
+                    emit_output(connection, sender, "format", step);
+                })?;
+                let partitions: Vec<String> = partitions
+                    .iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect();
+                Ok(Some((partitions,).to_variant()))
+            }
+            _ => bail!("Unknown method {}", method),
+        }
+    }
+
+    /// Run `nixos-rebuild`, forwarding each line it prints to the caller
+    fn rebuild(
+        &self,
+        connection: &gio::DBusConnection,
+        sender: &str,
+        paths: &ConfigPaths,
+    ) -> Result<i32> {
+        eprintln!(
+            "🔄 Lancement de nixos-rebuild {}...",
+            paths.rebuild_args().join(" ")
+        );
+        let mut group = self.rebuild_group.lock().expect("rebuild lock poisoned");
+        if group.is_some() {
+            bail!("A rebuild is already running");
+        }
+        // Its own process group, so that cancelling also stops the builds
+        let mut child = rebuild_command(paths)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| NixDiskError::tool("nixos-rebuild", e))?;
+        *group = Some(child.id());
+        drop(group);
+
+        let stdout = child.stdout.take().context("stdout not captured")?;
+        let stderr = child.stderr.take().context("stderr not captured")?;
+        std::thread::scope(|scope| {
+            scope.spawn(|| forward_lines(connection, sender, stdout));
+            forward_lines(connection, sender, stderr);
+        });
+
+        let status = child.wait();
+        *self.rebuild_group.lock().expect("rebuild lock poisoned") = None;
+        let status = status.context("Failed to wait for nixos-rebuild")?;
+        // Report a rebuild killed by a signal the way shells do
+        let code = status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+            .unwrap_or(-1);
+        eprintln!("🏁 nixos-rebuild terminé avec le code {}", code);
+        Ok(code)
+    }
+
+    /// Stop the running nixos-rebuild and everything it started
+    fn cancel_rebuild(&self) -> Result<()> {
+        let Some(group) = *self.rebuild_group.lock().expect("rebuild lock poisoned") else {
+            bail!("No rebuild is running");
+        };
+        eprintln!("🛑 Annulation de nixos-rebuild (groupe {})", group);
+        let status = Command::new("kill")
+            .args(["-TERM", "--", &format!("-{}", group)])
+            .status()
+            .map_err(|e| NixDiskError::tool("kill", e))?;
+        if !status.success() {
+            bail!("kill failed ({})", status);
+        }
+        Ok(())
+    }
+}
+
+/// Only `.nix` files of the configuration directory may be written, also
+/// once symlinks are resolved
+fn check_config_path(config_dir: &Path, path: &Path) -> Result<()> {
+    let inside = path.is_absolute()
+        && path.starts_with(config_dir)
+        && !path.components().any(|c| c == Component::ParentDir)
+        && path.extension().is_some_and(|e| e == "nix");
+    if !inside || !resolved_inside(config_dir, path) {
+        bail!(
+            "Refusing to write {}: not a .nix file of {}",
+            path.display(),
+            config_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether `path`, or its directory when it does not exist yet, still is in
+/// `config_dir` once both are canonicalized. A dangling symlink is refused.
+fn resolved_inside(config_dir: &Path, path: &Path) -> bool {
+    let Ok(config_dir) = config_dir.canonicalize() else {
+        return false;
+    };
+    let resolved = if path.symlink_metadata().is_ok() {
+        path.canonicalize().ok()
+    } else {
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => parent.canonicalize().ok().map(|p| p.join(name)),
+            _ => None,
+        }
+    };
+    resolved.is_some_and(|resolved| {
+        resolved.starts_with(&config_dir) && resolved.extension().is_some_and(|e| e == "nix")
+    })
+}
+
+fn write_config(
+    config_dir: &Path,
+    path: &Path,
+    content: &str,
+    action: &str,
+    expected_hash: Option<&str>,
+) -> Result<()> {
+    let created = !path.exists();
+    let backup = write_with_backup(Path::new(BACKUP_DIR), path, content, action, expected_hash)?;
+    eprintln!("✅ {} écrit", path.display());
+    if let Some(backup) = backup {
+        eprintln!(
+            "🗄️  Version précédente sauvegardée dans {}",
+            backup.dir.display()
+        );
+    }
+    // Flakes only see files known to git
+    if created && config_dir.join("flake.nix").exists() {
+        track_in_git(config_dir, path)?;
+    }
+    Ok(())
+}
+
+/// Rebuild target of a `Rebuild` call: "" for configuration.nix, or a
+/// `<config dir>#<host>` flake reference
+fn rebuild_paths(config_dir: &Path, flake_ref: &str) -> Result<ConfigPaths> {
+    let mut paths = ConfigPaths::new(config_dir);
+    if flake_ref.is_empty() {
+        return Ok(paths);
+    }
+    let Some((dir, host)) = flake_ref.split_once('#') else {
+        bail!("Invalid flake reference: {}", flake_ref);
+    };
+    let valid_host = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if Path::new(dir) != config_dir || !valid_host {
+        bail!("Invalid flake reference: {}", flake_ref);
+    }
+    paths.flake = Some(FlakeTarget {
+        dir: config_dir.to_path_buf(),
+        host: host.to_string(),
+    });
+    Ok(paths)
+}
+
+fn forward_lines(connection: &gio::DBusConnection, sender: &str, output: impl Read) {
+    for line in BufReader::new(output).lines().map_while(Result::ok) {
+        emit_output(connection, sender, "rebuild", &line);
+    }
+}
+
+fn emit_output(connection: &gio::DBusConnection, sender: &str, operation: &str, line: &str) {
+    if let Err(e) = connection.emit_signal(
+        Some(sender),
+        OBJECT_PATH,
+        INTERFACE,
+        OUTPUT_SIGNAL,
+        Some(&(operation, line).to_variant()),
+    ) {
+        eprintln!("⚠️  Impossible d'envoyer la sortie: {}", e);
+    }
+}
+
+/// Only whole disks may be formatted, returns the size of the disk
+fn check_format_target(disk: &Path, layout: &DiskLayout) -> Result<u64> {
+    layout.validate()?;
+    let disks = get_disks(&[])?;
+    match disks.iter().find(|d| d.path == disk) {
+        Some(disk) => Ok(disk.size),
+        None => bail!("{} is not a disk", disk.display()),
+    }
+}
@@ -0,0 +1,106 @@
+{
+  lib,
+  rustPlatform,
+  pkg-config,
+  wrapGAppsHook4,
+  gettext,
+  gtk4,
+  libadwaita,
+  parted,
+  e2fsprogs,
+  btrfs-progs,
+  xfsprogs,
+  exfatprogs,
+  ntfs3g,
+  dosfstools,
+  cryptsetup,
+  util-linux,
+  coreutils,
+  git,
+}:
+
+let
+  # Tools run as root by the helper and by `nix-disk format`
+  tools = {
+    PARTED_BIN = "${parted}/bin/parted";
+    MKFS_EXT4_BIN = "${e2fsprogs}/bin/mkfs.ext4";
+    MKFS_BTRFS_BIN = "${btrfs-progs}/bin/mkfs.btrfs";
+    BTRFS_BIN = "${btrfs-progs}/bin/btrfs";
+    MKFS_XFS_BIN = "${xfsprogs}/bin/mkfs.xfs";
+    MKFS_EXFAT_BIN = "${exfatprogs}/bin/mkfs.exfat";
+    MKFS_NTFS_BIN = "${ntfs3g}/bin/mkfs.ntfs";
+    MKFS_VFAT_BIN = "${dosfstools}/bin/mkfs.vfat";
+    CRYPTSETUP_BIN = "${cryptsetup}/bin/cryptsetup";
+  };
+  wrapperArgs = lib.concatStringsSep " " (
+    lib.mapAttrsToList (name: value: "--set ${name} ${value}") tools
+    ++ [
+      "--prefix PATH : ${
+        lib.makeBinPath [
+          util-linux
+          coreutils
+          git
+        ]
+      }"
+      # nixos-rebuild and nix-instantiate of the running system
+      "--suffix PATH : /run/current-system/sw/bin"
+    ]
+  );
+in
+rustPlatform.buildRustPackage {
+  pname = "nix-disk";
+  version = "2.3.2";
+
+  src = ./.;
+  cargoLock.lockFile = ./Cargo.lock;
+
+  nativeBuildInputs = [
+    pkg-config
+    wrapGAppsHook4
+    gettext
+  ];
+  buildInputs = [
+    gtk4
+    libadwaita
+  ];
+
+  env.LOCALE_DIR = "${placeholder "out"}/share/locale";
+
+  # The D-Bus service, the desktop entry and the polkit actions point at the
+  # binaries of this package
+  postInstall = ''
+    mkdir -p $out/share/dbus-1/system-services $out/share/polkit-1/actions $out/share/applications
+    substitute data/org.glfos.nixdiskmanager.Helper.service.in \
+      $out/share/dbus-1/system-services/org.glfos.nixdiskmanager.Helper.service \
+      --replace-fail @bindir@ $out/bin
+    install -Dm644 data/org.glfos.nixdiskmanager.Helper.conf -t $out/share/dbus-1/system.d
+    substitute data/org.glfos.nixdiskmanager.in \
+      $out/share/polkit-1/actions/org.glfos.nixdiskmanager.policy \
+      --replace-fail @bindir@ $out/bin
+    substitute data/nix-disk.desktop.in $out/share/applications/nix-disk.desktop \
+      --replace-fail @bindir@ $out/bin
+
+    for size in 16 32 48 64 128 256 512; do
+      install -Dm644 data/icons/nix-disk-''${size}x''${size}.png \
+        $out/share/icons/hicolor/''${size}x''${size}/apps/nix-disk.png
+    done
+
+    for po in po/*.po; do
+      lang=$(basename $po .po)
+      mkdir -p $out/share/locale/$lang/LC_MESSAGES
+      msgfmt -o $out/share/locale/$lang/LC_MESSAGES/nixdiskmanager.mo $po
+    done
+  '';
+
+  # wrapGAppsHook4 wraps both binaries
+  preFixup = ''
+    gappsWrapperArgs+=(${wrapperArgs})
+  '';
+
+  meta = {
+    description = "A simple GUI to manage disks on NixOS";
+    license = lib.licenses.gpl3Plus;
+    mainProgram = "nix-disk";
+    platforms = lib.platforms.linux;
+  };
+}
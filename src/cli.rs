@@ -0,0 +1,574 @@
+//! Command-line front-end, used when nix-disk is started with a subcommand
+//!
+//! It works on the same configuration files as the window and runs
+//! everything in the foreground, so it can be used over SSH or from
+//! provisioning scripts.
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use nix_disk_core::NixDiskError;
+use nix_disk_core::models::{Disk, NixConfig, Partition, Unlock};
+use nix_disk_core::utils::format::{FormatOptions, FsType, LuksOptions, Owner, format_disk};
+use nix_disk_core::utils::layout::{DiskLayout, TableType, format_size};
+use nix_disk_core::utils::{
+    BACKUP_DIR, ConfigPaths, StagedConfig, build_inventory, check_syntax, configured_partitions,
+    content_hash, default_host, find_missing_partitions, flake_hosts, get_disks,
+    get_nix_disks_config, is_critical_mount_point, load_config, parse_nix_filesystems,
+    rebuild_command, validate_mount_point, write_with_backup,
+};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Parser)]
+#[command(name = "nix-disk", version, about = "Manage disks on NixOS")]
+pub struct Cli {
+    /// Directory holding the NixOS configuration
+    #[arg(long, global = true, default_value = "/etc/nixos")]
+    pub config_dir: PathBuf,
+
+    /// nixosConfigurations entry to manage when the configuration is a flake
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Without a subcommand the graphical interface is started
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// List disks, partitions and their mount points
+    List {
+        /// Print a versioned JSON document instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Mount a partition at boot
+    AddMount {
+        /// Partition device, e.g. /dev/sdb1 or /dev/disk/by-uuid/...
+        partition: PathBuf,
+        /// Absolute path, or a name mounted under /media
+        mount_point: String,
+        /// Run nixos-rebuild once the configuration is written
+        #[arg(long)]
+        rebuild: bool,
+        /// Check that the system builds with nixos-rebuild dry-build before writing
+        #[arg(long)]
+        dry_build: bool,
+    },
+    /// Stop mounting a partition at boot
+    RemoveMount {
+        mount_point: String,
+        /// Run nixos-rebuild once the configuration is written
+        #[arg(long)]
+        rebuild: bool,
+        /// Check that the system builds with nixos-rebuild dry-build before writing
+        #[arg(long)]
+        dry_build: bool,
+    },
+    /// Erase a disk and create a single partition, or the ones of --layout
+    Format {
+        /// Whole disk device, e.g. /dev/sdb
+        disk: PathBuf,
+        /// Volume name of the new filesystem
+        #[arg(long, required_unless_present = "layout")]
+        label: Option<String>,
+        /// JSON file describing the partition table and partitions to create
+        #[arg(long, conflicts_with = "label")]
+        layout: Option<PathBuf>,
+        /// gpt or mbr
+        #[arg(long, default_value = "gpt")]
+        table: TableType,
+        /// ext4, btrfs, xfs, exfat, ntfs or vfat
+        #[arg(long, default_value = "ext4")]
+        fs_type: FsType,
+        /// btrfs: zstd, lzo, zlib or none
+        #[arg(long, default_value = "zstd")]
+        compression: String,
+        /// btrfs: subvolume to create, the first one is mounted by default
+        #[arg(long = "subvolume")]
+        subvolumes: Vec<String>,
+        /// XFS: do not share blocks between copies
+        #[arg(long)]
+        no_reflink: bool,
+        /// exFAT: cluster size in KiB
+        #[arg(long)]
+        cluster_size: Option<u32>,
+        /// Create the filesystem inside a LUKS volume opened as /dev/mapper/NAME
+        #[arg(long, value_name = "NAME", conflicts_with = "layout")]
+        encrypt: Option<String>,
+        /// crypttab (opened once the system is up, skipped when the disk is
+        /// missing) or initrd (passphrase asked at boot, which waits for the disk)
+        #[arg(long, default_value = "crypttab", requires = "encrypt")]
+        unlock: Unlock,
+        /// crypttab: add a key file so that the volume opens without the passphrase
+        #[arg(long, requires = "encrypt")]
+        key_file: bool,
+        /// User owning the new filesystem, defaults to the user running sudo
+        #[arg(long)]
+        owner: Option<String>,
+        /// Do not ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// List configured partitions that no longer exist, exits with 1 if any
+    CheckMissing {
+        /// Remove them from the configuration
+        #[arg(long)]
+        remove: bool,
+        /// Run nixos-rebuild once the configuration is written
+        #[arg(long)]
+        rebuild: bool,
+        /// Check that the system builds with nixos-rebuild dry-build before writing
+        #[arg(long)]
+        dry_build: bool,
+    },
+    /// Apply the configuration with nixos-rebuild switch
+    Rebuild,
+}
+
+/// Run a subcommand, returning the process exit code
+pub fn run(cli: &Cli, command: &Commands) -> Result<i32> {
+    let paths = resolve_config_paths(&cli.config_dir, cli.host.as_deref())?;
+
+    match command {
+        Commands::List { json } => {
+            let (config, disks) = load(&paths)?;
+            if *json {
+                let inventory = build_inventory(&paths, &config, &disks)?;
+                println!("{}", serde_json::to_string_pretty(&inventory)?);
+            } else {
+                print_disks(&disks);
+            }
+            Ok(0)
+        }
+        Commands::AddMount {
+            partition,
+            mount_point,
+            rebuild,
+            dry_build,
+        } => {
+            let (config, mut disks) = load(&paths)?;
+            let mount_point = validate_mount_point(&expand_mount_point(mount_point), &disks)?;
+
+            let target = find_partition(&mut disks, partition)?;
+            target.add_mount_point(mount_point.clone());
+            println!("{} → {}", target.path.display(), mount_point);
+
+            save(
+                &paths,
+                &config,
+                &disks,
+                &[],
+                &format!("Add mount point {}", mount_point),
+                *dry_build,
+            )?;
+            if *rebuild {
+                rebuild_system(&paths)
+            } else {
+                Ok(0)
+            }
+        }
+        Commands::RemoveMount {
+            mount_point,
+            rebuild,
+            dry_build,
+        } => {
+            let (config, mut disks) = load(&paths)?;
+            let filesystems = parse_nix_filesystems(&config.content)?;
+            if filesystems.get(mount_point).is_none() {
+                bail!(
+                    "{} is not declared in {}",
+                    mount_point,
+                    config.path.display()
+                );
+            }
+
+            // The partition of an unplugged disk is not in `disks`, its entry
+            // only goes away once the partition is forgotten
+            let configured: Vec<Partition> =
+                configured_partitions(&filesystems).into_values().collect();
+            let unplugged: Vec<Partition> = find_missing_partitions(&configured, &disks)
+                .into_iter()
+                .filter(|p| p.mount_points.iter().any(|mp| mp == mount_point))
+                .collect();
+            if let Some(partition) = unplugged.iter().find(|p| p.mount_points.len() > 1) {
+                bail!(
+                    "{} is not plugged in and is also mounted at {}; remove all its mount points with check-missing --remove",
+                    partition.path.display(),
+                    partition
+                        .mount_points
+                        .iter()
+                        .filter(|mp| *mp != mount_point)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+
+            for partition in disks.iter_mut().flat_map(|d| d.partitions.iter_mut()) {
+                partition.remove_mount_point(mount_point);
+            }
+            save(
+                &paths,
+                &config,
+                &disks,
+                &unplugged,
+                &format!("Remove mount point {}", mount_point),
+                *dry_build,
+            )?;
+            if *rebuild {
+                rebuild_system(&paths)
+            } else {
+                Ok(0)
+            }
+        }
+        Commands::Format {
+            disk,
+            label,
+            layout,
+            table,
+            fs_type,
+            compression,
+            subvolumes,
+            no_reflink,
+            cluster_size,
+            encrypt,
+            unlock,
+            key_file,
+            owner,
+            yes,
+        } => {
+            let layout = match (layout, label) {
+                (Some(file), _) => {
+                    let content = fs::read_to_string(file)
+                        .with_context(|| format!("Failed to read {}", file.display()))?;
+                    serde_json::from_str(&content)
+                        .with_context(|| format!("Invalid layout in {}", file.display()))?
+                }
+                (None, label) => DiskLayout {
+                    table: *table,
+                    ..DiskLayout::single(FormatOptions {
+                        compression: Some(compression.clone()).filter(|c| c != "none"),
+                        subvolumes: subvolumes.clone(),
+                        reflink: !no_reflink,
+                        cluster_size: *cluster_size,
+                        encryption: encrypt.as_deref().map(|name| LuksOptions {
+                            unlock: *unlock,
+                            key_file: *key_file,
+                            ..LuksOptions::new(name)
+                        }),
+                        ..FormatOptions::new(*fs_type, label.as_deref().unwrap_or_default())
+                    })
+                },
+            };
+            layout.validate()?;
+            let (_, disks) = load(&paths)?;
+            let Some(disk) = disks.iter().find(|d| &d.path == disk) else {
+                bail!("{} is not a disk", disk.display());
+            };
+            if let Some(mounted) = disk
+                .partitions
+                .iter()
+                .flat_map(|p| &p.mount_points)
+                .find(|mp| is_critical_mount_point(mp))
+            {
+                bail!("{} holds {}", disk.path.display(), mounted);
+            }
+            // Gone once formatted, with what the configuration declares for them
+            let previous = disk.partitions.clone();
+
+            println!(
+                "{} partition table on {}:",
+                layout.table,
+                disk.path.display()
+            );
+            for partition in layout.plan(disk.size)? {
+                println!(
+                    "  {}\t{}\t{}\t{}\t{}\t{}",
+                    partition.number,
+                    format_size(partition.size()),
+                    partition.spec.format.fs_type,
+                    partition.spec.format.label,
+                    partition.spec.type_guid.as_deref().unwrap_or("-"),
+                    partition
+                        .spec
+                        .format
+                        .encryption
+                        .as_ref()
+                        .map_or("-".to_string(), |luks| format!("LUKS {}", luks.name))
+                );
+            }
+            if !yes
+                && !confirm(&format!(
+                    "All data on {} ({} GB) will be lost. Continue?",
+                    disk.path.display(),
+                    disk.size / 1_000_000_000
+                ))?
+            {
+                return Ok(1);
+            }
+
+            let owner = match owner {
+                Some(user) => Some(Owner::lookup(user)?),
+                None => match std::env::var("SUDO_UID") {
+                    Ok(uid) => Some(Owner::lookup(&uid)?),
+                    Err(_) => None,
+                },
+            };
+
+            let encrypted = layout
+                .partitions
+                .iter()
+                .any(|p| p.format.encryption.is_some());
+            let passphrase = if encrypted {
+                Some(read_new_passphrase()?)
+            } else {
+                None
+            };
+
+            let partitions =
+                format_disk(&disk.path, &layout, passphrase.as_deref(), owner, &|step| {
+                    eprintln!("💾 {}...", step)
+                })?;
+            for (partition, spec) in partitions.iter().zip(&layout.partitions) {
+                println!(
+                    "{} formatted as {} ({})",
+                    partition.display(),
+                    spec.format.fs_type,
+                    spec.format.label
+                );
+            }
+
+            let configured = previous
+                .iter()
+                .any(|p| !p.mount_points.is_empty() || p.encryption.is_some());
+            if encrypted || configured {
+                // Open the new volumes at boot in place of what was declared
+                // for the old partitions; their mount points are added with
+                // add-mount like any other partition
+                let (config, mut disks) = load(&paths)?;
+                for (partition, spec) in partitions.iter().zip(&layout.partitions) {
+                    if let Some(luks) = &spec.format.encryption {
+                        find_partition(&mut disks, partition)?.encryption =
+                            Some(luks.encryption(spec.format.fs_type));
+                    }
+                }
+                save(
+                    &paths,
+                    &config,
+                    &disks,
+                    &previous,
+                    "Replace the formatted partitions",
+                    false,
+                )?;
+            }
+            Ok(0)
+        }
+        Commands::CheckMissing {
+            remove,
+            rebuild,
+            dry_build,
+        } => {
+            let (config, disks) = load(&paths)?;
+            let configured: Vec<Partition> =
+                configured_partitions(&parse_nix_filesystems(&config.content)?)
+                    .into_values()
+                    .collect();
+            let missing = find_missing_partitions(&configured, &disks);
+
+            for partition in &missing {
+                println!(
+                    "{}\t{}",
+                    partition.path.display(),
+                    partition.mount_points.join(", ")
+                );
+            }
+            if missing.is_empty() || !remove {
+                return Ok(if missing.is_empty() { 0 } else { 1 });
+            }
+
+            save(
+                &paths,
+                &config,
+                &disks,
+                &missing,
+                "Remove missing partitions",
+                *dry_build,
+            )?;
+            if *rebuild {
+                rebuild_system(&paths)
+            } else {
+                Ok(0)
+            }
+        }
+        Commands::Rebuild => rebuild_system(&paths),
+    }
+}
+
+/// Configuration files to work on, picking the flake host like the window does
+fn resolve_config_paths(config_dir: &Path, host: Option<&str>) -> Result<ConfigPaths> {
+    let hosts = match flake_hosts(config_dir)? {
+        Some(hosts) if !hosts.is_empty() => hosts,
+        _ => return Ok(ConfigPaths::new(config_dir)),
+    };
+
+    let selected = match host {
+        Some(name) => hosts.iter().find(|h| h.name == name),
+        None => default_host(&hosts),
+    };
+    match selected {
+        Some(host) => ConfigPaths::for_flake_host(config_dir, host),
+        None => {
+            let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+            bail!("Choose a configuration with --host: {}", names.join(", "))
+        }
+    }
+}
+
+fn load(paths: &ConfigPaths) -> Result<(NixConfig, Vec<Disk>)> {
+    let config = load_config(paths)?;
+    let disks = get_disks(&config.sources())?;
+    Ok((config, disks))
+}
+
+/// Write the configuration for `disks`, once it passed the checks; what it
+/// declares for the `forgotten` partitions is deleted
+fn save(
+    paths: &ConfigPaths,
+    config: &NixConfig,
+    disks: &[Disk],
+    forgotten: &[Partition],
+    action: &str,
+    dry_build: bool,
+) -> Result<()> {
+    let new_config = get_nix_disks_config(&config.content, disks, forgotten)?;
+    if new_config == config.content {
+        println!("{} is up to date", config.path.display());
+        return Ok(());
+    }
+    check_syntax(&config.path, &new_config)?;
+    if dry_build {
+        let staged = StagedConfig::new(paths, &config.path, &new_config)?;
+        let status = staged
+            .dry_build_command()
+            .status()
+            .map_err(|e| NixDiskError::tool("nixos-rebuild", e))?;
+        if !status.success() {
+            return Err(NixDiskError::CheckFailed {
+                path: config.path.clone(),
+                reason: "nixos-rebuild dry-build failed".to_string(),
+            }
+            .into());
+        }
+    }
+    let backup = write_with_backup(
+        Path::new(BACKUP_DIR),
+        &config.path,
+        &new_config,
+        action,
+        Some(&content_hash(&config.content)),
+    )?;
+    println!("{} updated", config.path.display());
+    if let Some(backup) = backup {
+        println!("Previous version saved in {}", backup.dir.display());
+    }
+    Ok(())
+}
+
+fn rebuild_system(paths: &ConfigPaths) -> Result<i32> {
+    let status = rebuild_command(paths)
+        .status()
+        .map_err(|e| NixDiskError::tool("nixos-rebuild", e))?;
+    if !status.success() {
+        return Err(NixDiskError::RebuildFailed {
+            code: status.code(),
+        }
+        .into());
+    }
+    Ok(0)
+}
+
+/// Same rule as the window: a bare name is mounted under /media
+fn expand_mount_point(input: &str) -> String {
+    let input = input.trim();
+    if input.starts_with('/') {
+        input.to_string()
+    } else {
+        format!("/media/{}", input)
+    }
+}
+
+fn find_partition<'a>(disks: &'a mut [Disk], device: &Path) -> Result<&'a mut Partition> {
+    // Accept /dev/disk/by-* links as well as kernel names
+    let resolved = fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+    disks
+        .iter_mut()
+        .flat_map(|d| d.partitions.iter_mut())
+        .find(|p| p.path == resolved || p.uuid_path == device)
+        .with_context(|| format!("Partition {} not found", device.display()))
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Ask twice for the passphrase of new LUKS volumes, without echoing it
+/// when standard input is a terminal
+fn read_new_passphrase() -> Result<String> {
+    let terminal = io::stdin().is_terminal();
+    let passphrase = read_passphrase("Passphrase", terminal)?;
+    if passphrase.is_empty() {
+        bail!("The passphrase is empty");
+    }
+    if terminal && read_passphrase("Passphrase again", terminal)? != passphrase {
+        bail!("The passphrases differ");
+    }
+    Ok(passphrase)
+}
+
+fn read_passphrase(prompt: &str, terminal: bool) -> Result<String> {
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+    let hidden = terminal
+        && Command::new("stty")
+            .arg("-echo")
+            .status()
+            .is_ok_and(|status| status.success());
+    let mut line = String::new();
+    let result = io::stdin().lock().read_line(&mut line);
+    if hidden {
+        let _ = Command::new("stty").arg("echo").status();
+        eprintln!();
+    }
+    result?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn print_disks(disks: &[Disk]) {
+    for disk in disks {
+        println!("{} ({} GB)", disk.path.display(), disk.size / 1_000_000_000);
+        if !disk.holders.is_empty() {
+            println!("  → {}", disk.holders.join(", "));
+        }
+        for partition in &disk.partitions {
+            println!(
+                "  {}\t{}\t{}\t{} GB\t{}",
+                partition.path.display(),
+                partition.fs_type.as_deref().unwrap_or("-"),
+                partition.label.as_deref().unwrap_or("-"),
+                partition.size / 1_000_000_000,
+                partition.mount_points.join(", ")
+            );
+            if let Some(stack) = partition.stack_description() {
+                println!("    {}", stack);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Client of nix-disk-helper
+//!
+//! The window runs as the logged-in user; writing the configuration,
+//! rebuilding and formatting go through the helper on the system bus, which
+//! asks polkit for each of them.
+
+use anyhow::{Context, Result};
+use glib::variant::ToVariant;
+use gtk4::{gio, glib};
+use nix_disk_core::NixDiskError;
+use nix_disk_core::helper::{BUS_NAME, INTERFACE, OBJECT_PATH, OUTPUT_SIGNAL, decode_error};
+use nix_disk_core::utils::layout::DiskLayout;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct HelperClient {
+    connection: gio::DBusConnection,
+}
+
+impl HelperClient {
+    pub fn connect() -> Result<Self> {
+        let connection = gio::bus_get_sync(gio::BusType::System, None::<&gio::Cancellable>)
+            .context("Cannot connect to the system bus")?;
+        Ok(Self { connection })
+    }
+
+    /// Write a file of the NixOS configuration
+    ///
+    /// The helper keeps the previous version in the history, described by
+    /// `action`. With `expected_hash`, the write is refused if the file
+    /// changed since it was loaded.
+    pub async fn write_config(
+        &self,
+        path: &Path,
+        content: &str,
+        action: &str,
+        expected_hash: Option<&str>,
+    ) -> Result<()> {
+        let parameters = (
+            path.to_string_lossy().to_string(),
+            content,
+            action,
+            expected_hash.unwrap_or_default(),
+        )
+            .to_variant();
+        self.call("WriteConfig", &parameters, "()").await?;
+        Ok(())
+    }
+
+    /// Run `nixos-rebuild switch`, `--flake flake_ref` when given
+    ///
+    /// Each line nixos-rebuild prints is passed to `on_output`. A nonzero
+    /// exit code is reported as [`NixDiskError::RebuildFailed`].
+    pub async fn rebuild(
+        &self,
+        flake_ref: Option<&str>,
+        on_output: impl Fn(&str) + 'static,
+    ) -> Result<()> {
+        let parameters = (flake_ref.unwrap_or_default(),).to_variant();
+        let reply = self
+            .with_output(
+                "rebuild",
+                on_output,
+                self.call("Rebuild", &parameters, "(i)"),
+            )
+            .await?;
+        let code = reply.child_value(0).get::<i32>().unwrap_or(-1);
+        if code != 0 {
+            return Err(NixDiskError::RebuildFailed { code: Some(code) }.into());
+        }
+        Ok(())
+    }
+
+    /// Stop the running rebuild; its `rebuild` call then fails
+    pub async fn cancel_rebuild(&self) -> Result<()> {
+        self.call("CancelRebuild", &().to_variant(), "()").await?;
+        Ok(())
+    }
+
+    /// Partition `disk` following `layout`, with filesystems owned by the
+    /// current user and encrypted ones opened by `passphrase`
+    ///
+    /// Returns the new partitions; each step is passed to `on_step`.
+    pub async fn format(
+        &self,
+        disk: &Path,
+        layout: &DiskLayout,
+        passphrase: Option<&str>,
+        on_step: impl Fn(&str) + 'static,
+    ) -> Result<Vec<PathBuf>> {
+        let layout = serde_json::to_string(layout)?;
+        let parameters = (
+            disk.to_string_lossy().to_string(),
+            layout,
+            passphrase.unwrap_or_default().to_string(),
+        )
+            .to_variant();
+        let reply = self
+            .with_output("format", on_step, self.call("Format", &parameters, "(as)"))
+            .await?;
+        let partitions = reply
+            .child_value(0)
+            .get::<Vec<String>>()
+            .unwrap_or_default();
+        Ok(partitions.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Forward the `Output` signals of `operation` while `call` runs
+    async fn with_output(
+        &self,
+        operation: &str,
+        on_output: impl Fn(&str) + 'static,
+        call: impl Future<Output = Result<glib::Variant>>,
+    ) -> Result<glib::Variant> {
+        let subscription = self.connection.signal_subscribe(
+            Some(BUS_NAME),
+            Some(INTERFACE),
+            Some(OUTPUT_SIGNAL),
+            Some(OBJECT_PATH),
+            Some(operation),
+            gio::DBusSignalFlags::NONE,
+            move |_, _, _, _, _, parameters| {
+                if let Some((_, line)) = parameters.get::<(String, String)>() {
+                    on_output(&line);
+                }
+            },
+        );
+        let result = call.await;
+        self.connection.signal_unsubscribe(subscription);
+        result
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        parameters: &glib::Variant,
+        reply_type: &str,
+    ) -> Result<glib::Variant> {
+        let reply_type = glib::VariantTy::new(reply_type).expect("valid reply type");
+        self.connection
+            .call_future(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                INTERFACE,
+                method,
+                Some(parameters),
+                Some(reply_type),
+                gio::DBusCallFlags::ALLOW_INTERACTIVE_AUTHORIZATION,
+                // Rebuilds and polkit dialogs take as long as they take
+                i32::MAX,
+            )
+            .await
+            .map_err(|e| decode_error(e.message()))
+    }
+}
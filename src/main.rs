@@ -1,8 +1,9 @@
-mod models;
+mod cli;
+mod helper;
 mod ui;
-mod utils;
 
 use anyhow::Result;
+use clap::Parser;
 use gettextrs::{LocaleCategory, bind_textdomain_codeset, bindtextdomain, setlocale, textdomain};
 use libadwaita as adw;
 use std::env;
@@ -10,6 +11,19 @@ use std::env;
 use ui::app::NixDiskManagerApp;
 
 fn main() -> Result<()> {
+    // With a subcommand, run headless and never touch the display
+    let cli = cli::Cli::parse();
+    if let Some(command) = &cli.command {
+        let exit_code = match cli::run(&cli, command) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                1
+            }
+        };
+        std::process::exit(exit_code);
+    }
+
     // Initialize GTK
     gtk4::init()?;
     adw::init()?;
@@ -1,5 +0,0 @@
-pub mod disk;
-pub mod partition;
-
-pub use disk::Disk;
-pub use partition::Partition;
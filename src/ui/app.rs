@@ -1,25 +1,26 @@
-use crate::models::Disk;
+use crate::ui::dialogs::{ErrorDialog, FlakeHostDialog};
 use crate::ui::window::NixDiskManagerWindow;
-use crate::utils::{find_missing_partitions, get_disks, parse_nix_filesystems};
 use gtk4::glib;
 use gtk4::prelude::*;
 use libadwaita as adw;
+use nix_disk_core::models::{Disk, NixConfig};
+use nix_disk_core::utils::{
+    ConfigPaths, FlakeHost, configured_partitions, default_host, find_missing_partitions,
+    flake_hosts, get_disks, load_config, module_in_use, parse_nix_filesystems,
+};
 use std::cell::RefCell;
-use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use std::rc::Rc;
 
 pub struct NixDiskManagerApp {
     app: adw::Application,
     #[allow(dead_code)]
-    hardware_config_file: PathBuf,
+    config_paths: ConfigPaths,
     #[allow(dead_code)]
-    hardware_config: Rc<RefCell<String>>,
+    nix_config: Rc<RefCell<NixConfig>>,
     #[allow(dead_code)]
     disks: Rc<RefCell<Vec<Disk>>>,
     #[allow(dead_code)]
-    must_save: Rc<RefCell<bool>>,
-    #[allow(dead_code)]
     windows: Rc<RefCell<Vec<adw::ApplicationWindow>>>,
 }
 
@@ -32,10 +33,9 @@ impl NixDiskManagerApp {
         glib::set_application_name("Nix-disk");
         glib::set_prgname(Some("nix-disk"));
 
-        let hardware_config_file = PathBuf::from("/etc/nixos/hardware-configuration.nix");
-        let hardware_config = Rc::new(RefCell::new(String::new()));
+        let config_paths = ConfigPaths::default();
+        let nix_config = Rc::new(RefCell::new(NixConfig::default()));
         let disks = Rc::new(RefCell::new(Vec::new()));
-        let must_save = Rc::new(RefCell::new(false));
         let windows: Rc<RefCell<Vec<adw::ApplicationWindow>>> = Rc::new(RefCell::new(Vec::new()));
 
         // Configure theme to follow system (simple approach)
@@ -44,27 +44,24 @@ impl NixDiskManagerApp {
 
         let app_instance = Self {
             app: app.clone(),
-            hardware_config_file: hardware_config_file.clone(),
-            hardware_config: hardware_config.clone(),
+            config_paths: config_paths.clone(),
+            nix_config: nix_config.clone(),
             disks: disks.clone(),
-            must_save: must_save.clone(),
             windows: windows.clone(),
         };
 
         // Setup activation
-        let hardware_config_clone = hardware_config.clone();
+        let nix_config_clone = nix_config.clone();
         let disks_clone = disks.clone();
-        let config_file_clone = hardware_config_file.clone();
-        let must_save_clone = must_save.clone();
+        let config_paths_clone = config_paths.clone();
         let windows_clone = windows.clone();
 
         app.connect_activate(move |app| {
             Self::on_activate(
                 app,
-                &config_file_clone,
-                &hardware_config_clone,
+                &config_paths_clone,
+                &nix_config_clone,
                 &disks_clone,
-                &must_save_clone,
                 &windows_clone,
             );
         });
@@ -74,34 +71,92 @@ impl NixDiskManagerApp {
 
     fn on_activate(
         app: &adw::Application,
-        config_file: &PathBuf,
-        hardware_config: &Rc<RefCell<String>>,
+        config_paths: &ConfigPaths,
+        nix_config: &Rc<RefCell<NixConfig>>,
         disks: &Rc<RefCell<Vec<Disk>>>,
-        must_save: &Rc<RefCell<bool>>,
         windows: &Rc<RefCell<Vec<adw::ApplicationWindow>>>,
     ) {
-        // Load hardware configuration
-        if let Ok(config) = fs::read_to_string(config_file) {
-            *hardware_config.borrow_mut() = config;
-        } else {
-            eprintln!("Failed to read hardware configuration file");
+        // A flake-based system may hold several machines, find the one to manage
+        let config_dir = config_paths
+            .configuration
+            .parent()
+            .unwrap_or(Path::new("/etc/nixos"))
+            .to_path_buf();
+        let hosts = match flake_hosts(&config_dir) {
+            Ok(Some(hosts)) if !hosts.is_empty() => hosts,
+            Ok(Some(_)) => {
+                eprintln!("⚠️  Aucune nixosConfigurations trouvée dans flake.nix");
+                Vec::new()
+            }
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                eprintln!("❌ Impossible de lire flake.nix: {:#}", e);
+                Self::show_startup_error(app, &e);
+                return;
+            }
+        };
+
+        if hosts.is_empty() {
+            Self::open_window(app, config_paths, nix_config, disks, windows);
             return;
         }
 
+        let open_host = {
+            let app = app.clone();
+            let nix_config = nix_config.clone();
+            let disks = disks.clone();
+            let windows = windows.clone();
+            move |host: FlakeHost| match ConfigPaths::for_flake_host(&config_dir, &host) {
+                Ok(paths) => {
+                    eprintln!("❄️  Flake {}", paths.rebuild_args().join(" "));
+                    Self::open_window(&app, &paths, &nix_config, &disks, &windows);
+                }
+                Err(e) => {
+                    eprintln!("Failed to locate hardware configuration: {:#}", e);
+                    Self::show_startup_error(&app, &e);
+                }
+            }
+        };
+
+        match default_host(&hosts) {
+            Some(host) => open_host(host.clone()),
+            None => FlakeHostDialog::new(app, hosts, open_host).present(),
+        }
+    }
+
+    fn open_window(
+        app: &adw::Application,
+        config_paths: &ConfigPaths,
+        nix_config: &Rc<RefCell<NixConfig>>,
+        disks: &Rc<RefCell<Vec<Disk>>>,
+        windows: &Rc<RefCell<Vec<adw::ApplicationWindow>>>,
+    ) {
+        // Load nix-disk.nix, or hardware-configuration.nix if it is not set up yet
+        match load_config(config_paths) {
+            Ok(config) => *nix_config.borrow_mut() = config,
+            Err(e) => {
+                eprintln!("Failed to read NixOS configuration: {:#}", e);
+                Self::show_startup_error(app, &e);
+                return;
+            }
+        }
+
         // Parse disks
-        let config_ref = hardware_config.borrow();
-        match get_disks(Some(&config_ref)) {
+        let config_ref = nix_config.borrow().clone();
+        match get_disks(&config_ref.sources()) {
             Ok(parsed_disks) => {
-                // Check for missing partitions
-                let configured_partitions = match parse_nix_filesystems(&config_ref) {
-                    Ok(parts) => parts.into_values().collect::<Vec<_>>(),
+                // Check for missing partitions, only the managed file can be cleaned up
+                let configured = match parse_nix_filesystems(&config_ref.content) {
+                    Ok(filesystems) => configured_partitions(&filesystems)
+                        .into_values()
+                        .collect::<Vec<_>>(),
                     Err(e) => {
                         eprintln!("Failed to parse filesystems: {}", e);
                         Vec::new()
                     }
                 };
 
-                let missing = find_missing_partitions(&configured_partitions, &parsed_disks);
+                let missing = find_missing_partitions(&configured, &parsed_disks);
 
                 *disks.borrow_mut() = parsed_disks;
 
@@ -109,9 +164,8 @@ impl NixDiskManagerApp {
                 let window = NixDiskManagerWindow::new(
                     app,
                     disks.clone(),
-                    hardware_config.clone(),
-                    config_file.clone(),
-                    must_save.clone(),
+                    nix_config.clone(),
+                    config_paths.clone(),
                     true, // Skip welcome dialog
                 );
 
@@ -125,14 +179,29 @@ impl NixDiskManagerApp {
                 if !missing.is_empty() {
                     window.show_missing_partitions_dialog(&missing);
                 }
+
+                // Offer to move the mount points to nix-disk.nix
+                if !module_in_use(config_paths) {
+                    window.show_module_setup_dialog();
+                }
             }
             Err(e) => {
-                eprintln!("Failed to get disks: {}", e);
+                eprintln!("Failed to get disks: {:#}", e);
+                Self::show_startup_error(app, &e);
             }
         }
     }
 
+    /// Explain why no window can be opened instead of exiting silently
+    fn show_startup_error(app: &adw::Application, error: &anyhow::Error) {
+        let dialog = ErrorDialog::new(error);
+        dialog.set_application(app);
+        dialog.present(None::<&gtk4::Window>);
+    }
+
     pub fn run(&self) -> i32 {
-        self.app.run().into()
+        // Command-line options were already handled by clap, keep GTK from rejecting them
+        let program = std::env::args().next().unwrap_or_default();
+        self.app.run_with_args(&[program]).into()
     }
 }
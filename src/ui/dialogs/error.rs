@@ -0,0 +1,110 @@
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::NixDiskError;
+
+pub struct ErrorDialog {
+    dialog: adw::MessageDialog,
+}
+
+impl ErrorDialog {
+    pub fn new(error: &anyhow::Error) -> Self {
+        let (title, message) = Self::describe(error);
+        let dialog = adw::MessageDialog::new(None::<&gtk4::Window>, Some(&title), Some(&message));
+        dialog.add_response("ok", "OK");
+        dialog.set_default_response(Some("ok"));
+        dialog.set_close_response("ok");
+        Self { dialog }
+    }
+
+    /// Title and actionable explanation for an error
+    fn describe(error: &anyhow::Error) -> (String, String) {
+        let Some(typed) = NixDiskError::find(error) else {
+            return (gettext("Error"), format!("{:#}", error));
+        };
+
+        match typed {
+            NixDiskError::ConfigNotFound { path } => (
+                gettext("Configuration Not Found"),
+                // TRANSLATORS: %s is a file path (e.g., /etc/nixos/hardware-configuration.nix)
+                gettext("%s does not exist. Generate it with nixos-generate-config, then start Nix-disk again.")
+                    .replace("%s", &path.display().to_string()),
+            ),
+            NixDiskError::Parse { path, source } => (
+                gettext("Invalid NixOS Configuration"),
+                format!(
+                    "{}\n\n{}\n\n{}",
+                    // TRANSLATORS: %s is a file path (e.g., /etc/nixos/configuration.nix)
+                    gettext("%s contains a syntax error:")
+                        .replace("%s", &path.display().to_string()),
+                    source,
+                    gettext("Fix it, then start Nix-disk again."),
+                ),
+            ),
+            NixDiskError::PermissionDenied { path } => (
+                gettext("Permission Denied"),
+                // TRANSLATORS: %s is a file or device path
+                gettext("Nix-disk cannot access %s. Check that your user is allowed to read it.")
+                    .replace("%s", &path.display().to_string()),
+            ),
+            NixDiskError::ToolMissing { tool } if tool == "nix-disk-helper" => (
+                gettext("Helper Not Installed"),
+                gettext("The nix-disk-helper service is not available on the system bus. Add the Nix-disk package to services.dbus.packages and security.polkit, rebuild, then try again."),
+            ),
+            NixDiskError::ToolMissing { tool } => (
+                gettext("Missing Program"),
+                // TRANSLATORS: %s is a program name (e.g., lsblk, parted)
+                gettext("%s was not found. Install util-linux, parted and e2fsprogs, or use the Nix-disk package which provides them.")
+                    .replace("%s", tool),
+            ),
+            NixDiskError::ModifiedExternally { path } => (
+                gettext("Configuration Changed"),
+                // TRANSLATORS: %s is a file path (e.g., /etc/nixos/nix-disk.nix)
+                gettext("%s was modified by another program since Nix-disk loaded it. Nothing was written. Start Nix-disk again to load the current version, then redo your changes.")
+                    .replace("%s", &path.display().to_string()),
+            ),
+            NixDiskError::CheckFailed { path, reason } => (
+                gettext("Configuration Not Saved"),
+                format!(
+                    "{}\n\n{}",
+                    // TRANSLATORS: %s is a file path (e.g., /etc/nixos/nix-disk.nix)
+                    gettext("The new version of %s failed a check before being written, so nothing was changed:")
+                        .replace("%s", &path.display().to_string()),
+                    reason,
+                ),
+            ),
+            NixDiskError::DeviceBusy { reason, .. } => (
+                gettext("Device in Use"),
+                format!(
+                    "{}\n\n{}",
+                    reason,
+                    gettext("Unmount it (or disable the swap) and try again.")
+                ),
+            ),
+            NixDiskError::NotAuthorized { .. } => (
+                gettext("Not Authorized"),
+                gettext("The authentication was cancelled or refused. Nothing was changed."),
+            ),
+            NixDiskError::RebuildFailed { .. } => (
+                gettext("Rebuild Failed"),
+                gettext("The configuration was saved but nixos-rebuild reported an error. Check its output, fix the problem and rebuild again."),
+            ),
+            NixDiskError::Io { .. } => (gettext("Error"), format!("{:#}", error)),
+        }
+    }
+
+    /// Keep the application running while no window is open yet
+    pub fn set_application(&self, app: &adw::Application) {
+        self.dialog.set_application(Some(app));
+    }
+
+    pub fn present(&self, parent: Option<&impl IsA<gtk4::Widget>>) {
+        if let Some(p) = parent
+            && let Some(window) = p.dynamic_cast_ref::<gtk4::Window>()
+        {
+            self.dialog.set_transient_for(Some(window));
+        }
+        self.dialog.present();
+    }
+}
@@ -0,0 +1,53 @@
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::utils::FlakeHost;
+
+pub struct FlakeHostDialog {
+    dialog: adw::MessageDialog,
+}
+
+impl FlakeHostDialog {
+    pub fn new<F>(app: &adw::Application, hosts: Vec<FlakeHost>, on_selected: F) -> Self
+    where
+        F: Fn(FlakeHost) + 'static,
+    {
+        let dialog = adw::MessageDialog::new(
+            None::<&gtk4::Window>,
+            Some(&gettext("Choose a Configuration")),
+            Some(&gettext(
+                "This system is built from a flake with several NixOS configurations. Choose the one describing this machine.",
+            )),
+        );
+        // No window exists yet, keep the application alive while the dialog is open
+        dialog.set_application(Some(app));
+
+        let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+        let dropdown = gtk4::DropDown::from_strings(&names);
+        dropdown.set_margin_top(12);
+        dialog.set_extra_child(Some(&dropdown));
+
+        dialog.add_response("cancel", &gettext("Cancel"));
+        dialog.add_response("select", &gettext("Select"));
+        dialog.set_response_appearance("select", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("select"));
+        dialog.set_close_response("cancel");
+
+        dialog.connect_response(None, move |_, response| {
+            if response != "select" {
+                return;
+            }
+            if let Some(host) = hosts.get(dropdown.selected() as usize) {
+                eprintln!("✓ Configuration du flake choisie: {}", host.name);
+                on_selected(host.clone());
+            }
+        });
+
+        Self { dialog }
+    }
+
+    pub fn present(&self) {
+        self.dialog.present();
+    }
+}
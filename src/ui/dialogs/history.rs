@@ -0,0 +1,147 @@
+use crate::ui::widgets::DiffView;
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use gtk4::{Button, Orientation, glib};
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::utils::{BACKUP_DIR, Backup, list_backups};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Previous versions of the configuration files, with their diff
+pub struct HistoryDialog {
+    window: adw::Window,
+}
+
+impl HistoryDialog {
+    /// `on_restore` receives the chosen version and whether to rebuild
+    pub fn new<F>(on_restore: F) -> Self
+    where
+        F: Fn(Backup, bool) + 'static,
+    {
+        let window = adw::Window::builder()
+            .modal(true)
+            .default_width(700)
+            .default_height(550)
+            .build();
+
+        let toolbar_view = adw::ToolbarView::new();
+        let header = adw::HeaderBar::new();
+        header.set_title_widget(Some(&adw::WindowTitle::new(&gettext("History"), "")));
+        toolbar_view.add_top_bar(&header);
+
+        let backups = match list_backups(Path::new(BACKUP_DIR)) {
+            Ok(backups) => backups,
+            Err(e) => {
+                eprintln!("❌ Erreur lecture de l'historique: {:#}", e);
+                Vec::new()
+            }
+        };
+
+        if backups.is_empty() {
+            let status = adw::StatusPage::builder()
+                .icon_name("document-open-recent-symbolic")
+                .title(gettext("No Previous Versions"))
+                .description(gettext(
+                    "A copy of the configuration is kept here each time Nix-disk changes it.",
+                ))
+                .build();
+            toolbar_view.set_content(Some(&status));
+            window.set_content(Some(&toolbar_view));
+            return Self { window };
+        }
+
+        let list = gtk4::ListBox::new();
+        list.set_selection_mode(gtk4::SelectionMode::None);
+        list.add_css_class("boxed-list");
+        list.set_margin_top(12);
+        list.set_margin_bottom(12);
+        list.set_margin_start(12);
+        list.set_margin_end(12);
+        list.set_valign(gtk4::Align::Start);
+
+        let on_restore = Rc::new(on_restore);
+        for backup in backups {
+            list.append(&Self::backup_row(&backup, &window, on_restore.clone()));
+        }
+
+        let scrolled = gtk4::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk4::PolicyType::Never)
+            .child(&list)
+            .build();
+        toolbar_view.set_content(Some(&scrolled));
+        window.set_content(Some(&toolbar_view));
+
+        Self { window }
+    }
+
+    fn backup_row<F>(backup: &Backup, window: &adw::Window, on_restore: Rc<F>) -> adw::ExpanderRow
+    where
+        F: Fn(Backup, bool) + 'static,
+    {
+        let row = adw::ExpanderRow::builder()
+            .use_markup(false)
+            .title(&backup.action)
+            .subtitle(format!(
+                "{} — {}",
+                format_timestamp(backup.timestamp),
+                backup.path.display()
+            ))
+            .build();
+
+        let content = gtk4::Box::new(Orientation::Vertical, 12);
+        content.set_margin_top(12);
+        content.set_margin_bottom(12);
+        content.set_margin_start(12);
+        content.set_margin_end(12);
+
+        let diff = backup.diff().unwrap_or_else(|e| format!("{:#}", e));
+        let diff_view = DiffView::new();
+        diff_view.set_diff(&diff);
+        diff_view.widget().set_max_content_height(300);
+        diff_view.widget().set_propagate_natural_height(true);
+        content.append(diff_view.widget());
+
+        let buttons = gtk4::Box::new(Orientation::Horizontal, 12);
+        buttons.set_halign(gtk4::Align::End);
+        let restore_button = Button::with_label(&gettext("Restore"));
+        restore_button.add_css_class("pill");
+        let rebuild_button = Button::with_label(&gettext("Restore and Rebuild"));
+        rebuild_button.add_css_class("pill");
+        rebuild_button.add_css_class("suggested-action");
+        buttons.append(&restore_button);
+        buttons.append(&rebuild_button);
+        content.append(&buttons);
+
+        row.add_row(&content);
+
+        for (button, rebuild) in [(restore_button, false), (rebuild_button, true)] {
+            let backup = backup.clone();
+            let window = window.clone();
+            let on_restore = on_restore.clone();
+            button.connect_clicked(move |_| {
+                window.close();
+                on_restore(backup.clone(), rebuild);
+            });
+        }
+
+        row
+    }
+
+    pub fn present(&self, parent: Option<&impl IsA<gtk4::Widget>>) {
+        if let Some(p) = parent
+            && let Some(window) = p.dynamic_cast_ref::<gtk4::Window>()
+        {
+            self.window.set_transient_for(Some(window));
+        }
+        self.window.present();
+    }
+}
+
+/// Local date and time of a backup
+pub fn format_timestamp(timestamp: u64) -> String {
+    glib::DateTime::from_unix_local(timestamp as i64)
+        .and_then(|date| date.format("%x %X"))
+        .map(|date| date.to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
@@ -1,20 +1,17 @@
-use crate::models::{Disk, Partition};
+use crate::ui::dialogs::MountOptionsDialog;
 use gettextrs::gettext;
 use gtk4::prelude::*;
-use gtk4::{Button, Entry, Label, Orientation};
+use gtk4::{Button, Entry, Label, Orientation, gio, glib};
 use libadwaita as adw;
 use libadwaita::prelude::*;
+use nix_disk_core::models::{Disk, MountChange, Partition};
+use nix_disk_core::utils::{InvalidMountPoint, is_critical_mount_point, validate_mount_point};
 use std::cell::RefCell;
 use std::rc::Rc;
 
-/// List of critical mount points that should be filtered out
-const CRITICAL_MOUNT_POINTS: &[&str] = &["/", "/boot", "/boot/efi", "/nix", "/nix/store"];
-
 /// Check if a partition has critical mount points
 fn has_critical_mount_point(mount_points: &[String]) -> bool {
-    mount_points
-        .iter()
-        .any(|mp| CRITICAL_MOUNT_POINTS.contains(&mp.as_str()))
+    mount_points.iter().any(|mp| is_critical_mount_point(mp))
 }
 
 /// Check if a partition should be filtered out (critical mount points or swap)
@@ -34,20 +31,57 @@ fn should_filter_partition(partition: &Partition) -> bool {
     false
 }
 
-#[allow(dead_code)]
+/// Build mount point: if starts with /, use as-is, otherwise prepend /media/
+fn expand_mount_point(input: &str) -> String {
+    if input.starts_with('/') {
+        input.to_string()
+    } else {
+        format!("/media/{}", input)
+    }
+}
+
+fn invalid_mount_point_message(error: &InvalidMountPoint) -> String {
+    match error {
+        InvalidMountPoint::Empty => gettext("Enter a folder name"),
+        InvalidMountPoint::NotAbsolute(_) => gettext("The mount point must start with /"),
+        InvalidMountPoint::ParentDirectory(_) => gettext("The mount point cannot contain \"..\""),
+        InvalidMountPoint::ControlCharacter(_) => {
+            gettext("The mount point cannot contain line breaks or tabs")
+        }
+        InvalidMountPoint::Critical(path) => {
+            // TRANSLATORS: %s is a mount point (e.g., /boot)
+            gettext("%s is used by the system itself").replace("%s", path)
+        }
+        InvalidMountPoint::Reserved { reserved, .. } => {
+            // TRANSLATORS: %s is a system directory (e.g., /proc)
+            gettext("Nothing can be mounted inside %s").replace("%s", reserved)
+        }
+        InvalidMountPoint::AlreadyUsed { path, partition } => {
+            // TRANSLATORS: first %s is a mount point, second %s a partition (e.g., /dev/sdb1)
+            gettext("%s is already used by %s")
+                .replacen("%s", path, 1)
+                .replacen("%s", &partition.display().to_string(), 1)
+        }
+    }
+}
+
+/// Edit the mount points of the partitions of a disk
+///
+/// Edits are staged through `on_change`, the window applies them together.
+#[derive(Clone)]
 pub struct ManageDiskDialog {
     window: adw::Window,
     content: gtk4::Box,
     disks: Rc<RefCell<Vec<Disk>>>,
     disk_path: std::path::PathBuf,
-    on_save_callback: Option<Rc<dyn Fn()>>,
+    on_change: Option<Rc<dyn Fn(MountChange)>>,
 }
 
 impl ManageDiskDialog {
     pub fn new(
         disk: &Disk,
         disks: Rc<RefCell<Vec<Disk>>>,
-        on_save_callback: Option<Rc<dyn Fn()>>,
+        on_change: Option<Rc<dyn Fn(MountChange)>>,
     ) -> Self {
         // Create a proper window
         let window = adw::Window::builder()
@@ -64,8 +98,35 @@ impl ManageDiskDialog {
             "Gérer {}",
             disk.path.display()
         )))));
+
+        // The main window's undo and redo, once present() shares its actions
+        let undo_button = Button::builder()
+            .icon_name("edit-undo-symbolic")
+            .tooltip_text(gettext("Undo"))
+            .action_name("win.undo")
+            .build();
+        let redo_button = Button::builder()
+            .icon_name("edit-redo-symbolic")
+            .tooltip_text(gettext("Redo"))
+            .action_name("win.redo")
+            .build();
+        header.pack_start(&undo_button);
+        header.pack_start(&redo_button);
         toolbar_view.add_top_bar(&header);
 
+        let shortcuts = gtk4::ShortcutController::new();
+        for (trigger, action) in [
+            ("<Control>z", "win.undo"),
+            ("<Control><Shift>z", "win.redo"),
+            ("<Control>y", "win.redo"),
+        ] {
+            shortcuts.add_shortcut(gtk4::Shortcut::new(
+                gtk4::ShortcutTrigger::parse_string(trigger),
+                Some(gtk4::NamedAction::new(action)),
+            ));
+        }
+        window.add_controller(shortcuts);
+
         // Create scrollable content area with partitions
         let scrolled = gtk4::ScrolledWindow::builder()
             .vexpand(true)
@@ -78,6 +139,37 @@ impl ManageDiskDialog {
         content.set_margin_start(12);
         content.set_margin_end(12);
 
+        scrolled.set_child(Some(&content));
+        toolbar_view.set_content(Some(&scrolled));
+        window.set_content(Some(&toolbar_view));
+
+        let dialog = Self {
+            window,
+            content,
+            disks,
+            disk_path: disk.path.clone(),
+            on_change,
+        };
+        dialog.populate();
+        dialog
+    }
+
+    /// Build the content from the disk as it is with the staged changes
+    fn populate(&self) {
+        while let Some(child) = self.content.first_child() {
+            self.content.remove(&child);
+        }
+
+        let Some(disk) = self
+            .disks
+            .borrow()
+            .iter()
+            .find(|d| d.path == self.disk_path)
+            .cloned()
+        else {
+            return;
+        };
+
         // Disk info
         let info_label = Label::new(Some(&format!(
             "Disque: {}\nTaille: {} GB",
@@ -87,7 +179,7 @@ impl ManageDiskDialog {
         info_label.set_halign(gtk4::Align::Start);
         info_label.add_css_class("title-3");
         info_label.set_margin_bottom(12);
-        content.append(&info_label);
+        self.content.append(&info_label);
 
         // Partitions list (filter out critical partitions and swap)
         let non_critical_partitions: Vec<_> = disk
@@ -101,39 +193,31 @@ impl ManageDiskDialog {
                 "Aucune partition gérable\n(seules les partitions système sont présentes)",
             ));
             no_parts.set_justify(gtk4::Justification::Center);
-            content.append(&no_parts);
+            self.content.append(&no_parts);
         } else {
             for partition in non_critical_partitions {
-                let part_box =
-                    Self::create_partition_row(partition, disks.clone(), on_save_callback.clone());
-                content.append(&part_box);
+                let part_box = self.create_partition_row(partition);
+                self.content.append(&part_box);
 
                 // Add separator between partitions
                 let separator = gtk4::Separator::new(gtk4::Orientation::Horizontal);
                 separator.set_margin_top(12);
                 separator.set_margin_bottom(12);
-                content.append(&separator);
+                self.content.append(&separator);
             }
         }
+    }
 
-        scrolled.set_child(Some(&content));
-        toolbar_view.set_content(Some(&scrolled));
-        window.set_content(Some(&toolbar_view));
-
-        Self {
-            window,
-            content: content.clone(),
-            disks: disks.clone(),
-            disk_path: disk.path.clone(),
-            on_save_callback,
+    /// Hand a change to the window, then show its result
+    fn stage(&self, change: MountChange) {
+        eprintln!("📍 Modification en attente: {:?}", change);
+        if let Some(ref on_change) = self.on_change {
+            on_change(change);
         }
+        self.populate();
     }
 
-    fn create_partition_row(
-        partition: &Partition,
-        disks: Rc<RefCell<Vec<Disk>>>,
-        on_save_callback: Option<Rc<dyn Fn()>>,
-    ) -> gtk4::Box {
+    fn create_partition_row(&self, partition: &Partition) -> gtk4::Box {
         let row = gtk4::Box::new(Orientation::Vertical, 12);
 
         // Partition info in a card-like box
@@ -162,6 +246,19 @@ impl ManageDiskDialog {
         info.set_margin_bottom(12);
         info_box.append(&info);
 
+        // Devices the filesystem is built on: LUKS, LVM, RAID
+        if let Some(stack) = partition.stack_description() {
+            info.set_margin_bottom(4);
+            let stack_label = Label::new(Some(&stack));
+            stack_label.add_css_class("caption");
+            stack_label.add_css_class("dim-label");
+            stack_label.set_halign(gtk4::Align::Start);
+            stack_label.set_margin_start(12);
+            stack_label.set_margin_end(12);
+            stack_label.set_margin_bottom(8);
+            info_box.append(&stack_label);
+        }
+
         // Add "already mounted" warning if partition has mount points
         if !partition.mount_points.is_empty() {
             let warning_box = gtk4::Box::new(Orientation::Horizontal, 6);
@@ -188,9 +285,6 @@ impl ManageDiskDialog {
         mount_label.set_margin_bottom(6);
         row.append(&mount_label);
 
-        let partition_clone = partition.clone();
-        let disks_for_mount = disks.clone();
-
         // Show existing mount points
         for mount_point in &partition.mount_points {
             let mp_box = gtk4::Box::new(Orientation::Horizontal, 12);
@@ -201,6 +295,26 @@ impl ManageDiskDialog {
             mp_label.set_hexpand(true);
             mp_box.append(&mp_label);
 
+            let options_btn = Button::builder()
+                .icon_name("document-edit-symbolic")
+                .tooltip_text(gettext("Mount Options"))
+                .build();
+            options_btn.add_css_class("flat");
+
+            let dialog = self.clone();
+            let partition_for_options = partition.clone();
+            let mount_point_for_options = mount_point.clone();
+            options_btn.connect_clicked(move |_| {
+                let stage_dialog = dialog.clone();
+                let options_dialog = MountOptionsDialog::new(
+                    &partition_for_options,
+                    &mount_point_for_options,
+                    move |change| stage_dialog.stage(change),
+                );
+                options_dialog.present(Some(&dialog.window));
+            });
+            mp_box.append(&options_btn);
+
             let remove_btn = Button::builder()
                 .icon_name("user-trash-symbolic")
                 .tooltip_text("Supprimer ce point de montage")
@@ -208,92 +322,12 @@ impl ManageDiskDialog {
             remove_btn.add_css_class("flat");
             remove_btn.add_css_class("destructive-action");
 
-            let mount_point_clone = mount_point.clone();
-            let partition_path = partition_clone.path.clone();
-            let disks_for_remove = disks_for_mount.clone();
-            let on_save_callback_for_remove = on_save_callback.clone();
-
-            remove_btn.connect_clicked(move |btn| {
-                // Create confirmation dialog
-                let dialog = adw::MessageDialog::new(
-                    btn.root()
-                        .and_then(|r| r.downcast::<gtk4::Window>().ok())
-                        .as_ref(),
-                    Some(&gettext("Confirm mount point removal")),
-                    Some(&format!(
-                        "{}\n\n{}",
-                        gettext("Do you really want to remove the mount point '%s'?")
-                            .replace("%s", &mount_point_clone),
-                        gettext(
-                            "This action will save the configuration and rebuild the NixOS system."
-                        )
-                    )),
-                );
-
-                dialog.add_response("cancel", &gettext("Cancel"));
-                dialog.add_response("confirm", &gettext("Confirm"));
-                dialog.set_response_appearance("confirm", adw::ResponseAppearance::Destructive);
-                dialog.set_default_response(Some("confirm"));
-                dialog.set_close_response("cancel");
-
-                let disks_for_confirm = disks_for_remove.clone();
-                let partition_path_for_confirm = partition_path.clone();
-                let mount_point_for_confirm = mount_point_clone.clone();
-                let on_save_callback_for_confirm = on_save_callback_for_remove.clone();
-                let btn_for_confirm = btn.clone();
-
-                dialog.connect_response(None, move |_, response| {
-                    if response == "confirm" {
-                        eprintln!("✓ Confirmation reçue, suppression du point de montage");
-
-                        let mut removed = false;
-                        {
-                            let mut disks_mut = disks_for_confirm.borrow_mut();
-                            for disk in disks_mut.iter_mut() {
-                                for part in disk.partitions.iter_mut() {
-                                    if part.path == partition_path_for_confirm {
-                                        part.mount_points
-                                            .retain(|mp| mp != &mount_point_for_confirm);
-                                        removed = true;
-                                        eprintln!(
-                                            "✓ Point de montage supprimé: {}",
-                                            mount_point_for_confirm
-                                        );
-                                        eprintln!(
-                                            "✓ Points de montage restants: {:?}",
-                                            part.mount_points
-                                        );
-                                        break;
-                                    }
-                                }
-                                if removed {
-                                    break;
-                                }
-                            }
-                        }
-
-                        // If successfully removed, trigger save callback
-                        if removed {
-                            eprintln!("📍 Appel de la callback de sauvegarde...");
-                            if let Some(ref callback) = on_save_callback_for_confirm {
-                                callback();
-                            }
-
-                            // Close the manage dialog
-                            if let Some(window) = btn_for_confirm
-                                .root()
-                                .and_then(|r| r.downcast::<gtk4::Window>().ok())
-                            {
-                                window.close();
-                            }
-                        }
-                    } else {
-                        eprintln!("✗ Suppression du point de montage annulée par l'utilisateur");
-                    }
-                });
-
-                dialog.present();
-            });
+            let dialog = self.clone();
+            let change = MountChange::Remove {
+                partition: partition.path.clone(),
+                mount_point: mount_point.clone(),
+            };
+            remove_btn.connect_clicked(move |_| dialog.stage(change.clone()));
 
             mp_box.append(&remove_btn);
             row.append(&mp_box);
@@ -326,145 +360,79 @@ impl ManageDiskDialog {
         add_btn.add_css_class("circular");
         add_btn.add_css_class("suggested-action");
 
+        // Shown under the entry while the mount point cannot be added
+        let error_label = Label::new(None);
+        error_label.set_halign(gtk4::Align::Start);
+        error_label.set_wrap(true);
+        error_label.set_margin_start(12);
+        error_label.add_css_class("caption");
+        error_label.add_css_class("error");
+        error_label.set_visible(false);
+        add_btn.set_sensitive(false);
+
+        let disks_for_check = self.disks.clone();
+        let error_label_for_check = error_label.clone();
+        let add_btn_for_check = add_btn.clone();
+        entry.connect_changed(move |entry| {
+            let input = entry.text().trim().to_string();
+            let result = (!input.is_empty()).then(|| {
+                validate_mount_point(&expand_mount_point(&input), &disks_for_check.borrow())
+            });
+            match &result {
+                Some(Err(e)) => {
+                    entry.add_css_class("error");
+                    error_label_for_check.set_text(&invalid_mount_point_message(e));
+                    error_label_for_check.set_visible(true);
+                }
+                _ => {
+                    entry.remove_css_class("error");
+                    error_label_for_check.set_visible(false);
+                }
+            }
+            add_btn_for_check.set_sensitive(matches!(result, Some(Ok(_))));
+        });
+
         let partition_path_for_add = partition.path.clone();
-        let disks_for_add = disks.clone();
         let entry_clone = entry.clone();
-        let _row_clone = row.clone();
-
-        let on_save_callback_clone = on_save_callback.clone();
-        add_btn.connect_clicked(move |btn| {
-            let input = entry_clone.text().to_string().trim().to_string();
+        let dialog = self.clone();
+        add_btn.connect_clicked(move |_| {
+            let input = entry_clone.text().trim().to_string();
             if input.is_empty() {
                 return;
             }
 
-            // Build mount point: if starts with /, use as-is, otherwise prepend /media/
-            let mount_point = if input.starts_with('/') {
-                input
-            } else {
-                format!("/media/{}", input)
-            };
-
+            let mount_point = expand_mount_point(&input);
             eprintln!("📍 Tentative d'ajout du point de montage: {}", mount_point);
             eprintln!("📍 Pour la partition: {}", partition_path_for_add.display());
 
-            // Create confirmation dialog using MessageDialog (libadwaita 0.7 compatible)
-            let dialog = adw::MessageDialog::new(
-                btn.root()
-                    .and_then(|r| r.downcast::<gtk4::Window>().ok())
-                    .as_ref(),
-                Some(&gettext("Confirm mount point addition")),
-                Some(&format!(
-                    "{}\n\n{}",
-                    // TRANSLATORS: %s is the mount point path (e.g., /media/data)
-                    gettext("Do you really want to add the mount point '%s'?")
-                        .replace("%s", &mount_point),
-                    gettext(
-                        "This action will save the configuration and rebuild the NixOS system."
-                    )
-                )),
-            );
-
-            dialog.add_response("cancel", &gettext("Cancel"));
-            dialog.add_response("confirm", &gettext("Confirm"));
-            dialog.set_response_appearance("confirm", adw::ResponseAppearance::Suggested);
-            dialog.set_default_response(Some("confirm"));
-            dialog.set_close_response("cancel");
-
-            let disks_for_confirm = disks_for_add.clone();
-            let partition_path_for_confirm = partition_path_for_add.clone();
-            let mount_point_for_confirm = mount_point.clone();
-            let entry_for_confirm = entry_clone.clone();
-            let btn_for_confirm = btn.clone();
-            let on_save_callback_for_confirm = on_save_callback_clone.clone();
-
-            dialog.connect_response(None, move |_, response| {
-                if response == "confirm" {
-                    eprintln!("✓ Confirmation reçue, ajout du point de montage");
-
-                    let mut added = false;
-                    {
-                        let mut disks_mut = disks_for_confirm.borrow_mut();
-                        eprintln!("📍 Nombre de disques dans RefCell: {}", disks_mut.len());
-
-                        for (disk_idx, disk) in disks_mut.iter_mut().enumerate() {
-                            eprintln!(
-                                "📍 Vérification disque {}: {}",
-                                disk_idx,
-                                disk.path.display()
-                            );
-                            for (part_idx, part) in disk.partitions.iter_mut().enumerate() {
-                                eprintln!(
-                                    "📍   Vérification partition {}: {}",
-                                    part_idx,
-                                    part.path.display()
-                                );
-                                if part.path == partition_path_for_confirm {
-                                    eprintln!("📍   ✓ Partition trouvée!");
-                                    if !part.mount_points.contains(&mount_point_for_confirm) {
-                                        part.mount_points.push(mount_point_for_confirm.clone());
-                                        entry_for_confirm.set_text("");
-                                        added = true;
-                                        eprintln!(
-                                            "✓ Point de montage ajouté: {}",
-                                            mount_point_for_confirm
-                                        );
-                                        eprintln!(
-                                            "✓ Points de montage actuels: {:?}",
-                                            part.mount_points
-                                        );
-                                    } else {
-                                        eprintln!("⚠ Point de montage déjà existant");
-                                    }
-                                    break;
-                                }
-                            }
-                            if added {
-                                break;
-                            }
-                        }
-
-                        if !added {
-                            eprintln!(
-                                "❌ ERREUR: Partition non trouvée dans la liste des disques!"
-                            );
-                        }
-                    }
-
-                    // If successfully added, trigger save callback
-                    if added {
-                        eprintln!("📍 Appel de la callback de sauvegarde...");
-                        if let Some(ref callback) = on_save_callback_for_confirm {
-                            callback();
-                        }
-
-                        // Close the manage dialog
-                        if let Some(window) = btn_for_confirm
-                            .root()
-                            .and_then(|r| r.downcast::<gtk4::Window>().ok())
-                        {
-                            window.close();
-                        }
-                    }
-                } else {
-                    eprintln!("✗ Ajout du point de montage annulé par l'utilisateur");
+            // The disks may have changed since the entry was checked
+            let mount_point = match validate_mount_point(&mount_point, &dialog.disks.borrow()) {
+                Ok(mount_point) => mount_point,
+                Err(e) => {
+                    eprintln!("⚠️  Point de montage refusé: {}", e);
+                    return;
                 }
-            });
+            };
 
-            // Present dialog (MessageDialog presents automatically when created with parent)
-            dialog.present();
+            dialog.stage(MountChange::Add {
+                partition: partition_path_for_add.clone(),
+                mount_point,
+            });
         });
 
         // Allow Enter key to add mount point
         let add_btn_clone = add_btn.clone();
         entry.connect_activate(move |_| {
-            add_btn_clone.emit_clicked();
+            if add_btn_clone.is_sensitive() {
+                add_btn_clone.emit_clicked();
+            }
         });
 
         add_box.append(&prefix_label);
         add_box.append(&entry);
         add_box.append(&add_btn);
         row.append(&add_box);
+        row.append(&error_label);
 
         row
     }
@@ -475,6 +443,39 @@ impl ManageDiskDialog {
         {
             self.window.set_transient_for(Some(window));
         }
+        if let Some(main_window) =
+            parent.and_then(|p| p.dynamic_cast_ref::<adw::ApplicationWindow>())
+        {
+            self.share_history(main_window);
+        }
         self.window.present();
     }
+
+    /// Use the undo and redo actions of the main window, showing their
+    /// result here too
+    fn share_history(&self, main_window: &adw::ApplicationWindow) {
+        self.window
+            .insert_action_group("win", Some(main_window.upcast_ref::<gio::ActionGroup>()));
+
+        let mut handlers = Vec::new();
+        for name in ["undo", "redo"] {
+            let Some(action) = main_window
+                .lookup_action(name)
+                .and_downcast::<gio::SimpleAction>()
+            else {
+                continue;
+            };
+            let dialog = self.clone();
+            let handler = action.connect_activate(move |_, _| dialog.populate());
+            handlers.push((action, handler));
+        }
+
+        let handlers = RefCell::new(handlers);
+        self.window.connect_close_request(move |_| {
+            for (action, handler) in handlers.take() {
+                action.disconnect(handler);
+            }
+            glib::Propagation::Proceed
+        });
+    }
 }
@@ -1,16 +1,18 @@
-use crate::models::{Disk, Partition};
 use gtk4::prelude::*;
 use libadwaita as adw;
 use libadwaita::prelude::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use nix_disk_core::models::Partition;
 
 pub struct MissingPartitionsDialog {
     dialog: adw::MessageDialog,
 }
 
 impl MissingPartitionsDialog {
-    pub fn new(missing: &[Partition], disks: Rc<RefCell<Vec<Disk>>>) -> Self {
+    /// `on_remove` gets the partitions whose entries the user chose to remove
+    pub fn new<F>(missing: &[Partition], on_remove: F) -> Self
+    where
+        F: Fn(Vec<Partition>) + 'static,
+    {
         let mut message =
             String::from("Les partitions suivantes sont configurées mais n'existent plus :\n\n");
 
@@ -40,22 +42,13 @@ impl MissingPartitionsDialog {
         let missing_vec = missing.to_vec();
         dialog.connect_response(None, move |_, response| {
             if response == "remove" {
-                Self::remove_missing_partitions(&missing_vec, &disks);
+                on_remove(missing_vec.clone());
             }
         });
 
         Self { dialog }
     }
 
-    fn remove_missing_partitions(missing: &[Partition], disks: &Rc<RefCell<Vec<Disk>>>) {
-        let mut disks_mut = disks.borrow_mut();
-
-        for disk in disks_mut.iter_mut() {
-            disk.partitions
-                .retain(|p| !missing.iter().any(|m| m.path == p.path));
-        }
-    }
-
     pub fn present(&self, parent: Option<&impl IsA<gtk4::Widget>>) {
         if let Some(p) = parent
             && let Some(window) = p.dynamic_cast_ref::<gtk4::Window>()
@@ -1,9 +1,21 @@
+pub mod error;
+pub mod flake_host;
 pub mod format_disk;
+pub mod history;
 pub mod manage_disk;
 pub mod missing_partitions;
+pub mod module_setup;
+pub mod mount_options;
+pub mod review_changes;
 pub mod welcome;
 
+pub use error::ErrorDialog;
+pub use flake_host::FlakeHostDialog;
 pub use format_disk::FormatDiskDialog;
+pub use history::HistoryDialog;
 pub use manage_disk::ManageDiskDialog;
 pub use missing_partitions::MissingPartitionsDialog;
+pub use module_setup::ModuleSetupDialog;
+pub use mount_options::MountOptionsDialog;
+pub use review_changes::ReviewChangesDialog;
 pub use welcome::WelcomeDialog;
@@ -0,0 +1,91 @@
+use crate::helper::HelperClient;
+use crate::ui::dialogs::ErrorDialog;
+use gettextrs::gettext;
+use gtk4::glib;
+use gtk4::prelude::*;
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::utils::{ConfigPaths, plan_module_setup};
+use std::rc::Rc;
+
+pub struct ModuleSetupDialog {
+    dialog: adw::MessageDialog,
+}
+
+impl ModuleSetupDialog {
+    pub fn new<F>(config_paths: ConfigPaths, on_enabled: F) -> Self
+    where
+        F: Fn(Vec<String>) + 'static,
+    {
+        let message = format!(
+            "{}\n\n{}",
+            // TRANSLATORS: %s is the path of the nix-disk module (e.g., /etc/nixos/nix-disk.nix)
+            gettext("Nix-disk keeps its mount points in %s so that they are not lost when hardware-configuration.nix is regenerated.")
+                .replace("%s", &config_paths.module.display().to_string()),
+            gettext("The file will be imported from configuration.nix and the mount points currently declared in hardware-configuration.nix will be moved into it. System partitions are left untouched."),
+        );
+
+        let dialog = adw::MessageDialog::new(
+            None::<&gtk4::Window>,
+            Some(&gettext("Set up nix-disk.nix")),
+            Some(&message),
+        );
+
+        dialog.add_response("later", &gettext("Not Now"));
+        dialog.add_response("enable", &gettext("Set Up"));
+        dialog.set_response_appearance("enable", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("enable"));
+        dialog.set_close_response("later");
+
+        let on_enabled = Rc::new(on_enabled);
+        dialog.connect_response(None, move |dialog, response| {
+            if response != "enable" {
+                return;
+            }
+
+            let config_paths = config_paths.clone();
+            let on_enabled = on_enabled.clone();
+            let parent = dialog.transient_for();
+            glib::spawn_future_local(async move {
+                match Self::enable(&config_paths).await {
+                    Ok(moved) => {
+                        eprintln!(
+                            "✓ nix-disk.nix configuré, points de montage déplacés: {:?}",
+                            moved
+                        );
+                        on_enabled(moved);
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "❌ Erreur lors de la configuration de nix-disk.nix: {:#}",
+                            e
+                        );
+                        ErrorDialog::new(&e).present(parent.as_ref());
+                    }
+                }
+            });
+        });
+
+        Self { dialog }
+    }
+
+    /// Write the files of the setup through the helper, module first
+    async fn enable(config_paths: &ConfigPaths) -> anyhow::Result<Vec<String>> {
+        let setup = plan_module_setup(config_paths)?;
+        let helper = HelperClient::connect()?;
+        let action = gettext("Set up nix-disk.nix");
+        for (path, content) in &setup.writes {
+            helper.write_config(path, content, &action, None).await?;
+        }
+        Ok(setup.moved)
+    }
+
+    pub fn present(&self, parent: Option<&impl IsA<gtk4::Widget>>) {
+        if let Some(p) = parent
+            && let Some(window) = p.dynamic_cast_ref::<gtk4::Window>()
+        {
+            self.dialog.set_transient_for(Some(window));
+        }
+        self.dialog.present();
+    }
+}
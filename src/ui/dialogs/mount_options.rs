@@ -0,0 +1,116 @@
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::models::{MountChange, Partition};
+use nix_disk_core::utils::get_filesystem_options;
+
+/// Edit the `fsType` and `options` of one mount point
+pub struct MountOptionsDialog {
+    dialog: adw::MessageDialog,
+}
+
+impl MountOptionsDialog {
+    /// `on_change` receives each setting that was changed, to be staged
+    pub fn new<F>(partition: &Partition, mount_point: &str, on_change: F) -> Self
+    where
+        F: Fn(MountChange) + 'static,
+    {
+        // TRANSLATORS: %s is a mount point (e.g., /media/data)
+        let body = gettext("How %s is mounted, separate the options with commas.")
+            .replace("%s", mount_point);
+        let dialog = adw::MessageDialog::new(
+            None::<&gtk4::Window>,
+            Some(&gettext("Mount Options")),
+            Some(&body),
+        );
+
+        let settings = partition.settings(mount_point);
+        let fs_type = partition
+            .mount_fs_type(mount_point)
+            .unwrap_or("auto")
+            .to_string();
+        let options = settings
+            .options
+            .clone()
+            .or_else(|| partition.default_options.clone())
+            .unwrap_or_else(|| get_filesystem_options(&fs_type, mount_point, None, None));
+
+        let group = adw::PreferencesGroup::new();
+        group.set_margin_top(12);
+        let fs_type_row = adw::EntryRow::builder()
+            .title(gettext("Filesystem Type"))
+            .text(fs_type.as_str())
+            .build();
+        let options_row = adw::EntryRow::builder()
+            .title(gettext("Options"))
+            .text(options.join(", "))
+            .build();
+        group.add(&fs_type_row);
+        group.add(&options_row);
+        dialog.set_extra_child(Some(&group));
+
+        dialog.add_response("cancel", &gettext("Cancel"));
+        dialog.add_response("apply", &gettext("Apply"));
+        dialog.set_response_appearance("apply", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("apply"));
+        dialog.set_close_response("cancel");
+
+        // fsType ends up in a Nix string, keep it to what mount(8) accepts
+        let dialog_for_check = dialog.clone();
+        fs_type_row.connect_changed(move |row| {
+            let text = row.text();
+            let valid = !text.is_empty()
+                && text
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+            if valid {
+                row.remove_css_class("error");
+            } else {
+                row.add_css_class("error");
+            }
+            dialog_for_check.set_response_enabled("apply", valid);
+        });
+
+        let partition_path = partition.path.clone();
+        let mount_point = mount_point.to_string();
+        dialog.connect_response(None, move |_, response| {
+            if response != "apply" {
+                return;
+            }
+
+            let new_fs_type = fs_type_row.text().trim().to_string();
+            if new_fs_type != fs_type {
+                on_change(MountChange::SetFsType {
+                    partition: partition_path.clone(),
+                    mount_point: mount_point.clone(),
+                    fs_type: Some(new_fs_type),
+                    previous: settings.fs_type.clone(),
+                });
+            }
+
+            let new_options: Vec<String> = options_row
+                .text()
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(str::to_string)
+                .collect();
+            if new_options != options {
+                on_change(MountChange::SetOptions {
+                    partition: partition_path.clone(),
+                    mount_point: mount_point.clone(),
+                    options: Some(new_options),
+                    previous: settings.options.clone(),
+                });
+            }
+        });
+
+        Self { dialog }
+    }
+
+    pub fn present(&self, parent: Option<&impl IsA<gtk4::Window>>) {
+        self.dialog.set_transient_for(parent);
+        self.dialog.present();
+    }
+}
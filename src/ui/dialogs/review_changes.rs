@@ -0,0 +1,121 @@
+use crate::ui::widgets::DiffView;
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use gtk4::{Button, Label, Orientation};
+use libadwaita as adw;
+use libadwaita::prelude::*;
+use nix_disk_core::models::{Disk, NixConfig, Partition};
+use nix_disk_core::utils::{get_nix_disks_config, unified_diff};
+
+/// Lines of context around each change of the preview
+const DIFF_CONTEXT: usize = 3;
+
+/// The exact change to the configuration file, shown before it is written
+pub struct ReviewChangesDialog {
+    window: adw::Window,
+}
+
+impl ReviewChangesDialog {
+    /// Preview the configuration generated for `disks` without the
+    /// `forgotten` partitions; `on_apply` runs when the user accepts it
+    pub fn new<F>(
+        title: &str,
+        summary: &str,
+        nix_config: &NixConfig,
+        disks: &[Disk],
+        forgotten: &[Partition],
+        on_apply: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: Fn() + 'static,
+    {
+        let new_config = get_nix_disks_config(&nix_config.content, disks, forgotten)?;
+        let name = nix_config.path.display().to_string();
+        let diff = unified_diff(
+            &nix_config.content,
+            &new_config,
+            &format!("a{}", name),
+            &format!("b{}", name),
+            DIFF_CONTEXT,
+        );
+
+        let window = adw::Window::builder()
+            .modal(true)
+            .default_width(700)
+            .default_height(550)
+            .build();
+
+        let toolbar_view = adw::ToolbarView::new();
+        let header = adw::HeaderBar::new();
+        header.set_show_start_title_buttons(false);
+        header.set_show_end_title_buttons(false);
+        header.set_title_widget(Some(&adw::WindowTitle::new(title, "")));
+
+        let cancel_button = Button::with_label(&gettext("Cancel"));
+        header.pack_start(&cancel_button);
+        let apply_button = Button::with_label(&gettext("Save and Rebuild"));
+        apply_button.add_css_class("suggested-action");
+        header.pack_end(&apply_button);
+        toolbar_view.add_top_bar(&header);
+
+        let content = gtk4::Box::new(Orientation::Vertical, 12);
+        content.set_margin_top(12);
+        content.set_margin_bottom(12);
+        content.set_margin_start(12);
+        content.set_margin_end(12);
+
+        let summary_label = Label::new(Some(summary));
+        summary_label.set_wrap(true);
+        summary_label.set_xalign(0.0);
+        content.append(&summary_label);
+
+        let details = Label::new(None);
+        details.set_wrap(true);
+        details.set_xalign(0.0);
+        details.add_css_class("dim-label");
+        content.append(&details);
+
+        if diff.is_empty() {
+            details.set_text(&gettext(
+                "The configuration would not change, there is nothing to save.",
+            ));
+            apply_button.set_sensitive(false);
+        } else {
+            // TRANSLATORS: %s is a file path (e.g., /etc/nixos/nix-disk.nix)
+            details.set_text(
+                &gettext("These lines of %s will change, then the NixOS system will be rebuilt.")
+                    .replace("%s", &name),
+            );
+            let diff_view = DiffView::new();
+            diff_view.set_diff(&diff);
+            diff_view.widget().set_vexpand(true);
+            content.append(diff_view.widget());
+        }
+
+        toolbar_view.set_content(Some(&content));
+        window.set_content(Some(&toolbar_view));
+
+        let window_for_cancel = window.clone();
+        cancel_button.connect_clicked(move |_| {
+            eprintln!("✗ Modification annulée par l'utilisateur");
+            window_for_cancel.close();
+        });
+
+        let window_for_apply = window.clone();
+        apply_button.connect_clicked(move |_| {
+            window_for_apply.close();
+            on_apply();
+        });
+
+        Ok(Self { window })
+    }
+
+    pub fn present(&self, parent: Option<&impl IsA<gtk4::Widget>>) {
+        if let Some(p) = parent
+            && let Some(window) = p.dynamic_cast_ref::<gtk4::Window>()
+        {
+            self.window.set_transient_for(Some(window));
+        }
+        self.window.present();
+    }
+}
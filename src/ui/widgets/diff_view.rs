@@ -0,0 +1,86 @@
+use gtk4::prelude::*;
+use gtk4::{TextTag, TextView, gdk};
+
+/// Read-only view of a unified diff, with added and removed lines colored
+#[derive(Clone)]
+pub struct DiffView {
+    scrolled: gtk4::ScrolledWindow,
+    text_view: TextView,
+}
+
+impl DiffView {
+    pub fn new() -> Self {
+        let text_view = TextView::builder()
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .top_margin(6)
+            .bottom_margin(6)
+            .left_margin(6)
+            .right_margin(6)
+            .build();
+
+        let buffer = text_view.buffer();
+        let tags = buffer.tag_table();
+        // Translucent backgrounds stay readable with the light and dark styles
+        tags.add(
+            &TextTag::builder()
+                .name("added")
+                .paragraph_background_rgba(&gdk::RGBA::new(0.18, 0.76, 0.49, 0.2))
+                .build(),
+        );
+        tags.add(
+            &TextTag::builder()
+                .name("removed")
+                .paragraph_background_rgba(&gdk::RGBA::new(0.88, 0.11, 0.14, 0.2))
+                .build(),
+        );
+        tags.add(
+            &TextTag::builder()
+                .name("hunk")
+                .foreground_rgba(&gdk::RGBA::new(0.21, 0.52, 0.89, 1.0))
+                .build(),
+        );
+        tags.add(&TextTag::builder().name("file").weight(700).build());
+
+        let scrolled = gtk4::ScrolledWindow::builder()
+            .min_content_height(150)
+            .child(&text_view)
+            .build();
+        scrolled.add_css_class("card");
+
+        Self {
+            scrolled,
+            text_view,
+        }
+    }
+
+    pub fn widget(&self) -> &gtk4::ScrolledWindow {
+        &self.scrolled
+    }
+
+    /// Show `diff`, as produced by `unified_diff`
+    pub fn set_diff(&self, diff: &str) {
+        let buffer = self.text_view.buffer();
+        buffer.set_text("");
+        let mut end = buffer.end_iter();
+        for line in diff.lines() {
+            let tag = if line.starts_with("+++") || line.starts_with("---") {
+                Some("file")
+            } else if line.starts_with("@@") {
+                Some("hunk")
+            } else if line.starts_with('+') {
+                Some("added")
+            } else if line.starts_with('-') {
+                Some("removed")
+            } else {
+                None
+            };
+            let text = format!("{}\n", line);
+            match tag {
+                Some(tag) => buffer.insert_with_tags_by_name(&mut end, &text, &[tag]),
+                None => buffer.insert(&mut end, &text),
+            }
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use crate::models::Disk;
 use crate::ui::dialogs::ManageDiskDialog;
 use gtk4::prelude::*;
 use gtk4::{Button, Image, Label, Orientation};
 use libadwaita as adw;
+use nix_disk_core::models::{Disk, MountChange, NixConfig};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -17,7 +17,7 @@ fn has_critical_mount_point(mount_points: &[String]) -> bool {
 }
 
 /// Check if a partition should be filtered out (critical mount points or swap)
-fn should_filter_partition(partition: &crate::models::Partition) -> bool {
+fn should_filter_partition(partition: &nix_disk_core::models::Partition) -> bool {
     // Filter out partitions with critical mount points
     if has_critical_mount_point(&partition.mount_points) {
         return true;
@@ -36,9 +36,11 @@ fn should_filter_partition(partition: &crate::models::Partition) -> bool {
 pub struct DisksWidget {
     container: gtk4::Box,
     disks: Rc<RefCell<Vec<Disk>>>,
-    hardware_config: Option<Rc<RefCell<String>>>,
+    nix_config: Option<Rc<RefCell<NixConfig>>>,
     #[allow(clippy::type_complexity)]
-    on_save_callback: Rc<RefCell<Option<Rc<dyn Fn()>>>>,
+    on_change_callback: Rc<RefCell<Option<Rc<dyn Fn(MountChange)>>>>,
+    #[allow(clippy::type_complexity)]
+    on_reload_callback: Rc<RefCell<Option<Rc<dyn Fn()>>>>,
 }
 
 impl Clone for DisksWidget {
@@ -46,8 +48,9 @@ impl Clone for DisksWidget {
         Self {
             container: self.container.clone(),
             disks: self.disks.clone(),
-            hardware_config: self.hardware_config.clone(),
-            on_save_callback: self.on_save_callback.clone(),
+            nix_config: self.nix_config.clone(),
+            on_change_callback: self.on_change_callback.clone(),
+            on_reload_callback: self.on_reload_callback.clone(),
         }
     }
 }
@@ -60,7 +63,7 @@ impl DisksWidget {
 
     pub fn new_with_config(
         disks: Rc<RefCell<Vec<Disk>>>,
-        hardware_config: Option<Rc<RefCell<String>>>,
+        nix_config: Option<Rc<RefCell<NixConfig>>>,
     ) -> Self {
         let container = gtk4::Box::new(Orientation::Vertical, 20);
         container.set_vexpand(true);
@@ -70,19 +73,29 @@ impl DisksWidget {
         let widget = Self {
             container: container.clone(),
             disks: disks.clone(),
-            hardware_config,
-            on_save_callback: Rc::new(RefCell::new(None)),
+            nix_config,
+            on_change_callback: Rc::new(RefCell::new(None)),
+            on_reload_callback: Rc::new(RefCell::new(None)),
         };
 
         widget.populate();
         widget
     }
 
-    pub fn set_on_save_callback<F>(&self, callback: F)
+    /// Called with each change made in the manage dialog
+    pub fn set_on_change_callback<F>(&self, callback: F)
+    where
+        F: Fn(MountChange) + 'static,
+    {
+        *self.on_change_callback.borrow_mut() = Some(Rc::new(callback));
+    }
+
+    /// Called once the disks were probed again after a format
+    pub fn set_on_reload_callback<F>(&self, callback: F)
     where
         F: Fn() + 'static,
     {
-        *self.on_save_callback.borrow_mut() = Some(Rc::new(callback));
+        *self.on_reload_callback.borrow_mut() = Some(Rc::new(callback));
     }
 
     fn populate(&self) {
@@ -107,8 +120,7 @@ impl DisksWidget {
                 // Show disk if:
                 // 1. It has at least one partition that is NOT critical (/, /boot, etc.), OR
                 // 2. It has no partitions at all (virgin disk)
-                disk.partitions.is_empty()
-                    || disk.partitions.iter().any(|p| !should_filter_partition(p))
+                disk.is_blank() || disk.partitions.iter().any(|p| !should_filter_partition(p))
             })
             .collect();
 
@@ -157,7 +169,7 @@ impl DisksWidget {
         card.append(&disk_label);
 
         // Show status/partitions info
-        let is_virgin = disk.partitions.is_empty();
+        let is_virgin = disk.is_blank();
         if is_virgin {
             let status_label = Label::new(Some("Disque vierge"));
             status_label.add_css_class("dim-label");
@@ -187,7 +199,12 @@ impl DisksWidget {
                         .unwrap_or("unknown");
                     let fs_type = partition.fs_type.as_deref().unwrap_or("unknown");
 
-                    let part_info = Label::new(Some(&format!("{} ({})", part_name, fs_type)));
+                    // Filesystems on LUKS, LVM or RAID show the devices below them
+                    let part_info = Label::new(Some(
+                        &partition
+                            .stack_description()
+                            .unwrap_or_else(|| format!("{} ({})", part_name, fs_type)),
+                    ));
                     part_info.add_css_class("caption");
                     part_info.set_halign(gtk4::Align::Start);
                     part_info.set_valign(gtk4::Align::Center);
@@ -264,32 +281,44 @@ impl DisksWidget {
         let disks_rc = self.disks.clone();
         let container_clone = self.container.clone();
         let disks_for_refresh = self.disks.clone();
-        let hardware_config_clone = self.hardware_config.clone();
-        let on_save_callback_clone = self.on_save_callback.clone();
+        let nix_config_clone = self.nix_config.clone();
+        let on_change_callback_clone = self.on_change_callback.clone();
+        let on_reload_callback_clone = self.on_reload_callback.clone();
 
         manage_button.connect_clicked(move |btn| {
             if let Some(window) = btn.root().and_then(|r| r.downcast::<gtk4::Window>().ok()) {
-                if disk_clone.partitions.is_empty() {
+                if disk_clone.is_blank() {
                     // Show format dialog
                     use crate::ui::dialogs::FormatDiskDialog;
+                    use crate::ui::dialogs::format_disk::MountOptions;
 
                     // Create refresh callback
                     let container_refresh = container_clone.clone();
                     let disks_refresh = disks_for_refresh.clone();
-                    let hardware_config_for_refresh = hardware_config_clone.clone();
-                    let on_save_callback_for_refresh = on_save_callback_clone.clone();
+                    let nix_config_for_refresh = nix_config_clone.clone();
+                    let on_change_callback_for_refresh = on_change_callback_clone.clone();
+                    let on_reload_callback_for_refresh = on_reload_callback_clone.clone();
 
-                    let refresh_callback = move || {
+                    let refresh_callback = move |changes: Vec<_>, options: MountOptions| {
                         eprintln!("🔄 Rafraîchissement après formatage...");
 
-                        // Reload disks from system with hardware config
-                        use crate::utils::get_disks;
-                        let config_ref = hardware_config_for_refresh
-                            .as_ref()
-                            .map(|c| c.borrow().clone());
-                        let config_str = config_ref.as_deref();
-
-                        if let Ok(new_disks) = get_disks(config_str) {
+                        // Reload disks from system with the NixOS config
+                        use nix_disk_core::utils::get_disks;
+                        let config_ref =
+                            nix_config_for_refresh.as_ref().map(|c| c.borrow().clone());
+                        let sources = config_ref.as_ref().map(|c| c.sources()).unwrap_or_default();
+
+                        if let Ok(mut new_disks) = get_disks(&sources) {
+                            // Options of the mount points added next
+                            for (path, defaults) in options {
+                                if let Some(partition) = new_disks
+                                    .iter_mut()
+                                    .flat_map(|d| d.partitions.iter_mut())
+                                    .find(|p| p.path == path)
+                                {
+                                    partition.default_options = Some(defaults);
+                                }
+                            }
                             *disks_refresh.borrow_mut() = new_disks;
                         }
 
@@ -301,27 +330,42 @@ impl DisksWidget {
                         // Recreate the widget to repopulate
                         let temp_widget = Self::new_with_config(
                             disks_refresh.clone(),
-                            hardware_config_for_refresh.clone(),
+                            nix_config_for_refresh.clone(),
                         );
 
-                        // Reapply the save callback to the new widget
-                        if let Some(callback) = on_save_callback_for_refresh.borrow().as_ref() {
+                        // Reapply the callbacks to the new widget
+                        if let Some(callback) = on_change_callback_for_refresh.borrow().as_ref() {
                             let callback_clone = callback.clone();
-                            temp_widget.set_on_save_callback(move || callback_clone());
+                            temp_widget
+                                .set_on_change_callback(move |change| callback_clone(change));
+                        }
+                        if let Some(callback) = on_reload_callback_for_refresh.borrow().as_ref() {
+                            let callback_clone = callback.clone();
+                            temp_widget.set_on_reload_callback(move || callback_clone());
                         }
 
                         while let Some(child) = temp_widget.container.first_child() {
                             temp_widget.container.remove(&child);
                             container_refresh.append(&child);
                         }
+
+                        if let Some(callback) = on_reload_callback_for_refresh.borrow().as_ref() {
+                            callback();
+                        }
+
+                        // Staged on top of the disks probed again
+                        if let Some(callback) = on_change_callback_for_refresh.borrow().as_ref() {
+                            for change in changes {
+                                callback(change);
+                            }
+                        }
                     };
 
-                    let dialog =
-                        FormatDiskDialog::new(&disk_clone, disks_rc.clone(), refresh_callback);
+                    let dialog = FormatDiskDialog::new(&disk_clone, refresh_callback);
                     dialog.present(Some(&window));
                 } else {
                     // Show manage dialog
-                    let callback = on_save_callback_clone.borrow().clone();
+                    let callback = on_change_callback_clone.borrow().clone();
                     let dialog = ManageDiskDialog::new(&disk_clone, disks_rc.clone(), callback);
                     dialog.present(Some(&window));
                 }
@@ -375,8 +419,7 @@ impl DisksWidget {
         disks
             .iter()
             .filter(|disk| {
-                disk.partitions.is_empty()
-                    || disk.partitions.iter().any(|p| !should_filter_partition(p))
+                disk.is_blank() || disk.partitions.iter().any(|p| !should_filter_partition(p))
             })
             .count()
     }
@@ -392,8 +435,7 @@ impl DisksWidget {
         let max_partitions_height = disks
             .iter()
             .filter(|disk| {
-                disk.partitions.is_empty()
-                    || disk.partitions.iter().any(|p| !should_filter_partition(p))
+                disk.is_blank() || disk.partitions.iter().any(|p| !should_filter_partition(p))
             })
             .map(|disk| {
                 let non_critical_count = disk
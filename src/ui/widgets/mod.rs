@@ -1,3 +1,11 @@
+pub mod diff_view;
 pub mod disks;
+pub mod partition_layout;
+pub mod pending_bar;
+pub mod rebuild_log;
 
+pub use diff_view::DiffView;
 pub use disks::DisksWidget;
+pub use partition_layout::LayoutEditor;
+pub use pending_bar::PendingBar;
+pub use rebuild_log::{RebuildLog, RebuildOutcome};
@@ -0,0 +1,649 @@
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use gtk4::{Button, CheckButton, DropDown, Entry, Label, Orientation, PasswordEntry};
+use nix_disk_core::models::{Disk, Unlock};
+use nix_disk_core::utils::format::{
+    BTRFS_COMPRESSIONS, FormatOptions, FsType, InvalidLabel, LuksOptions, max_label_length,
+    validate_label,
+};
+use nix_disk_core::utils::layout::{
+    DiskLayout, PARTITION_TYPES, PartitionSize, PartitionSpec, PlannedPartition, TableType,
+    format_size,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Tables offered, in the order of the drop-down
+const TABLE_TYPES: [TableType; 2] = [TableType::Gpt, TableType::Msdos];
+
+/// exFAT cluster sizes offered, in KiB; `None` lets mkfs choose
+const CLUSTER_SIZES: [Option<u32>; 5] = [None, Some(4), Some(32), Some(128), Some(1024)];
+
+/// Unlock methods offered, in the order of the drop-down; an unplugged
+/// disk opened in the initrd blocks the boot, so crypttab comes first
+const UNLOCK_METHODS: [Unlock; 2] = [Unlock::Crypttab, Unlock::Initrd];
+
+fn unlock_description(unlock: Unlock) -> String {
+    match unlock {
+        Unlock::Crypttab => gettext("Once the system is up, skipped if the disk is missing"),
+        Unlock::Initrd => gettext("At boot, which waits for the disk"),
+    }
+}
+
+fn fs_type_description(fs_type: FsType) -> String {
+    match fs_type {
+        FsType::Ext4 => gettext("ext4 (Linux)"),
+        FsType::Btrfs => gettext("btrfs (Linux, compression and snapshots)"),
+        FsType::Xfs => gettext("XFS (Linux, large files)"),
+        FsType::Exfat => gettext("exFAT (shared with Windows and macOS)"),
+        FsType::Ntfs => gettext("NTFS (shared with Windows)"),
+        FsType::Vfat => gettext("FAT32 (small USB sticks)"),
+    }
+}
+
+fn label_hint(fs_type: FsType) -> String {
+    // TRANSLATORS: %d is the maximum length of a volume name
+    gettext("(Maximum %d characters)")
+        .replace("%d", &max_label_length(fs_type.as_str()).to_string())
+}
+
+/// Widgets choosing the filesystem and its options
+#[derive(Clone)]
+struct FsTypeChooser {
+    fs_type: DropDown,
+    btrfs_box: gtk4::Box,
+    compression: DropDown,
+    subvolumes: Entry,
+    xfs_box: gtk4::Box,
+    reflink: CheckButton,
+    exfat_box: gtk4::Box,
+    cluster_size: DropDown,
+}
+
+impl FsTypeChooser {
+    fn new() -> Self {
+        let descriptions: Vec<String> = FsType::ALL.into_iter().map(fs_type_description).collect();
+        let descriptions: Vec<&str> = descriptions.iter().map(String::as_str).collect();
+        let fs_type = DropDown::from_strings(&descriptions);
+
+        let option_box = || {
+            let option_box = gtk4::Box::new(Orientation::Vertical, 6);
+            option_box.set_visible(false);
+            option_box
+        };
+        let caption = |text: &str| {
+            let label = Label::new(Some(text));
+            label.set_halign(gtk4::Align::Start);
+            label.add_css_class("caption");
+            label
+        };
+
+        let btrfs_box = option_box();
+        let none = gettext("None");
+        let mut compressions: Vec<&str> = BTRFS_COMPRESSIONS.to_vec();
+        compressions.push(&none);
+        let compression = DropDown::from_strings(&compressions);
+        let subvolumes = Entry::builder()
+            .placeholder_text(gettext("e.g. @data, @snapshots"))
+            .build();
+        btrfs_box.append(&caption(&gettext("Compression")));
+        btrfs_box.append(&compression);
+        btrfs_box.append(&caption(&gettext(
+            "Subvolumes, separated by commas (the first one is mounted)",
+        )));
+        btrfs_box.append(&subvolumes);
+
+        let xfs_box = option_box();
+        let reflink = CheckButton::with_label(&gettext("Copies share their blocks (reflink)"));
+        reflink.set_active(true);
+        xfs_box.append(&reflink);
+
+        let exfat_box = option_box();
+        let sizes: Vec<String> = CLUSTER_SIZES
+            .iter()
+            .map(|size| match size {
+                None => gettext("Automatic"),
+                Some(kib) if kib % 1024 == 0 => format!("{} MiB", kib / 1024),
+                Some(kib) => format!("{} KiB", kib),
+            })
+            .collect();
+        let sizes: Vec<&str> = sizes.iter().map(String::as_str).collect();
+        let cluster_size = DropDown::from_strings(&sizes);
+        exfat_box.append(&caption(&gettext("Cluster size")));
+        exfat_box.append(&cluster_size);
+
+        let chooser = Self {
+            fs_type,
+            btrfs_box,
+            compression,
+            subvolumes,
+            xfs_box,
+            reflink,
+            exfat_box,
+            cluster_size,
+        };
+        let chooser_for_change = chooser.clone();
+        chooser.fs_type.connect_selected_notify(move |_| {
+            chooser_for_change.show_options();
+        });
+        chooser
+    }
+
+    fn append_to(&self, container: &gtk4::Box) {
+        container.append(&self.fs_type);
+        container.append(&self.btrfs_box);
+        container.append(&self.xfs_box);
+        container.append(&self.exfat_box);
+    }
+
+    fn selected(&self) -> FsType {
+        FsType::ALL
+            .get(self.fs_type.selected() as usize)
+            .copied()
+            .unwrap_or(FsType::Ext4)
+    }
+
+    /// Only show the options of the selected filesystem
+    fn show_options(&self) {
+        let fs_type = self.selected();
+        self.btrfs_box.set_visible(fs_type == FsType::Btrfs);
+        self.xfs_box.set_visible(fs_type == FsType::Xfs);
+        self.exfat_box.set_visible(fs_type == FsType::Exfat);
+    }
+
+    fn options(&self, label: &str) -> FormatOptions {
+        let subvolumes = self.subvolumes.text();
+        FormatOptions {
+            compression: BTRFS_COMPRESSIONS
+                .get(self.compression.selected() as usize)
+                .map(|c| c.to_string()),
+            subvolumes: subvolumes
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            reflink: self.reflink.is_active(),
+            cluster_size: CLUSTER_SIZES
+                .get(self.cluster_size.selected() as usize)
+                .copied()
+                .flatten(),
+            ..FormatOptions::new(self.selected(), label)
+        }
+    }
+}
+
+fn invalid_label_message(error: &InvalidLabel) -> String {
+    match error {
+        InvalidLabel::Empty => gettext("Enter a volume name"),
+        InvalidLabel::TooLong { max, .. } => {
+            // TRANSLATORS: %d is the maximum length of a volume name
+            gettext("The name is too long, at most %d characters").replace("%d", &max.to_string())
+        }
+        InvalidLabel::Character { character, .. } => {
+            // TRANSLATORS: %s is a character (e.g., "/")
+            gettext("The name cannot contain %s").replace("%s", &format!("{:?}", character))
+        }
+        InvalidLabel::LeadingDash => gettext("The name cannot start with a dash"),
+    }
+}
+
+/// Settings of one partition of the layout
+#[derive(Clone)]
+struct PartitionRow {
+    container: gtk4::Box,
+    title: Label,
+    remove_button: Button,
+    size: Entry,
+    fs: FsTypeChooser,
+    label: Entry,
+    label_hint: Label,
+    gpt_box: gtk4::Box,
+    name: Entry,
+    type_guid: DropDown,
+    encrypt: CheckButton,
+    luks_name: Entry,
+    unlock: DropDown,
+    key_file: CheckButton,
+}
+
+impl PartitionRow {
+    fn new(size: &str, label: &str) -> Self {
+        let container = gtk4::Box::new(Orientation::Vertical, 6);
+        container.add_css_class("card");
+        container.set_margin_bottom(6);
+        let inner = gtk4::Box::new(Orientation::Vertical, 6);
+        inner.set_margin_top(12);
+        inner.set_margin_bottom(12);
+        inner.set_margin_start(12);
+        inner.set_margin_end(12);
+        container.append(&inner);
+
+        let header = gtk4::Box::new(Orientation::Horizontal, 6);
+        let title = Label::new(None);
+        title.set_halign(gtk4::Align::Start);
+        title.set_hexpand(true);
+        title.add_css_class("heading");
+        header.append(&title);
+        let remove_button = Button::from_icon_name("user-trash-symbolic");
+        remove_button.add_css_class("flat");
+        remove_button.set_tooltip_text(Some(&gettext("Remove this partition")));
+        header.append(&remove_button);
+        inner.append(&header);
+
+        let caption = |text: &str| {
+            let caption = Label::new(Some(text));
+            caption.set_halign(gtk4::Align::Start);
+            caption.add_css_class("caption");
+            caption
+        };
+
+        inner.append(&caption(&gettext("Size (e.g. 512MiB, 20G, 25% or rest)")));
+        let size_entry = Entry::builder().text(size).build();
+        inner.append(&size_entry);
+
+        inner.append(&caption(&gettext("Filesystem")));
+        let fs = FsTypeChooser::new();
+        fs.append_to(&inner);
+
+        inner.append(&caption(&gettext("Volume name")));
+        let label_entry = Entry::builder()
+            .text(label)
+            .placeholder_text(gettext("e.g. data, backup, storage..."))
+            .build();
+        inner.append(&label_entry);
+        let label_hint = Label::new(None);
+        label_hint.set_halign(gtk4::Align::Start);
+        label_hint.set_wrap(true);
+        label_hint.add_css_class("caption");
+        inner.append(&label_hint);
+
+        let gpt_box = gtk4::Box::new(Orientation::Vertical, 6);
+        gpt_box.append(&caption(&gettext("Partition name")));
+        let name = Entry::builder()
+            .placeholder_text(gettext("Same as the volume name"))
+            .build();
+        gpt_box.append(&name);
+        gpt_box.append(&caption(&gettext("Partition type")));
+        let mut types = vec![gettext("Automatic (from the filesystem)")];
+        types.extend(
+            PARTITION_TYPES
+                .iter()
+                .map(|(description, guid)| format!("{} ({})", description, guid)),
+        );
+        let types: Vec<&str> = types.iter().map(String::as_str).collect();
+        let type_guid = DropDown::from_strings(&types);
+        gpt_box.append(&type_guid);
+        inner.append(&gpt_box);
+
+        let encrypt = CheckButton::with_label(&gettext("Encrypt (LUKS)"));
+        inner.append(&encrypt);
+        let luks_box = gtk4::Box::new(Orientation::Vertical, 6);
+        luks_box.set_visible(false);
+        luks_box.append(&caption(&gettext("Encrypted volume name (/dev/mapper/…)")));
+        let luks_name = Entry::builder()
+            .placeholder_text(gettext("Same as the volume name"))
+            .build();
+        luks_box.append(&luks_name);
+        luks_box.append(&caption(&gettext("Unlock")));
+        let unlock_methods: Vec<String> =
+            UNLOCK_METHODS.into_iter().map(unlock_description).collect();
+        let unlock_methods: Vec<&str> = unlock_methods.iter().map(String::as_str).collect();
+        let unlock = DropDown::from_strings(&unlock_methods);
+        luks_box.append(&unlock);
+        let key_file = CheckButton::with_label(&gettext(
+            "Open it with a key file stored on the system disk",
+        ));
+        luks_box.append(&key_file);
+        inner.append(&luks_box);
+
+        encrypt.connect_toggled(move |check| {
+            luks_box.set_visible(check.is_active());
+        });
+        // Only the running system can hold the key file
+        let key_file_for_unlock = key_file.clone();
+        unlock.connect_selected_notify(move |dropdown| {
+            let crypttab =
+                UNLOCK_METHODS.get(dropdown.selected() as usize) == Some(&Unlock::Crypttab);
+            key_file_for_unlock.set_sensitive(crypttab);
+            if !crypttab {
+                key_file_for_unlock.set_active(false);
+            }
+        });
+
+        Self {
+            container,
+            title,
+            remove_button,
+            size: size_entry,
+            fs,
+            label: label_entry,
+            label_hint,
+            gpt_box,
+            name,
+            type_guid,
+            encrypt,
+            luks_name,
+            unlock,
+            key_file,
+        }
+    }
+
+    /// Call `callback` whenever a setting changes
+    fn connect_changed(&self, callback: Rc<dyn Fn()>) {
+        for entry in [
+            &self.size,
+            &self.label,
+            &self.name,
+            &self.fs.subvolumes,
+            &self.luks_name,
+        ] {
+            let callback = callback.clone();
+            entry.connect_changed(move |_| callback());
+        }
+        for dropdown in [
+            &self.fs.fs_type,
+            &self.type_guid,
+            &self.fs.cluster_size,
+            &self.unlock,
+        ] {
+            let callback = callback.clone();
+            dropdown.connect_selected_notify(move |_| callback());
+        }
+        for check in [&self.encrypt, &self.key_file] {
+            let callback = callback.clone();
+            check.connect_toggled(move |_| callback());
+        }
+    }
+
+    /// Show what is wrong with the volume name, returns the message
+    fn check_label(&self) -> Option<String> {
+        let fs_type = self.fs.selected();
+        match validate_label(fs_type.as_str(), self.label.text().trim()) {
+            Ok(()) => {
+                self.label.remove_css_class("error");
+                self.label_hint.remove_css_class("error");
+                self.label_hint.add_css_class("dim-label");
+                self.label_hint.set_text(&label_hint(fs_type));
+                None
+            }
+            Err(e) => {
+                let message = invalid_label_message(&e);
+                self.label.add_css_class("error");
+                self.label_hint.remove_css_class("dim-label");
+                self.label_hint.add_css_class("error");
+                self.label_hint.set_text(&message);
+                Some(message)
+            }
+        }
+    }
+
+    fn spec(&self, table: TableType) -> anyhow::Result<PartitionSpec> {
+        let size: PartitionSize = self.size.text().parse()?;
+        let label = self.label.text().trim().to_string();
+        let mut spec = PartitionSpec::new(size, self.fs.options(&label));
+        if self.encrypt.is_active() {
+            let name = self.luks_name.text().trim().to_string();
+            spec.format.encryption = Some(LuksOptions {
+                unlock: UNLOCK_METHODS
+                    .get(self.unlock.selected() as usize)
+                    .copied()
+                    .unwrap_or(Unlock::Crypttab),
+                key_file: self.key_file.is_active(),
+                ..LuksOptions::new(if name.is_empty() { &label } else { &name })
+            });
+        }
+        if table == TableType::Gpt {
+            let name = self.name.text().trim().to_string();
+            spec.name = Some(name).filter(|n| !n.is_empty());
+            spec.type_guid = (self.type_guid.selected() as usize)
+                .checked_sub(1)
+                .and_then(|i| PARTITION_TYPES.get(i))
+                .map(|(_, guid)| guid.to_string());
+        }
+        Ok(spec)
+    }
+}
+
+/// Editor of the partitions created on a disk, with a preview of the table
+#[derive(Clone)]
+pub struct LayoutEditor {
+    container: gtk4::Box,
+    table: DropDown,
+    rows_box: gtk4::Box,
+    rows: Rc<RefCell<Vec<PartitionRow>>>,
+    passphrase_box: gtk4::Box,
+    passphrase: PasswordEntry,
+    passphrase_again: PasswordEntry,
+    preview: Label,
+    error_label: Label,
+    disk_size: u64,
+    on_validity: Rc<dyn Fn(bool)>,
+}
+
+impl LayoutEditor {
+    /// Starts with one partition filling `disk`; `on_validity` tells
+    /// whether the layout can be created each time it changes
+    pub fn new(disk: &Disk, on_validity: impl Fn(bool) + 'static) -> Self {
+        let container = gtk4::Box::new(Orientation::Vertical, 12);
+
+        let table_label = Label::new(Some(&gettext("Partition table")));
+        table_label.set_halign(gtk4::Align::Start);
+        table_label.add_css_class("heading");
+        container.append(&table_label);
+        let tables: Vec<String> = TABLE_TYPES.iter().map(|t| t.to_string()).collect();
+        let tables: Vec<&str> = tables.iter().map(String::as_str).collect();
+        let table = DropDown::from_strings(&tables);
+        container.append(&table);
+
+        let rows_box = gtk4::Box::new(Orientation::Vertical, 6);
+        container.append(&rows_box);
+
+        let add_button = Button::with_label(&gettext("Add a partition"));
+        add_button.set_halign(gtk4::Align::Start);
+        container.append(&add_button);
+
+        // One passphrase for every encrypted partition of the disk
+        let passphrase_box = gtk4::Box::new(Orientation::Vertical, 6);
+        passphrase_box.set_visible(false);
+        let passphrase_title = Label::new(Some(&gettext("Passphrase")));
+        passphrase_title.set_halign(gtk4::Align::Start);
+        passphrase_title.add_css_class("heading");
+        passphrase_box.append(&passphrase_title);
+        let passphrase = PasswordEntry::builder().show_peek_icon(true).build();
+        passphrase_box.append(&passphrase);
+        let passphrase_again = PasswordEntry::builder()
+            .show_peek_icon(true)
+            .placeholder_text(gettext("Confirm the passphrase"))
+            .build();
+        passphrase_box.append(&passphrase_again);
+        container.append(&passphrase_box);
+
+        let preview_title = Label::new(Some(&gettext("Preview")));
+        preview_title.set_halign(gtk4::Align::Start);
+        preview_title.add_css_class("heading");
+        container.append(&preview_title);
+        let preview = Label::new(None);
+        preview.set_halign(gtk4::Align::Start);
+        preview.add_css_class("monospace");
+        preview.set_selectable(true);
+        container.append(&preview);
+        let error_label = Label::new(None);
+        error_label.set_halign(gtk4::Align::Start);
+        error_label.set_wrap(true);
+        error_label.add_css_class("error");
+        container.append(&error_label);
+
+        let editor = Self {
+            container,
+            table,
+            rows_box,
+            rows: Rc::new(RefCell::new(Vec::new())),
+            passphrase_box,
+            passphrase,
+            passphrase_again,
+            preview,
+            error_label,
+            disk_size: disk.size,
+            on_validity: Rc::new(on_validity),
+        };
+
+        // Set a default volume name based on disk size
+        editor.add_row("rest", &format!("disk_{}", disk.size / 1_000_000_000));
+
+        let editor_for_table = editor.clone();
+        editor
+            .table
+            .connect_selected_notify(move |_| editor_for_table.refresh());
+        for entry in [&editor.passphrase, &editor.passphrase_again] {
+            let editor = editor.clone();
+            entry.connect_changed(move |_| editor.refresh());
+        }
+        let editor_for_add = editor.clone();
+        add_button.connect_clicked(move |_| {
+            // The previous last partition no longer gets the rest of the disk
+            if let Some(last) = editor_for_add.rows.borrow().last()
+                && last.size.text().parse::<PartitionSize>() == Ok(PartitionSize::Rest)
+            {
+                last.size.set_text("50%");
+            }
+            let number = editor_for_add.rows.borrow().len() + 1;
+            editor_for_add.add_row("rest", &format!("part{}", number));
+        });
+        editor
+    }
+
+    pub fn widget(&self) -> &gtk4::Box {
+        &self.container
+    }
+
+    fn add_row(&self, size: &str, label: &str) {
+        let row = PartitionRow::new(size, label);
+        let editor = self.clone();
+        row.connect_changed(Rc::new(move || editor.refresh()));
+
+        let editor = self.clone();
+        let container = row.container.clone();
+        row.remove_button.connect_clicked(move |_| {
+            editor
+                .rows
+                .borrow_mut()
+                .retain(|r| r.container != container);
+            editor.rows_box.remove(&container);
+            editor.refresh();
+        });
+
+        self.rows_box.append(&row.container);
+        self.rows.borrow_mut().push(row);
+        self.refresh();
+    }
+
+    /// Passphrase of the encrypted partitions, `None` when there are none
+    pub fn passphrase(&self) -> Option<String> {
+        self.passphrase_box
+            .is_visible()
+            .then(|| self.passphrase.text().to_string())
+    }
+
+    fn table_type(&self) -> TableType {
+        TABLE_TYPES
+            .get(self.table.selected() as usize)
+            .copied()
+            .unwrap_or(TableType::Gpt)
+    }
+
+    /// The layout being edited
+    pub fn layout(&self) -> anyhow::Result<DiskLayout> {
+        let table = self.table_type();
+        let partitions = self
+            .rows
+            .borrow()
+            .iter()
+            .map(|row| row.spec(table))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let layout = DiskLayout { table, partitions };
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Update the rows and the preview after a change
+    fn refresh(&self) {
+        let table = self.table_type();
+        let mut label_error = None;
+        {
+            let rows = self.rows.borrow();
+            for (i, row) in rows.iter().enumerate() {
+                // TRANSLATORS: %d is the number of a partition
+                let title = gettext("Partition %d").replace("%d", &(i + 1).to_string());
+                row.title.set_text(&title);
+                row.remove_button.set_sensitive(rows.len() > 1);
+                row.gpt_box.set_visible(table == TableType::Gpt);
+                if let Some(message) = row.check_label() {
+                    label_error.get_or_insert(format!("{}: {}", title, message));
+                }
+            }
+        }
+
+        let encrypted = self.rows.borrow().iter().any(|row| row.encrypt.is_active());
+        self.passphrase_box.set_visible(encrypted);
+        let passphrase_error = if !encrypted {
+            None
+        } else if self.passphrase.text().is_empty() {
+            Some(gettext("Enter the passphrase of the encrypted partitions"))
+        } else if self.passphrase.text() != self.passphrase_again.text() {
+            Some(gettext("The passphrases differ"))
+        } else {
+            None
+        };
+
+        let result = match label_error {
+            Some(message) => Err(anyhow::anyhow!(message)),
+            None => self.layout().and_then(|layout| layout.plan(self.disk_size)),
+        };
+        match result {
+            Ok(planned) => {
+                self.preview.set_text(&preview_text(&planned));
+                self.preview.set_visible(true);
+                // The table can be previewed before the passphrase is typed
+                self.error_label
+                    .set_text(passphrase_error.as_deref().unwrap_or_default());
+                self.error_label.set_visible(passphrase_error.is_some());
+                (self.on_validity)(passphrase_error.is_none());
+            }
+            Err(e) => {
+                self.preview.set_visible(false);
+                self.error_label.set_text(&format!("{:#}", e));
+                self.error_label.set_visible(true);
+                (self.on_validity)(false);
+            }
+        }
+    }
+}
+
+/// The partition table as it will be written, one line per partition
+fn preview_text(planned: &[PlannedPartition]) -> String {
+    let mut lines = vec![format!(
+        "{:<3} {:>10} {:>10}  {:<6} {}",
+        "#",
+        gettext("Start"),
+        gettext("Size"),
+        gettext("Type"),
+        gettext("Name")
+    )];
+    for partition in planned {
+        let format = &partition.spec.format;
+        let mut line = format!(
+            "{:<3} {:>10} {:>10}  {:<6} {}",
+            partition.number,
+            format_size(partition.start),
+            format_size(partition.size()),
+            format.fs_type.as_str(),
+            format.label
+        );
+        if let Some(luks) = &format.encryption {
+            line.push_str(&format!(" (LUKS, /dev/mapper/{})", luks.name));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
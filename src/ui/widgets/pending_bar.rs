@@ -0,0 +1,59 @@
+use gettextrs::{gettext, ngettext};
+use gtk4::prelude::*;
+use gtk4::{Button, Label};
+
+/// Bar at the bottom of the window while changes wait to be applied
+#[derive(Clone)]
+pub struct PendingBar {
+    action_bar: gtk4::ActionBar,
+    label: Label,
+    apply_button: Button,
+    discard_button: Button,
+}
+
+impl PendingBar {
+    pub fn new() -> Self {
+        let action_bar = gtk4::ActionBar::new();
+        action_bar.set_revealed(false);
+
+        let label = Label::new(None);
+        label.add_css_class("heading");
+        action_bar.set_center_widget(Some(&label));
+
+        let discard_button = Button::with_label(&gettext("Discard"));
+        discard_button.add_css_class("destructive-action");
+        action_bar.pack_start(&discard_button);
+
+        let apply_button = Button::with_label(&gettext("Apply"));
+        apply_button.add_css_class("suggested-action");
+        action_bar.pack_end(&apply_button);
+
+        Self {
+            action_bar,
+            label,
+            apply_button,
+            discard_button,
+        }
+    }
+
+    pub fn widget(&self) -> &gtk4::ActionBar {
+        &self.action_bar
+    }
+
+    /// Show the number of pending changes, hiding the bar when there are none
+    pub fn set_count(&self, count: usize) {
+        // TRANSLATORS: %d is the number of changes not applied yet
+        let text = ngettext("%d pending change", "%d pending changes", count as u32)
+            .replace("%d", &count.to_string());
+        self.label.set_text(&text);
+        self.action_bar.set_revealed(count > 0);
+    }
+
+    pub fn connect_apply(&self, callback: impl Fn() + 'static) {
+        self.apply_button.connect_clicked(move |_| callback());
+    }
+
+    pub fn connect_discard(&self, callback: impl Fn() + 'static) {
+        self.discard_button.connect_clicked(move |_| callback());
+    }
+}
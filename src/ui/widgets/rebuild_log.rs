@@ -0,0 +1,172 @@
+use gettextrs::gettext;
+use gtk4::prelude::*;
+use gtk4::{Button, Label, Orientation, TextView};
+use nix_disk_core::NixDiskError;
+use nix_disk_core::utils::{RebuildFailure, RebuildPhase, parse_failure, parse_phase};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Live output of `nixos-rebuild`, shown at the bottom of the window
+#[derive(Clone)]
+pub struct RebuildLog {
+    revealer: gtk4::Revealer,
+    phase_label: Label,
+    text_view: TextView,
+    scrolled: gtk4::ScrolledWindow,
+    lines: Rc<RefCell<Vec<String>>>,
+}
+
+impl RebuildLog {
+    pub fn new() -> Self {
+        let container = gtk4::Box::new(Orientation::Vertical, 6);
+        container.set_margin_start(12);
+        container.set_margin_end(12);
+        container.set_margin_bottom(12);
+
+        let header = gtk4::Box::new(Orientation::Horizontal, 6);
+        let phase_label = Label::new(None);
+        phase_label.set_halign(gtk4::Align::Start);
+        phase_label.set_hexpand(true);
+        phase_label.add_css_class("heading");
+        header.append(&phase_label);
+
+        let close_button = Button::from_icon_name("window-close-symbolic");
+        close_button.add_css_class("flat");
+        close_button.set_tooltip_text(Some(&gettext("Hide the rebuild log")));
+        header.append(&close_button);
+        container.append(&header);
+
+        let text_view = TextView::builder()
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk4::WrapMode::WordChar)
+            .build();
+        let scrolled = gtk4::ScrolledWindow::builder()
+            .min_content_height(200)
+            .child(&text_view)
+            .build();
+        scrolled.add_css_class("card");
+        container.append(&scrolled);
+
+        let revealer = gtk4::Revealer::builder()
+            .transition_type(gtk4::RevealerTransitionType::SlideUp)
+            .child(&container)
+            .reveal_child(false)
+            .build();
+
+        let revealer_clone = revealer.clone();
+        close_button.connect_clicked(move |_| revealer_clone.set_reveal_child(false));
+
+        Self {
+            revealer,
+            phase_label,
+            text_view,
+            scrolled,
+            lines: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn widget(&self) -> &gtk4::Revealer {
+        &self.revealer
+    }
+
+    /// Clear the previous output and show the pane
+    pub fn start(&self) {
+        self.text_view.buffer().set_text("");
+        self.lines.borrow_mut().clear();
+        self.phase_label
+            .set_text(&gettext("Evaluating the configuration..."));
+        self.revealer.set_reveal_child(true);
+    }
+
+    /// Clear the previous output and show the pane for a dry build
+    pub fn start_check(&self) {
+        self.start();
+        self.phase_label
+            .set_text(&gettext("Checking that the system still builds..."));
+    }
+
+    /// Show whether the dry build passed
+    pub fn finish_check(&self, success: bool) {
+        let status = if success {
+            gettext("The new configuration builds")
+        } else {
+            gettext("The new configuration does not build, nothing was saved")
+        };
+        self.phase_label.set_text(&status);
+    }
+
+    /// Append a line of output
+    ///
+    /// Returns the text describing the phase this line starts, if any.
+    pub fn append_line(&self, line: &str) -> Option<String> {
+        self.append_output(line);
+
+        let phase = match parse_phase(line)? {
+            RebuildPhase::Building => gettext("Building the system..."),
+            RebuildPhase::Activating => gettext("Activating the configuration..."),
+            RebuildPhase::RestartingUnits => gettext("Restarting services..."),
+        };
+        self.phase_label.set_text(&phase);
+        Some(phase)
+    }
+
+    /// Append a line of output without looking for a phase
+    pub fn append_output(&self, line: &str) {
+        self.lines.borrow_mut().push(line.to_string());
+        let buffer = self.text_view.buffer();
+        let mut end = buffer.end_iter();
+        buffer.insert(&mut end, line);
+        buffer.insert(&mut end, "\n");
+
+        // Follow the output while the user has not scrolled up
+        let adjustment = self.scrolled.vadjustment();
+        if adjustment.value() + adjustment.page_size() >= adjustment.upper() - 50.0 {
+            let mark = buffer.create_mark(None, &buffer.end_iter(), false);
+            self.text_view.scroll_mark_onscreen(&mark);
+            buffer.delete_mark(&mark);
+        }
+    }
+
+    /// Show how the rebuild ended
+    pub fn finish(&self, outcome: &RebuildOutcome) {
+        let status = match outcome {
+            RebuildOutcome::Success => gettext("Rebuild finished successfully"),
+            RebuildOutcome::Cancelled => gettext("Rebuild cancelled"),
+            RebuildOutcome::Failed(e) => match NixDiskError::find(e) {
+                Some(NixDiskError::RebuildFailed { code: Some(code) }) => {
+                    // TRANSLATORS: %d is the exit code of nixos-rebuild
+                    gettext("Rebuild failed with exit code %d").replace("%d", &code.to_string())
+                }
+                _ => gettext("Rebuild failed"),
+            },
+        };
+        self.phase_label.set_text(&status);
+    }
+
+    /// One-line explanation of a failed rebuild, from its output
+    pub fn failure_summary(&self) -> Option<String> {
+        let summary = match parse_failure(&self.lines.borrow())? {
+            // TRANSLATORS: %s is the name of a Nix derivation (e.g., unit-backup.service)
+            RebuildFailure::Derivation(name) => gettext("Failed to build %s").replace("%s", &name),
+            RebuildFailure::Activation(units) if units.is_empty() => {
+                gettext("Failed to switch to the new configuration")
+            }
+            // TRANSLATORS: %s is a list of systemd units (e.g., mnt-data.mount)
+            RebuildFailure::Activation(units) => {
+                gettext("Failed to switch to the new configuration: %s").replace("%s", &units)
+            }
+            RebuildFailure::Error(message) => message,
+        };
+        Some(summary)
+    }
+}
+
+/// How a rebuild ended
+pub enum RebuildOutcome {
+    Success,
+    /// Stopped by the user, or the authentication was dismissed
+    Cancelled,
+    Failed(anyhow::Error),
+}
@@ -1,27 +1,36 @@
-use crate::models::{Disk, Partition};
-use crate::ui::dialogs::{MissingPartitionsDialog, WelcomeDialog};
-use crate::ui::widgets::DisksWidget;
-use crate::utils::get_nix_disks_config;
-use gettextrs::gettext;
+use crate::helper::HelperClient;
+use crate::ui::dialogs::history::format_timestamp;
+use crate::ui::dialogs::{
+    ErrorDialog, HistoryDialog, MissingPartitionsDialog, ModuleSetupDialog, ReviewChangesDialog,
+    WelcomeDialog,
+};
+use crate::ui::widgets::{DisksWidget, PendingBar, RebuildLog, RebuildOutcome};
+use anyhow::Context;
+use gettextrs::{gettext, ngettext};
 use gtk4::prelude::*;
 use gtk4::{gio, glib};
 use libadwaita as adw;
 use libadwaita::prelude::*;
-use std::cell::RefCell;
-use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use nix_disk_core::NixDiskError;
+use nix_disk_core::error::read_file;
+use nix_disk_core::models::{Disk, MountChange, NixConfig, Partition, PendingChanges};
+use nix_disk_core::utils::{
+    Backup, ConfigPaths, StagedConfig, check_syntax, content_hash, get_disks, get_nix_disks_config,
+    load_config,
+};
+use std::cell::{Cell, RefCell};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 #[allow(dead_code)]
 pub struct NixDiskManagerWindow {
     window: adw::ApplicationWindow,
     disks: Rc<RefCell<Vec<Disk>>>,
-    hardware_config: Rc<RefCell<String>>,
-    config_file: PathBuf,
-    must_save: Rc<RefCell<bool>>,
-    rebuild_banner: adw::Banner,
-    rebuild_error_banner: adw::Banner,
+    nix_config: Rc<RefCell<NixConfig>>,
+    config_paths: ConfigPaths,
+    pending: Pending,
+    rebuild_status: RebuildStatus,
     disks_widget: DisksWidget,
     toast_overlay: adw::ToastOverlay,
 }
@@ -30,9 +39,8 @@ impl NixDiskManagerWindow {
     pub fn new(
         app: &adw::Application,
         disks: Rc<RefCell<Vec<Disk>>>,
-        hardware_config: Rc<RefCell<String>>,
-        config_file: PathBuf,
-        must_save: Rc<RefCell<bool>>,
+        nix_config: Rc<RefCell<NixConfig>>,
+        config_paths: ConfigPaths,
         skip_welcome: bool,
     ) -> Rc<Self> {
         let window = adw::ApplicationWindow::builder()
@@ -52,6 +60,7 @@ impl NixDiskManagerWindow {
 
         // Create banners
         let rebuild_banner = adw::Banner::new(&gettext("Rebuilding NixOS configuration..."));
+        rebuild_banner.set_button_label(Some(&gettext("Cancel")));
         rebuild_banner.set_revealed(false);
 
         let rebuild_error_banner =
@@ -62,8 +71,49 @@ impl NixDiskManagerWindow {
         main_box.append(&rebuild_banner);
         main_box.append(&rebuild_error_banner);
 
+        // Output of nixos-rebuild, revealed while it runs
+        let rebuild_status = RebuildStatus {
+            banner: rebuild_banner.clone(),
+            error_banner: rebuild_error_banner.clone(),
+            log: RebuildLog::new(),
+            helper: Rc::new(RefCell::new(None)),
+            cancel_requested: Rc::new(Cell::new(false)),
+            previous_config: Rc::new(RefCell::new(None)),
+            check_build: Rc::new(Cell::new(false)),
+        };
+
+        let status_for_cancel = rebuild_status.clone();
+        rebuild_banner.connect_button_clicked(move |_| status_for_cancel.cancel());
+
         // Create toolbar
         let header_bar = adw::HeaderBar::new();
+        let history_button = gtk4::Button::from_icon_name("document-open-recent-symbolic");
+        history_button.set_tooltip_text(Some(&gettext("History")));
+        let menu = gio::Menu::new();
+        menu.append(
+            Some(&gettext("Check the System Builds Before Saving")),
+            Some("win.check-build"),
+        );
+        let menu_button = gtk4::MenuButton::builder()
+            .icon_name("open-menu-symbolic")
+            .menu_model(&menu)
+            .tooltip_text(gettext("Main Menu"))
+            .build();
+        header_bar.pack_end(&menu_button);
+        header_bar.pack_end(&history_button);
+
+        let undo_button = gtk4::Button::builder()
+            .icon_name("edit-undo-symbolic")
+            .tooltip_text(gettext("Undo"))
+            .action_name("win.undo")
+            .build();
+        let redo_button = gtk4::Button::builder()
+            .icon_name("edit-redo-symbolic")
+            .tooltip_text(gettext("Redo"))
+            .action_name("win.redo")
+            .build();
+        header_bar.pack_start(&undo_button);
+        header_bar.pack_start(&redo_button);
         main_box.append(&header_bar);
 
         // Create toast overlay for notifications
@@ -87,39 +137,41 @@ impl NixDiskManagerWindow {
         top_spacer.set_vexpand(true);
         content_box.append(&top_spacer);
 
-        // Create disks widget with hardware config
-        let disks_widget =
-            DisksWidget::new_with_config(disks.clone(), Some(hardware_config.clone()));
-
-        // Set up save callback for the disks widget
-        let disks_for_save = disks.clone();
-        let hardware_config_for_save = hardware_config.clone();
-        let config_file_for_save = config_file.clone();
-        let rebuild_banner_for_save = rebuild_banner.clone();
-        let rebuild_error_banner_for_save = rebuild_error_banner.clone();
-        let must_save_for_save = must_save.clone();
-        let disks_widget_for_refresh = disks_widget.clone();
-
-        disks_widget.set_on_save_callback(move || {
-            eprintln!("🔘 Callback de sauvegarde appelée!");
-
-            // Create refresh callback that will be called after rebuild completes
-            let disks_widget_clone = disks_widget_for_refresh.clone();
-            let refresh_callback = Rc::new(move || {
-                eprintln!("🔔 Callback de rafraîchissement appelée depuis do_save_config!");
-                disks_widget_clone.refresh();
-                eprintln!("✅ Rafraîchissement du widget terminé");
-            });
-
-            Self::do_save_config(
-                &config_file_for_save,
-                &disks_for_save,
-                &hardware_config_for_save,
-                &rebuild_banner_for_save,
-                &rebuild_error_banner_for_save,
-                &must_save_for_save,
-                Some(refresh_callback),
-            );
+        // Create disks widget with the NixOS config
+        let disks_widget = DisksWidget::new_with_config(disks.clone(), Some(nix_config.clone()));
+
+        // Edits are staged until "Apply" writes them all and rebuilds once
+        let undo_action = gio::SimpleAction::new("undo", None);
+        let redo_action = gio::SimpleAction::new("redo", None);
+        undo_action.set_enabled(false);
+        redo_action.set_enabled(false);
+        window.add_action(&undo_action);
+        window.add_action(&redo_action);
+        app.set_accels_for_action("win.undo", &["<Control>z"]);
+        app.set_accels_for_action("win.redo", &["<Control><Shift>z", "<Control>y"]);
+
+        let pending = Pending {
+            changes: Rc::new(RefCell::new(PendingChanges::new(disks.borrow().clone()))),
+            bar: PendingBar::new(),
+            undo_action: undo_action.clone(),
+            redo_action: redo_action.clone(),
+        };
+
+        let pending_for_change = pending.clone();
+        let disks_for_change = disks.clone();
+        let disks_widget_for_change = disks_widget.clone();
+        disks_widget.set_on_change_callback(move |change| {
+            pending_for_change.stage(change, &disks_for_change);
+            disks_widget_for_change.refresh();
+        });
+
+        // A formatted disk was probed again, keep the staged changes on top
+        let pending_for_reload = pending.clone();
+        let disks_for_reload = disks.clone();
+        let disks_widget_for_reload = disks_widget.clone();
+        disks_widget.set_on_reload_callback(move || {
+            pending_for_reload.rebase(&disks_for_reload);
+            disks_widget_for_reload.refresh();
         });
 
         content_box.append(&disks_widget.widget());
@@ -155,21 +207,109 @@ impl NixDiskManagerWindow {
         // Wrap scrolled in toast overlay
         toast_overlay.set_child(Some(&scrolled));
         main_box.append(&toast_overlay);
+        main_box.append(rebuild_status.log.widget());
+        main_box.append(pending.bar.widget());
+
+        // Put back the configuration a failed or cancelled rebuild left behind
+        let status_for_revert = rebuild_status.clone();
+        let disks_for_revert = disks.clone();
+        let nix_config_for_revert = nix_config.clone();
+        let disks_widget_for_revert = disks_widget.clone();
+        let toast_overlay_for_revert = toast_overlay.clone();
+        let pending_for_revert = pending.clone();
+        rebuild_error_banner.connect_button_clicked(move |_| {
+            let disks_widget = disks_widget_for_revert.clone();
+            let toast_overlay = toast_overlay_for_revert.clone();
+            let pending = pending_for_revert.clone();
+            let disks = disks_for_revert.clone();
+            Self::revert_config(
+                &disks_for_revert,
+                &nix_config_for_revert,
+                &status_for_revert,
+                move || {
+                    pending.reset(&disks);
+                    disks_widget.refresh();
+                    toast_overlay.add_toast(adw::Toast::new(&gettext(
+                        "The previous configuration was restored",
+                    )));
+                },
+            );
+        });
+
+        // Dry build before each save, off by default as it takes a while
+        let check_build_action =
+            gio::SimpleAction::new_stateful("check-build", None, &false.to_variant());
+        let check_build = rebuild_status.check_build.clone();
+        check_build_action.connect_activate(move |action, _| {
+            let enabled = !action
+                .state()
+                .and_then(|state| state.get::<bool>())
+                .unwrap_or(false);
+            action.set_state(&enabled.to_variant());
+            check_build.set(enabled);
+        });
+        window.add_action(&check_build_action);
 
         window.set_content(Some(&main_box));
 
         let window_rc = Rc::new(Self {
             window: window.clone(),
             disks: disks.clone(),
-            hardware_config: hardware_config.clone(),
-            config_file,
-            must_save,
-            rebuild_banner,
-            rebuild_error_banner,
+            nix_config: nix_config.clone(),
+            config_paths,
+            pending,
+            rebuild_status,
             disks_widget,
             toast_overlay: toast_overlay.clone(),
         });
 
+        let window_weak = Rc::downgrade(&window_rc);
+        history_button.connect_clicked(move |_| {
+            if let Some(window) = window_weak.upgrade() {
+                window.show_history_dialog();
+            }
+        });
+
+        let window_weak = Rc::downgrade(&window_rc);
+        undo_action.connect_activate(move |_, _| {
+            if let Some(window) = window_weak.upgrade() {
+                window.undo();
+            }
+        });
+        let window_weak = Rc::downgrade(&window_rc);
+        redo_action.connect_activate(move |_, _| {
+            if let Some(window) = window_weak.upgrade() {
+                window.redo();
+            }
+        });
+
+        let window_weak = Rc::downgrade(&window_rc);
+        window_rc.pending.bar.connect_apply(move || {
+            if let Some(window) = window_weak.upgrade() {
+                window.review_pending();
+            }
+        });
+        let window_weak = Rc::downgrade(&window_rc);
+        window_rc.pending.bar.connect_discard(move || {
+            if let Some(window) = window_weak.upgrade() {
+                window.pending.discard(&window.disks);
+                window.disks_widget.refresh();
+            }
+        });
+
+        // Closing would lose the staged changes, ask first
+        let window_weak = Rc::downgrade(&window_rc);
+        window.connect_close_request(move |_| {
+            let Some(this) = window_weak.upgrade() else {
+                return glib::Propagation::Proceed;
+            };
+            if this.pending.changes.borrow().is_empty() {
+                return glib::Propagation::Proceed;
+            }
+            this.confirm_discard_on_close();
+            glib::Propagation::Stop
+        });
+
         // Fix minimization bug with pkexec: force redraw when window is shown
         let content_box_clone = content_box.clone();
         let scrolled_clone = scrolled.clone();
@@ -191,22 +331,220 @@ impl NixDiskManagerWindow {
     }
 
     pub fn show_missing_partitions_dialog(&self, missing: &[Partition]) {
-        let dialog = MissingPartitionsDialog::new(missing, self.disks.clone());
+        // Their entries are deleted with the other pending changes
+        let pending = self.pending.clone();
+        let dialog = MissingPartitionsDialog::new(missing, move |partitions| {
+            pending.forget(partitions);
+        });
         dialog.present(Some(&self.window));
     }
 
+    pub fn show_module_setup_dialog(&self) {
+        let nix_config = self.nix_config.clone();
+        let disks = self.disks.clone();
+        let disks_widget = self.disks_widget.clone();
+        let toast_overlay = self.toast_overlay.clone();
+        let paths = self.config_paths.clone();
+        let pending = self.pending.clone();
+
+        let dialog = ModuleSetupDialog::new(self.config_paths.clone(), move |moved| {
+            // nix-disk.nix is now the managed file, reload everything from it
+            match load_config(&paths) {
+                Ok(config) => {
+                    if let Ok(new_disks) = get_disks(&config.sources()) {
+                        *disks.borrow_mut() = new_disks;
+                    }
+                    *nix_config.borrow_mut() = config;
+                    pending.reset(&disks);
+                    disks_widget.refresh();
+                }
+                Err(e) => eprintln!("❌ Erreur lecture config: {:#}", e),
+            }
+
+            // TRANSLATORS: %d is the number of mount points moved to nix-disk.nix
+            let message = gettext("%d mount point(s) moved to nix-disk.nix")
+                .replace("%d", &moved.len().to_string());
+            toast_overlay.add_toast(adw::Toast::new(&message));
+        });
+        dialog.present(Some(&self.window));
+    }
+
+    fn show_history_dialog(self: &Rc<Self>) {
+        let window_weak = Rc::downgrade(self);
+        let dialog = HistoryDialog::new(move |backup, rebuild| {
+            if let Some(window) = window_weak.upgrade() {
+                window.restore_backup(backup, rebuild);
+            }
+        });
+        dialog.present(Some(&self.window));
+    }
+
+    /// Write a previous version back, then rebuild if asked to
+    fn restore_backup(self: &Rc<Self>, backup: Backup, rebuild: bool) {
+        let window = self.clone();
+        glib::spawn_future_local(async move {
+            // TRANSLATORS: %s is the date and time of a previous version
+            let action = gettext("Restore the version of %s")
+                .replace("%s", &format_timestamp(backup.timestamp));
+            let written = async {
+                let content = backup.content()?;
+                let current = read_file(&backup.path)?;
+                let helper = HelperClient::connect()?;
+                let loaded = content_hash(&current);
+                helper
+                    .write_config(&backup.path, &content, &action, Some(&loaded))
+                    .await?;
+                anyhow::Ok((helper, current, content))
+            }
+            .await;
+            let (helper, current, restored) = match written {
+                Ok(written) => written,
+                Err(e) => {
+                    eprintln!("❌ Erreur lors de la restauration: {:#}", e);
+                    ErrorDialog::new(&e).present(Some(&window.window));
+                    return;
+                }
+            };
+            eprintln!("↩️  {} restauré", backup.path.display());
+            window.reload_config();
+            window.toast_overlay.add_toast(adw::Toast::new(&gettext(
+                "The previous configuration was restored",
+            )));
+
+            if rebuild {
+                let flake_ref = window.config_paths.flake.as_ref().map(|f| f.flake_ref());
+                let previous = PreviousConfig {
+                    path: backup.path.clone(),
+                    content: current,
+                    written_hash: content_hash(&restored),
+                };
+                if Self::rebuild_after_write(&helper, flake_ref, &window.rebuild_status, previous)
+                    .await
+                {
+                    window.reload_config();
+                }
+            }
+        });
+    }
+
+    /// Read the configuration files and the disks again, dropping the
+    /// staged changes
+    fn reload_config(&self) {
+        match load_config(&self.config_paths) {
+            Ok(config) => {
+                if let Ok(new_disks) = get_disks(&config.sources()) {
+                    *self.disks.borrow_mut() = new_disks;
+                }
+                *self.nix_config.borrow_mut() = config;
+                self.pending.reset(&self.disks);
+                self.disks_widget.refresh();
+            }
+            Err(e) => eprintln!("❌ Erreur lecture config: {:#}", e),
+        }
+    }
+
+    fn undo(&self) {
+        if let Some(change) = self.pending.undo(&self.disks) {
+            eprintln!("↩️  Modification annulée: {:?}", change);
+            self.disks_widget.refresh();
+            // TRANSLATORS: %s describes a change (e.g., Mount /dev/sdb1 on /media/data)
+            let text = gettext("Undone: %s").replace("%s", &describe_change(&change));
+            self.toast_overlay.add_toast(adw::Toast::new(&text));
+        }
+    }
+
+    fn redo(&self) {
+        if let Some(change) = self.pending.redo(&self.disks) {
+            eprintln!("↪️  Modification rétablie: {:?}", change);
+            self.disks_widget.refresh();
+            // TRANSLATORS: %s describes a change (e.g., Mount /dev/sdb1 on /media/data)
+            let text = gettext("Redone: %s").replace("%s", &describe_change(&change));
+            self.toast_overlay.add_toast(adw::Toast::new(&text));
+        }
+    }
+
+    /// Show the diff of the staged changes, then apply them
+    fn review_pending(self: &Rc<Self>) {
+        if self.pending.changes.borrow().is_empty() {
+            return;
+        }
+        let changes = self.pending.changes.borrow().changes();
+        let forgotten = self.pending.changes.borrow().forgotten().to_vec();
+        let summary = changes
+            .iter()
+            .map(describe_change)
+            .chain(forgotten.iter().map(|partition| {
+                // TRANSLATORS: %s is a partition that is gone (e.g., /dev/sdb1)
+                gettext("Remove %s from the configuration")
+                    .replace("%s", &partition.path.display().to_string())
+            }))
+            .map(|line| format!("• {}", line))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let window = self.clone();
+        let dialog = ReviewChangesDialog::new(
+            &gettext("Apply Changes"),
+            &summary,
+            &self.nix_config.borrow(),
+            &self.disks.borrow(),
+            &forgotten,
+            move || window.save_config(),
+        );
+        match dialog {
+            Ok(dialog) => dialog.present(Some(&self.window)),
+            Err(e) => {
+                eprintln!("❌ Erreur de génération de la configuration: {:#}", e);
+                ErrorDialog::new(&e).present(Some(&self.window));
+            }
+        }
+    }
+
+    fn confirm_discard_on_close(&self) {
+        let count = self.pending.changes.borrow().len();
+        let dialog = adw::MessageDialog::new(
+            Some(&self.window),
+            Some(&gettext("Discard Pending Changes?")),
+            Some(
+                // TRANSLATORS: %d is the number of changes not applied yet
+                &ngettext(
+                    "%d change was not applied and will be lost.",
+                    "%d changes were not applied and will be lost.",
+                    count as u32,
+                )
+                .replace("%d", &count.to_string()),
+            ),
+        );
+        dialog.add_response("cancel", &gettext("Cancel"));
+        dialog.add_response("discard", &gettext("Discard"));
+        dialog.set_response_appearance("discard", adw::ResponseAppearance::Destructive);
+        dialog.set_default_response(Some("cancel"));
+        dialog.set_close_response("cancel");
+
+        let pending = self.pending.clone();
+        let disks = self.disks.clone();
+        let window = self.window.clone();
+        dialog.connect_response(None, move |_, response| {
+            if response == "discard" {
+                pending.discard(&disks);
+                window.close();
+            }
+        });
+        dialog.present();
+    }
+
     fn do_save_config(
-        config_file: &PathBuf,
         disks: &Rc<RefCell<Vec<Disk>>>,
-        hardware_config: &Rc<RefCell<String>>,
-        rebuild_banner: &adw::Banner,
-        rebuild_error_banner: &adw::Banner,
-        must_save: &Rc<RefCell<bool>>,
+        nix_config: &Rc<RefCell<NixConfig>>,
+        config_paths: &ConfigPaths,
+        status: &RebuildStatus,
+        pending: &Pending,
         on_rebuild_complete: Option<Rc<dyn Fn()>>,
     ) {
         eprintln!("=== Début de la sauvegarde ===");
 
-        let config = hardware_config.borrow().clone();
+        let config = nix_config.borrow().clone();
+        let config_file = config.path.clone();
         let disks_data = disks.borrow().clone();
 
         // Debug: afficher les disques et leurs points de montage
@@ -219,199 +557,290 @@ impl NixDiskManagerWindow {
             }
         }
 
-        match get_nix_disks_config(&config, &disks_data) {
-            Ok(new_config) => {
-                eprintln!("Configuration générée avec succès");
-                eprintln!("Écriture dans: {}", config_file.display());
+        let forgotten = pending.changes.borrow().forgotten().to_vec();
+        let new_config = match get_nix_disks_config(&config.content, &disks_data, &forgotten) {
+            Ok(new_config) => new_config,
+            Err(e) => {
+                eprintln!("Failed to generate config: {}", e);
+                status.show_error(&gettext("Failed to generate the configuration"), false);
+                return;
+            }
+        };
+        eprintln!("Configuration générée avec succès");
+
+        if let Err(e) = check_syntax(&config_file, &new_config) {
+            eprintln!("❌ Configuration générée invalide: {:#}", e);
+            status.show_error(
+                &gettext("The generated configuration is invalid, nothing was saved"),
+                false,
+            );
+            ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+            return;
+        }
 
-                if let Err(e) = fs::write(config_file, &new_config) {
-                    eprintln!("❌ Erreur d'écriture du fichier: {}", e);
-                    rebuild_error_banner.set_revealed(true);
+        let helper = match HelperClient::connect() {
+            Ok(helper) => helper,
+            Err(e) => {
+                eprintln!("❌ {:#}", e);
+                status.show_error(&gettext("Failed to save the configuration"), false);
+                ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+                return;
+            }
+        };
+
+        let status = status.clone();
+        let disks = disks.clone();
+        let nix_config = nix_config.clone();
+        let config_paths = config_paths.clone();
+        let flake_ref = config_paths.flake.as_ref().map(|f| f.flake_ref());
+        let pending = pending.clone();
+
+        glib::spawn_future_local(async move {
+            if status.check_build.get() {
+                status.error_banner.set_revealed(false);
+                status.log.start_check();
+                let result =
+                    Self::dry_build(&config_paths, &config_file, &new_config, &status.log).await;
+                status.log.finish_check(result.is_ok());
+                if let Err(e) = result {
+                    eprintln!("❌ La nouvelle configuration ne se construit pas: {:#}", e);
+                    let title = match NixDiskError::find(&e) {
+                        Some(NixDiskError::CheckFailed { .. }) => {
+                            status.log.failure_summary().unwrap_or_else(|| {
+                                gettext("The new configuration does not build, nothing was saved")
+                            })
+                        }
+                        _ => {
+                            ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+                            gettext("The new configuration does not build, nothing was saved")
+                        }
+                    };
+                    status.show_error(&title, false);
                     return;
                 }
+            }
 
-                eprintln!("✓ Fichier écrit avec succès");
-                eprintln!("Aperçu de la configuration:");
-                eprintln!("{}", &new_config[..new_config.len().min(500)]);
-
-                rebuild_error_banner.set_revealed(false);
-                rebuild_banner.set_revealed(true);
-
-                // Run nixos-rebuild in background
-                let rebuild_banner = rebuild_banner.clone();
-                let rebuild_error_banner = rebuild_error_banner.clone();
-                let _must_save = must_save.clone();
-                let disks_for_reload = disks.clone();
-                let hardware_config_for_reload = hardware_config.clone();
-                let config_file_for_reload = config_file.clone();
-
-                glib::spawn_future_local(async move {
-                    eprintln!("🔄 Lancement de nixos-rebuild switch...");
-                    let result = gio::spawn_blocking(|| {
-                        // Create a temporary wrapper script for rebuild
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs();
-                        let wrapper_path =
-                            format!("/tmp/nix_disk_manager_rebuild_{}.sh", timestamp);
-                        let status_file =
-                            format!("/tmp/nix_disk_manager_rebuild_{}.done", timestamp);
-
-                        let script_content = format!(
-                            r#"#!/usr/bin/env bash
-
-echo "======================================"
-echo "  RECONSTRUCTION DE LA CONFIGURATION"
-echo "======================================"
-echo ""
-
-# Preserve environment for sudo
-sudo -E nixos-rebuild switch
-EXIT_CODE=$?
-
-if [ $EXIT_CODE -eq 0 ]; then
-    echo ""
-    echo "======================================"
-    echo "  ✅ REBUILD TERMINÉ AVEC SUCCÈS"
-    echo "======================================"
-
-    # Signal completion
-    touch {}
-else
-    echo ""
-    echo "======================================"
-    echo "  ❌ ERREUR LORS DU REBUILD"
-    echo "======================================"
-fi
-
-echo ""
-echo "Appuyez sur Entrée ou fermez cette fenêtre..."
-read -t 300 || true
-"#,
-                            status_file
-                        );
-
-                        if let Err(e) = std::fs::write(&wrapper_path, script_content) {
-                            eprintln!("❌ Erreur: impossible d'écrire le script rebuild: {}", e);
-                            return (false, status_file.clone(), wrapper_path.clone());
-                        }
+            eprintln!("Écriture dans: {}", config_file.display());
+            let loaded = content_hash(&config.content);
+            if let Err(e) = helper
+                .write_config(
+                    &config_file,
+                    &new_config,
+                    &gettext("Save mount points"),
+                    Some(&loaded),
+                )
+                .await
+            {
+                eprintln!("❌ Erreur d'écriture du fichier: {:#}", e);
+                status.show_error(&gettext("Failed to save the configuration"), false);
+                ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+                return;
+            }
+            eprintln!("✓ Fichier écrit avec succès");
+            let previous = PreviousConfig {
+                path: config_file,
+                content: config.content,
+                written_hash: content_hash(&new_config),
+            };
+            nix_config.borrow_mut().content = new_config;
+            // The changes are in the file now, whatever the rebuild does
+            pending.reset(&disks);
+
+            if !Self::rebuild_after_write(&helper, flake_ref, &status, previous).await {
+                return;
+            }
 
-                        if let Err(e) = Command::new("chmod").arg("+x").arg(&wrapper_path).status()
-                        {
-                            eprintln!("❌ Erreur chmod: {}", e);
-                            let _ = std::fs::remove_file(&wrapper_path);
-                            return (false, status_file.clone(), wrapper_path.clone());
-                        }
+            // Reload disks from system with the updated config
+            let updated_config = nix_config.borrow().clone();
+            if let Ok(new_disks) = get_disks(&updated_config.sources()) {
+                *disks.borrow_mut() = new_disks;
+                pending.reset(&disks);
+                eprintln!("✅ Liste des disques rechargée après rebuild");
+            }
 
-                        // Try multiple terminals in order of preference
-                        let terminals: Vec<(&str, Vec<&str>)> = vec![
-                            ("kgx", vec!["--", &wrapper_path]), // GNOME Console
-                            ("gnome-terminal", vec!["--", &wrapper_path]),
-                            ("konsole", vec!["-e", &wrapper_path]),
-                            ("xfce4-terminal", vec!["-e", &wrapper_path]),
-                            ("alacritty", vec!["-e", &wrapper_path]),
-                            ("kitty", vec![&wrapper_path]),
-                            ("xterm", vec!["-e", &wrapper_path]),
-                        ];
-
-                        for (term, args) in terminals {
-                            eprintln!("⚠️ Tentative avec {}...", term);
-                            if Command::new(term).args(&args).spawn().is_ok() {
-                                eprintln!("✅ Terminal {} ouvert avec succès", term);
-                                return (true, status_file, wrapper_path);
-                            }
-                        }
+            if let Some(callback) = on_rebuild_complete {
+                eprintln!("🔄 Rafraîchissement de l'interface après rebuild");
+                callback();
+            }
+        });
+    }
 
-                        eprintln!("❌ Aucun terminal trouvé pour exécuter nixos-rebuild");
-                        let _ = std::fs::remove_file(&wrapper_path);
-                        (false, status_file, wrapper_path)
-                    })
-                    .await
-                    .unwrap_or((false, String::new(), String::new()));
-
-                    let (terminal_opened, status_file_path, script_path) = result;
-
-                    if !terminal_opened {
-                        rebuild_banner.set_revealed(false);
-                        rebuild_error_banner.set_revealed(true);
-                    } else {
-                        // Start watching for completion
-                        let rebuild_banner_watch = rebuild_banner.clone();
-                        let _rebuild_error_banner_watch = rebuild_error_banner.clone();
-                        let disks_watch = disks_for_reload.clone();
-                        let hardware_config_watch = hardware_config_for_reload.clone();
-                        let on_rebuild_complete_watch = on_rebuild_complete.clone();
-                        let config_file_watch = config_file_for_reload.clone();
-                        let check_count = Rc::new(RefCell::new(0u32));
-
-                        glib::timeout_add_local(std::time::Duration::from_secs(2), move || {
-                            *check_count.borrow_mut() += 1;
-                            let count = *check_count.borrow();
-
-                            // Check if status file exists
-                            if std::path::Path::new(&status_file_path).exists() {
-                                eprintln!("✅ Rebuild terminé détecté!");
-
-                                // Reload hardware config from disk (it was updated by the rebuild)
-                                eprintln!(
-                                    "📄 Rechargement de la config depuis: {}",
-                                    config_file_watch.display()
-                                );
-                                let updated_config = std::fs::read_to_string(&config_file_watch)
-                                    .unwrap_or_else(|e| {
-                                        eprintln!("❌ Erreur lecture config: {}", e);
-                                        hardware_config_watch.borrow().clone()
-                                    });
-
-                                // Update the config in memory
-                                *hardware_config_watch.borrow_mut() = updated_config.clone();
-                                eprintln!("✅ Config en mémoire mise à jour");
-
-                                // Reload disks from system with the updated config
-                                use crate::utils::get_disks;
-                                if let Ok(new_disks) = get_disks(Some(&updated_config)) {
-                                    *disks_watch.borrow_mut() = new_disks;
-                                    eprintln!("✅ Liste des disques rechargée après rebuild");
-                                }
-
-                                // Call the refresh callback if provided
-                                if let Some(ref callback) = on_rebuild_complete_watch {
-                                    eprintln!("🔄 Rafraîchissement de l'interface après rebuild");
-                                    callback();
-                                }
-
-                                // Hide banner
-                                rebuild_banner_watch.set_revealed(false);
-
-                                // Clean up
-                                let _ = std::fs::remove_file(&status_file_path);
-                                let _ = std::fs::remove_file(&script_path);
-
-                                return glib::ControlFlow::Break;
-                            }
-
-                            // Stop after 10 minutes (300 checks * 2 seconds)
-                            if count > 300 {
-                                eprintln!("⚠️ Timeout du watcher de rebuild");
-                                rebuild_banner_watch.set_revealed(false);
-                                let _ = std::fs::remove_file(&script_path);
-                                return glib::ControlFlow::Break;
-                            }
-
-                            glib::ControlFlow::Continue
-                        });
-                    }
-                });
+    /// Evaluate the system with `content` as `path` before writing it, from a
+    /// copy of the configuration, following `nixos-rebuild dry-build` in the log
+    async fn dry_build(
+        config_paths: &ConfigPaths,
+        path: &Path,
+        content: &str,
+        log: &RebuildLog,
+    ) -> anyhow::Result<()> {
+        let staged = StagedConfig::new(config_paths, path, content)?;
+        let command = staged.dry_build_command();
+        let argv: Vec<&OsStr> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .collect();
+        eprintln!("🔍 Lancement de {:?}", argv);
+        let process = gio::Subprocess::newv(
+            &argv,
+            gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_MERGE,
+        )
+        .context("Cannot run nixos-rebuild dry-build")?;
+
+        if let Some(stdout) = process.stdout_pipe() {
+            let stdout = gio::DataInputStream::new(&stdout);
+            while let Some(line) = stdout
+                .read_line_utf8_future(glib::Priority::DEFAULT)
+                .await?
+            {
+                log.append_output(&line);
             }
-            Err(e) => {
-                eprintln!("Failed to generate config: {}", e);
-                rebuild_error_banner.set_revealed(true);
+        }
+        process.wait_future().await?;
+
+        if !process.is_successful() {
+            return Err(NixDiskError::CheckFailed {
+                path: path.to_path_buf(),
+                reason: format!(
+                    "nixos-rebuild dry-build failed with exit code {}",
+                    process.exit_status()
+                ),
             }
+            .into());
         }
+        Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Rebuild after a configuration file was written, following the output
+    /// in the log and reporting the outcome in the banners
+    ///
+    /// `previous` is the file and its content before the write, offered by
+    /// "Revert" if the rebuild does not succeed. Returns whether it succeeded.
+    async fn rebuild_after_write(
+        helper: &HelperClient,
+        flake_ref: Option<String>,
+        status: &RebuildStatus,
+        previous: PreviousConfig,
+    ) -> bool {
+        *status.previous_config.borrow_mut() = Some(previous);
+
+        status.error_banner.set_revealed(false);
+        status
+            .banner
+            .set_title(&gettext("Rebuilding NixOS configuration..."));
+        status.banner.set_revealed(true);
+        status.log.start();
+        status.cancel_requested.set(false);
+        *status.helper.borrow_mut() = Some(helper.clone());
+
+        eprintln!("🔄 Lancement de nixos-rebuild...");
+        let banner = status.banner.clone();
+        let log = status.log.clone();
+        let result = helper
+            .rebuild(flake_ref.as_deref(), move |line| {
+                if let Some(phase) = log.append_line(line) {
+                    banner.set_title(&phase);
+                }
+            })
+            .await;
+        *status.helper.borrow_mut() = None;
+        status.banner.set_revealed(false);
+
+        let outcome = match result {
+            Ok(()) => RebuildOutcome::Success,
+            Err(e)
+                if status.cancel_requested.get()
+                    || matches!(
+                        NixDiskError::find(&e),
+                        Some(NixDiskError::NotAuthorized { .. })
+                    ) =>
+            {
+                RebuildOutcome::Cancelled
+            }
+            Err(e) => RebuildOutcome::Failed(e),
+        };
+        status.log.finish(&outcome);
+
+        match outcome {
+            RebuildOutcome::Success => {
+                eprintln!("✅ Rebuild terminé");
+                *status.previous_config.borrow_mut() = None;
+                true
+            }
+            RebuildOutcome::Cancelled => {
+                eprintln!("🛑 Rebuild annulé");
+                status.show_error(
+                    &gettext("Rebuild cancelled: the configuration was saved but not applied"),
+                    true,
+                );
+                false
+            }
+            RebuildOutcome::Failed(e) => {
+                eprintln!("❌ Rebuild échoué: {:#}", e);
+                let title = match NixDiskError::find(&e) {
+                    Some(NixDiskError::RebuildFailed { .. }) => status
+                        .log
+                        .failure_summary()
+                        .unwrap_or_else(|| gettext("Failed to rebuild NixOS configuration")),
+                    // The helper itself failed, there is no output to explain it
+                    _ => {
+                        ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+                        gettext("Failed to rebuild NixOS configuration")
+                    }
+                };
+                status.show_error(&title, true);
+                false
+            }
+        }
+    }
+
+    /// Write back the configuration saved before the last rebuild
+    fn revert_config(
+        disks: &Rc<RefCell<Vec<Disk>>>,
+        nix_config: &Rc<RefCell<NixConfig>>,
+        status: &RebuildStatus,
+        on_reverted: impl Fn() + 'static,
+    ) {
+        let Some(previous) = status.previous_config.borrow_mut().take() else {
+            return;
+        };
+
+        let disks = disks.clone();
+        let nix_config = nix_config.clone();
+        let status = status.clone();
+        glib::spawn_future_local(async move {
+            eprintln!("↩️  Restauration de {}", previous.path.display());
+            let result = match HelperClient::connect() {
+                Ok(helper) => {
+                    helper
+                        .write_config(
+                            &previous.path,
+                            &previous.content,
+                            &gettext("Revert after a failed rebuild"),
+                            Some(&previous.written_hash),
+                        )
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("❌ Erreur lors de la restauration: {:#}", e);
+                ErrorDialog::new(&e).present(status.error_banner.root().as_ref());
+                *status.previous_config.borrow_mut() = Some(previous);
+                return;
+            }
+
+            status.error_banner.set_revealed(false);
+            if nix_config.borrow().path == previous.path {
+                nix_config.borrow_mut().content = previous.content;
+            }
+            let config = nix_config.borrow().clone();
+            if let Ok(new_disks) = get_disks(&config.sources()) {
+                *disks.borrow_mut() = new_disks;
+            }
+            on_reverted();
+        });
+    }
+
+    /// Write the staged changes and rebuild
     pub fn save_config(&self) {
         let disks_widget_clone = self.disks_widget.clone();
         let refresh_callback = Rc::new(move || {
@@ -419,12 +848,11 @@ read -t 300 || true
         });
 
         Self::do_save_config(
-            &self.config_file,
             &self.disks,
-            &self.hardware_config,
-            &self.rebuild_banner,
-            &self.rebuild_error_banner,
-            &self.must_save,
+            &self.nix_config,
+            &self.config_paths,
+            &self.rebuild_status,
+            &self.pending,
             Some(refresh_callback),
         );
     }
@@ -442,3 +870,180 @@ read -t 300 || true
         self.disks_widget.refresh();
     }
 }
+
+/// Banners and log following a rebuild, shared by every save
+#[derive(Clone)]
+struct RebuildStatus {
+    banner: adw::Banner,
+    error_banner: adw::Banner,
+    log: RebuildLog,
+    /// Set while a rebuild runs, so that the banner can cancel it
+    helper: Rc<RefCell<Option<HelperClient>>>,
+    cancel_requested: Rc<Cell<bool>>,
+    /// Configuration file before the last save, for "Revert"
+    previous_config: Rc<RefCell<Option<PreviousConfig>>>,
+    /// Dry build before writing, toggled from the main menu
+    check_build: Rc<Cell<bool>>,
+}
+
+/// Changes staged in the window and the bar offering to apply them
+#[derive(Clone)]
+struct Pending {
+    changes: Rc<RefCell<PendingChanges>>,
+    bar: PendingBar,
+    undo_action: gio::SimpleAction,
+    redo_action: gio::SimpleAction,
+}
+
+impl Pending {
+    /// Stage a change and show it on `disks`
+    fn stage(&self, change: MountChange, disks: &Rc<RefCell<Vec<Disk>>>) {
+        self.changes.borrow_mut().push(change);
+        *disks.borrow_mut() = self.changes.borrow().disks();
+        self.update();
+    }
+
+    /// Stage the deletion of what the configuration declares for `partitions`
+    fn forget(&self, partitions: Vec<Partition>) {
+        self.changes.borrow_mut().forget(partitions);
+        self.update();
+    }
+
+    /// Start over from `disks`, which the configuration now declares
+    fn reset(&self, disks: &Rc<RefCell<Vec<Disk>>>) {
+        *self.changes.borrow_mut() = PendingChanges::new(disks.borrow().clone());
+        self.update();
+    }
+
+    /// Keep the staged changes on top of `disks`, probed again
+    fn rebase(&self, disks: &Rc<RefCell<Vec<Disk>>>) {
+        let probed = disks.borrow().clone();
+        self.changes.borrow_mut().rebase(probed);
+        *disks.borrow_mut() = self.changes.borrow().disks();
+        self.update();
+    }
+
+    /// Drop the staged changes, putting `disks` back as configured
+    fn discard(&self, disks: &Rc<RefCell<Vec<Disk>>>) {
+        let saved = self.changes.borrow().saved().to_vec();
+        *disks.borrow_mut() = saved;
+        self.reset(disks);
+    }
+
+    /// Take back the last staged change, returns it
+    fn undo(&self, disks: &Rc<RefCell<Vec<Disk>>>) -> Option<MountChange> {
+        let change = self.changes.borrow_mut().undo()?;
+        *disks.borrow_mut() = self.changes.borrow().disks();
+        self.update();
+        Some(change)
+    }
+
+    /// Stage the last undone change again, returns it
+    fn redo(&self, disks: &Rc<RefCell<Vec<Disk>>>) -> Option<MountChange> {
+        let change = self.changes.borrow_mut().redo()?;
+        *disks.borrow_mut() = self.changes.borrow().disks();
+        self.update();
+        Some(change)
+    }
+
+    fn update(&self) {
+        let changes = self.changes.borrow();
+        self.bar.set_count(changes.len());
+        self.undo_action.set_enabled(changes.can_undo());
+        self.redo_action.set_enabled(changes.can_redo());
+    }
+}
+
+/// One line of the list of staged changes
+fn describe_change(change: &MountChange) -> String {
+    match change {
+        MountChange::Add {
+            partition,
+            mount_point,
+        } => {
+            // TRANSLATORS: first %s is a partition (e.g., /dev/sdb1), second %s a mount point
+            gettext("Mount %s on %s")
+                .replacen("%s", &partition.display().to_string(), 1)
+                .replacen("%s", mount_point, 1)
+        }
+        MountChange::Remove { mount_point, .. } => {
+            // TRANSLATORS: %s is a mount point (e.g., /media/data)
+            gettext("Stop mounting %s").replace("%s", mount_point)
+        }
+        MountChange::SetFsType {
+            mount_point,
+            fs_type: Some(fs_type),
+            ..
+        } => {
+            // TRANSLATORS: first %s is a mount point, second %s a filesystem type (e.g., ntfs3)
+            gettext("Mount %s as %s")
+                .replacen("%s", mount_point, 1)
+                .replacen("%s", fs_type, 1)
+        }
+        MountChange::SetFsType { mount_point, .. } => {
+            // TRANSLATORS: %s is a mount point (e.g., /media/data)
+            gettext("Mount %s with the detected filesystem type").replace("%s", mount_point)
+        }
+        MountChange::SetOptions {
+            mount_point,
+            options: Some(options),
+            ..
+        } => {
+            // TRANSLATORS: first %s is a mount point, second %s a list of mount options
+            gettext("Mount %s with the options %s")
+                .replacen("%s", mount_point, 1)
+                .replacen("%s", &options.join(", "), 1)
+        }
+        MountChange::SetOptions { mount_point, .. } => {
+            // TRANSLATORS: %s is a mount point (e.g., /media/data)
+            gettext("Mount %s with the default options").replace("%s", mount_point)
+        }
+        MountChange::SetEncryption {
+            partition,
+            encryption: Some(encryption),
+            ..
+        } => {
+            // TRANSLATORS: first %s is a partition (e.g., /dev/sdb1), second %s a device (e.g., /dev/mapper/crypt-data)
+            gettext("Open the encrypted volume %s as %s at boot")
+                .replacen("%s", &partition.display().to_string(), 1)
+                .replacen("%s", &encryption.mapper_path().display().to_string(), 1)
+        }
+        MountChange::SetEncryption { partition, .. } => {
+            // TRANSLATORS: %s is a partition (e.g., /dev/sdb1)
+            gettext("Mount %s without opening its encrypted volume")
+                .replace("%s", &partition.display().to_string())
+        }
+    }
+}
+
+/// A configuration file as it was before nix-disk wrote it
+struct PreviousConfig {
+    path: PathBuf,
+    content: String,
+    /// Hash of what nix-disk wrote, so that reverting does not overwrite
+    /// changes made since by another program
+    written_hash: String,
+}
+
+impl RebuildStatus {
+    fn show_error(&self, title: &str, can_revert: bool) {
+        self.error_banner.set_title(title);
+        let revert_label = gettext("Revert");
+        self.error_banner
+            .set_button_label(can_revert.then_some(revert_label.as_str()));
+        self.error_banner.set_revealed(true);
+    }
+
+    fn cancel(&self) {
+        let Some(helper) = self.helper.borrow().clone() else {
+            return;
+        };
+        self.cancel_requested.set(true);
+        self.banner.set_title(&gettext("Cancelling the rebuild..."));
+        glib::spawn_future_local(async move {
+            if let Err(e) = helper.cancel_rebuild().await {
+                eprintln!("❌ Impossible d'annuler le rebuild: {:#}", e);
+            }
+        });
+    }
+}
@@ -1,5 +0,0 @@
-pub mod disk_parser;
-pub mod disk_writer;
-
-pub use disk_parser::{find_missing_partitions, get_disks, parse_nix_filesystems};
-pub use disk_writer::get_nix_disks_config;